@@ -0,0 +1,34 @@
+//! Runs arbitrary bytes as a program, every instruction must either execute or return a
+//! Chip8Error, never panic.
+//!
+//! cargo +nightly fuzz run fuzz_decode -s address
+//! cargo +nightly fuzz coverage fuzz_decode
+
+#![no_main]
+
+use chip_8::VM;
+use libfuzzer_sys::fuzz_target;
+
+// instructions executed per input
+const MAX_CYCLES: u32 = 1000;
+
+fuzz_target!(|data: &[u8]| {
+    // ROMs shorter than one instruction or past the end of RAM are rejected by load_rom,
+    // which is not what's being tested
+    if data.len() < 2 {
+        return;
+    }
+    let mut vm = VM::new_headless();
+    let rom = &data[..data.len().min(4096 - 0x200)];
+    vm.load_rom(rom).unwrap();
+
+    for cycle in 0..MAX_CYCLES {
+        if vm.step().is_err() {
+            break;
+        }
+        // let DXYN's display wait and the timers move along
+        if cycle % 10 == 9 {
+            vm.tick_timers();
+        }
+    }
+});
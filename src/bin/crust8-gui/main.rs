@@ -0,0 +1,368 @@
+//! Debugger window: the screen next to register, stack, breakpoint, disassembly and memory
+//! panels. The VM runs on its own thread, see session.
+//!
+//! F5 runs or pauses, F6 steps while paused and F7 resets. Clicking a disassembly line or a
+//! listed breakpoint toggles it, clicking a register or a memory byte edits it: type hex digits,
+//! Enter stores them and Escape cancels. Page Up and Page Down (or the mouse wheel) scroll the
+//! memory panel, Home moves it to I. The keypad is read as in the main emulator.
+mod panels;
+mod session;
+
+use std::{fs, path::PathBuf, thread, time::Duration};
+
+use chip_8::{
+    config::Config,
+    frame_timer::FrameTimer,
+    input::{InputSource, KEY_COUNT},
+    overlay::{cell_at, render_text},
+    sdl::SdlInput,
+    vm::{HIRES_SCREEN_HEIGHT, HIRES_SCREEN_WIDTH},
+};
+use sdl2::{
+    event::Event,
+    keyboard::Keycode,
+    mouse::MouseButton,
+    pixels::{Color, PixelFormatEnum},
+    rect::Rect,
+    render::{BlendMode, Canvas, TextureCreator},
+    video::{Window, WindowContext},
+};
+
+use panels::MEMORY_ROW;
+use session::{Command, Register, Session, Snapshot};
+
+const USAGE: &str = "usage: crust8-gui <rom>
+
+Settings, per-ROM sections included, are read from the same config file as chip-8.";
+
+// window pixels per text pixel
+const TEXT_SCALE: i32 = 2;
+// window pixels per hi-res pixel, low-res pixels are twice the size
+const DISPLAY_SCALE: u32 = 5;
+const DISPLAY_WIDTH: u32 = HIRES_SCREEN_WIDTH as u32 * DISPLAY_SCALE;
+const DISPLAY_HEIGHT: u32 = HIRES_SCREEN_HEIGHT as u32 * DISPLAY_SCALE;
+
+// top-left corner of each panel
+const REGISTERS: (i32, i32) = (656, 0);
+const STACK: (i32, i32) = (656, 150);
+const BREAKPOINTS: (i32, i32) = (816, 0);
+const DISASSEMBLY: (i32, i32) = (0, 336);
+const MEMORY: (i32, i32) = (336, 336);
+const STATUS: (i32, i32) = (0, 600);
+const DISASSEMBLY_ROWS: usize = 20;
+const MEMORY_ROWS: usize = 20;
+
+const WINDOW_WIDTH: u32 = 1000;
+const WINDOW_HEIGHT: u32 = 620;
+
+/// What a click started editing, the value is typed as hex digits.
+struct Edit {
+    target: Target,
+    digits: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Register(Register),
+    Ram(u16),
+}
+
+impl Target {
+    // hex digits the value has
+    fn width(self) -> usize {
+        match self {
+            Self::Register(Register::V(_)) | Self::Ram(_) => 2,
+            Self::Register(Register::I | Register::Pc) => 4,
+        }
+    }
+
+    fn command(self, value: u16) -> Command {
+        match self {
+            Self::Register(register) => Command::SetRegister(register, value),
+            Self::Ram(addr) => Command::WriteRam(addr, value as u8),
+        }
+    }
+
+    fn name(self) -> String {
+        match self {
+            Self::Register(Register::V(x)) => format!("V{x:X}"),
+            Self::Register(Register::I) => "I".to_string(),
+            Self::Register(Register::Pc) => "PC".to_string(),
+            Self::Ram(addr) => format!("{addr:04X}"),
+        }
+    }
+}
+
+impl Edit {
+    // stores the value once every digit is typed
+    fn type_digit(&mut self, digit: char, session: &Session) -> bool {
+        self.digits.push(digit);
+        if self.digits.len() < self.target.width() {
+            return false;
+        }
+        self.store(session);
+        true
+    }
+
+    fn store(&self, session: &Session) {
+        if let Ok(value) = u16::from_str_radix(&self.digits, 16) {
+            session.send(self.target.command(value));
+        }
+    }
+}
+
+// line and column of the text cell at window position x, y of a panel drawn at origin, None
+// outside its text
+fn panel_cell(origin: (i32, i32), lines: &[String], x: i32, y: i32) -> Option<(usize, usize)> {
+    if x < origin.0 || y < origin.1 {
+        return None;
+    }
+    let (line, column) = cell_at(
+        ((x - origin.0) / TEXT_SCALE) as usize,
+        ((y - origin.1) / TEXT_SCALE) as usize,
+    )?;
+    (column < lines.get(line)?.len()).then_some((line, column))
+}
+
+fn draw_text(
+    canvas: &mut Canvas<Window>,
+    texture_creator: &TextureCreator<WindowContext>,
+    lines: &[String],
+    origin: (i32, i32),
+) -> Result<(), String> {
+    let image = render_text(lines);
+    let (width, height) = (image.width as u32, image.height as u32);
+    let mut texture = texture_creator
+        .create_texture_static(PixelFormatEnum::RGBA32, width, height)
+        .map_err(|e| e.to_string())?;
+    texture
+        .update(None, &image.pixels, image.width * 4)
+        .map_err(|e| e.to_string())?;
+    texture.set_blend_mode(BlendMode::Blend);
+    let scale = TEXT_SCALE as u32;
+    let dst = Rect::new(origin.0, origin.1, width * scale, height * scale);
+    canvas.copy(&texture, None, dst)
+}
+
+fn status_line(snapshot: &Snapshot, edit: Option<&Edit>) -> String {
+    if let Some(edit) = edit {
+        return format!("EDIT {} = {}_", edit.target.name(), edit.digits);
+    }
+    let state = match (&snapshot.status, snapshot.running) {
+        (_, true) => "RUNNING".to_string(),
+        (Some(status), false) => format!("PAUSED: {status}"),
+        (None, false) => "PAUSED".to_string(),
+    };
+    format!(
+        "{state} - {} CYCLES - F5 RUN/PAUSE  F6 STEP  F7 RESET",
+        snapshot.cycle_count
+    )
+}
+
+fn main() -> Result<(), String> {
+    let rom_path = match std::env::args().nth(1) {
+        Some(arg) if arg.starts_with('-') => return Err(USAGE.to_string()),
+        Some(arg) => PathBuf::from(arg),
+        None => return Err(USAGE.to_string()),
+    };
+    let rom =
+        fs::read(&rom_path).map_err(|e| format!("unable to read {}: {e}", rom_path.display()))?;
+    let config = Config::load().map_err(|e| e.to_string())?.for_rom(&rom);
+    let palette = [
+        config.bg_color,
+        config.fg_color,
+        config.plane2_color,
+        config.both_color,
+    ];
+
+    let sdl_context = sdl2::init()?;
+    let window = sdl_context
+        .video()?
+        .window(
+            &format!("Crust-8 debugger - {}", rom_path.display()),
+            WINDOW_WIDTH,
+            WINDOW_HEIGHT,
+        )
+        .position_centered()
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    let texture_creator = canvas.texture_creator();
+    let mut screen = texture_creator
+        .create_texture_streaming(
+            PixelFormatEnum::RGB24,
+            HIRES_SCREEN_WIDTH as u32,
+            HIRES_SCREEN_HEIGHT as u32,
+        )
+        .map_err(|e| e.to_string())?;
+    let mut input = SdlInput::with_key_map(sdl_context.event_pump()?, &config.key_map)?;
+
+    let session = Session::spawn(rom, config)?;
+    // the VM thread sends one after its first frame
+    let mut snapshot = loop {
+        match session.latest() {
+            Some(snapshot) => break snapshot,
+            None => thread::sleep(Duration::from_millis(1)),
+        }
+    };
+    let mut edit: Option<Edit> = None;
+    let mut memory_start: usize = 0x200;
+    let mut keys = [false; KEY_COUNT];
+    let mut frame_timer = FrameTimer::new(60.0);
+
+    'running: loop {
+        for event in input.poll_events() {
+            match event {
+                Event::Quit { .. } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } if edit.is_some() => {
+                    let done = match keycode {
+                        Keycode::Escape => true,
+                        Keycode::Return | Keycode::KpEnter => {
+                            if let Some(edit) = &edit {
+                                edit.store(&session);
+                            }
+                            true
+                        }
+                        Keycode::Backspace => {
+                            if let Some(edit) = &mut edit {
+                                edit.digits.pop();
+                            }
+                            false
+                        }
+                        _ => {
+                            let name = keycode.name();
+                            let digit = name.chars().next().filter(|c| c.is_ascii_hexdigit());
+                            match (digit, &mut edit) {
+                                (Some(digit), Some(edit)) if name.len() == 1 => {
+                                    edit.type_digit(digit.to_ascii_uppercase(), &session)
+                                }
+                                _ => false,
+                            }
+                        }
+                    };
+                    if done {
+                        edit = None;
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => match keycode {
+                    Keycode::F5 => session.send(Command::SetRunning(!snapshot.running)),
+                    Keycode::F6 => session.send(Command::Step),
+                    Keycode::F7 => session.send(Command::Reset),
+                    Keycode::PageUp => {
+                        memory_start = memory_start.saturating_sub(MEMORY_ROW * MEMORY_ROWS)
+                    }
+                    Keycode::PageDown => memory_start += MEMORY_ROW * MEMORY_ROWS,
+                    Keycode::Home => memory_start = snapshot.i as usize,
+                    _ => {}
+                },
+                Event::MouseWheel { y, .. } => {
+                    let rows = (y.unsigned_abs() as usize) * MEMORY_ROW;
+                    memory_start = if y > 0 {
+                        memory_start.saturating_sub(rows)
+                    } else {
+                        memory_start + rows
+                    };
+                }
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } => {
+                    let disassembly = panels::disassembly(&snapshot, DISASSEMBLY_ROWS);
+                    let memory = panels::memory(&snapshot, memory_start, MEMORY_ROWS);
+                    let text = |rows: &[(u16, String)]| -> Vec<String> {
+                        rows.iter().map(|(_, line)| line.clone()).collect()
+                    };
+                    let registers = panels::registers(&snapshot);
+                    let breakpoints = panels::breakpoints(&snapshot);
+                    let mut target = None;
+                    if let Some((line, _)) = panel_cell(DISASSEMBLY, &text(&disassembly), x, y) {
+                        session.send(Command::ToggleBreakpoint(disassembly[line].0));
+                    } else if let Some((line, _)) = panel_cell(BREAKPOINTS, &breakpoints, x, y) {
+                        // the first line is the heading
+                        if let Some(&addr) = line
+                            .checked_sub(1)
+                            .and_then(|idx| snapshot.breakpoints.get(idx))
+                        {
+                            session.send(Command::ToggleBreakpoint(addr));
+                        }
+                    } else if let Some((line, column)) = panel_cell(REGISTERS, &registers, x, y) {
+                        target = panels::register_at(line, column).map(Target::Register);
+                    } else if let Some((line, column)) = panel_cell(MEMORY, &text(&memory), x, y) {
+                        target = panels::memory_byte_at(column)
+                            .map(|offset| Target::Ram(memory[line].0 + offset as u16));
+                    }
+                    edit = target.map(|target| Edit {
+                        target,
+                        digits: String::new(),
+                    });
+                    if edit.is_some() {
+                        // typing digits shouldn't also press keypad keys
+                        keys = [false; KEY_COUNT];
+                        session.send(Command::Keys(keys));
+                    }
+                }
+                _ => {}
+            }
+        }
+        // keep at least one row of RAM in view
+        memory_start = memory_start.min(snapshot.ram.len() - MEMORY_ROW) / MEMORY_ROW * MEMORY_ROW;
+
+        if edit.is_none() {
+            let mut pressed = [false; KEY_COUNT];
+            input.poll(&mut pressed);
+            if pressed != keys {
+                keys = pressed;
+                session.send(Command::Keys(keys));
+            }
+        }
+        if let Some(latest) = session.latest() {
+            snapshot = latest;
+        }
+
+        screen.with_lock(None, |pixels, pitch| {
+            for (y, row) in snapshot.pixels.chunks(HIRES_SCREEN_WIDTH).enumerate() {
+                let line = &mut pixels[y * pitch..y * pitch + HIRES_SCREEN_WIDTH * 3];
+                for (rgb, &color) in line.chunks_exact_mut(3).zip(row) {
+                    rgb.copy_from_slice(&palette[color as usize]);
+                }
+            }
+        })?;
+        canvas.set_draw_color(Color::RGB(24, 24, 24));
+        canvas.clear();
+        canvas.copy(
+            &screen,
+            None,
+            Rect::new(0, 0, DISPLAY_WIDTH, DISPLAY_HEIGHT),
+        )?;
+        let disassembly: Vec<String> = panels::disassembly(&snapshot, DISASSEMBLY_ROWS)
+            .into_iter()
+            .map(|(_, line)| line)
+            .collect();
+        let memory: Vec<String> = panels::memory(&snapshot, memory_start, MEMORY_ROWS)
+            .into_iter()
+            .map(|(_, line)| line)
+            .collect();
+        let panels = [
+            (panels::registers(&snapshot), REGISTERS),
+            (panels::stack(&snapshot), STACK),
+            (panels::breakpoints(&snapshot), BREAKPOINTS),
+            (disassembly, DISASSEMBLY),
+            (memory, MEMORY),
+            (vec![status_line(&snapshot, edit.as_ref())], STATUS),
+        ];
+        for (lines, origin) in &panels {
+            draw_text(&mut canvas, &texture_creator, lines, *origin)?;
+        }
+        canvas.present();
+        frame_timer.wait();
+    }
+    Ok(())
+}
@@ -0,0 +1,197 @@
+//! Text of the debugger panels, and which field of a panel a character cell belongs to so
+//! clicks can be mapped back to registers, addresses and breakpoints.
+use chip_8::disasm::disassemble;
+
+use crate::session::{Register, Snapshot};
+
+// bytes per memory panel row
+pub const MEMORY_ROW: usize = 8;
+// instructions listed before PC in the disassembly panel
+const DISASSEMBLY_BEFORE: usize = 6;
+
+/// V0-V7 and V8-VF side by side, then I, PC and the timers.
+pub fn registers(snapshot: &Snapshot) -> Vec<String> {
+    let v = &snapshot.registers;
+    let mut lines: Vec<String> = (0..8)
+        .map(|x| format!("V{x:X} {:02X}   V{:X} {:02X}", v[x], x + 8, v[x + 8]))
+        .collect();
+    lines.push(format!("I  {:04X}", snapshot.i));
+    lines.push(format!("PC {:04X}", snapshot.pc));
+    lines.push(format!("DT {:02X}   ST {:02X}", snapshot.dt, snapshot.st));
+    lines
+}
+
+/// Register shown at line and column of the registers panel.
+pub fn register_at(line: usize, column: usize) -> Option<Register> {
+    match (line, column) {
+        (0..8, 0..5) => Some(Register::V(line)),
+        (0..8, 8..13) => Some(Register::V(line + 8)),
+        (8, 0..7) => Some(Register::I),
+        (9, 0..7) => Some(Register::Pc),
+        _ => None,
+    }
+}
+
+/// Return addresses, innermost call first.
+pub fn stack(snapshot: &Snapshot) -> Vec<String> {
+    let mut lines = vec!["STACK".to_string()];
+    lines.extend(
+        snapshot
+            .stack
+            .iter()
+            .rev()
+            .map(|addr| format!("{addr:04X}")),
+    );
+    lines
+}
+
+/// Instructions around PC as (address, line), PC marked with > and breakpoints with *.
+/// Listed a word at a time from an even address, like the VM fetches them.
+pub fn disassembly(snapshot: &Snapshot, rows: usize) -> Vec<(u16, String)> {
+    let last_word = snapshot.ram.len().saturating_sub(2);
+    // short panels still show PC
+    let before = DISASSEMBLY_BEFORE.min(rows.saturating_sub(1));
+    let first = (snapshot.pc as usize & !1)
+        .saturating_sub(before * 2)
+        .min(last_word.saturating_sub(rows.saturating_sub(1) * 2));
+    (first..=last_word)
+        .step_by(2)
+        .take(rows)
+        .map(|addr| {
+            let word = u16::from_be_bytes([snapshot.ram[addr], snapshot.ram[addr + 1]]);
+            let addr = addr as u16;
+            let pc = if addr == snapshot.pc { '>' } else { ' ' };
+            let breakpoint = if snapshot.breakpoints.contains(&addr) {
+                '*'
+            } else {
+                ' '
+            };
+            let line = format!(
+                "{pc}{breakpoint}{addr:04X} {word:04X} {}",
+                disassemble(word)
+            );
+            (addr, line)
+        })
+        .collect()
+}
+
+/// Rows of MEMORY_ROW bytes starting at start, as (address, line).
+pub fn memory(snapshot: &Snapshot, start: usize, rows: usize) -> Vec<(u16, String)> {
+    (start..snapshot.ram.len())
+        .step_by(MEMORY_ROW)
+        .take(rows)
+        .map(|addr| {
+            let end = (addr + MEMORY_ROW).min(snapshot.ram.len());
+            let bytes: Vec<String> = snapshot.ram[addr..end]
+                .iter()
+                .map(|byte| format!("{byte:02X}"))
+                .collect();
+            (addr as u16, format!("{addr:04X} {}", bytes.join(" ")))
+        })
+        .collect()
+}
+
+/// Offset into its row of the byte shown at column of a memory panel line.
+pub fn memory_byte_at(column: usize) -> Option<usize> {
+    // 4 address digits and a space, then 2 digits and a space per byte
+    let column = column.checked_sub(5)?;
+    (column % 3 < 2 && column / 3 < MEMORY_ROW).then_some(column / 3)
+}
+
+/// Breakpoint addresses, one per line after the heading.
+pub fn breakpoints(snapshot: &Snapshot) -> Vec<String> {
+    let mut lines = vec!["BREAKPOINTS".to_string()];
+    lines.extend(
+        snapshot
+            .breakpoints
+            .iter()
+            .map(|addr| format!("{addr:04X}")),
+    );
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        let mut ram = vec![0; 0x1000];
+        // LD V0, 0x2A; JP 0x200
+        ram[0x200..0x204].copy_from_slice(&[0x60, 0x2A, 0x12, 0x00]);
+        let mut registers = [0; 16];
+        registers[0x9] = 0x5C;
+        Snapshot {
+            pixels: Vec::new(),
+            registers,
+            i: 0x123,
+            pc: 0x202,
+            stack: vec![0x204, 0x310],
+            dt: 3,
+            st: 0,
+            ram,
+            breakpoints: vec![0x200],
+            running: false,
+            cycle_count: 0,
+            status: None,
+        }
+    }
+
+    #[test]
+    fn registers_map_back_to_their_cells() {
+        let lines = registers(&snapshot());
+        assert_eq!(lines[1], "V1 00   V9 5C");
+        assert_eq!(lines[8], "I  0123");
+        assert_eq!(lines[10], "DT 03   ST 00");
+
+        assert_eq!(register_at(1, 0), Some(Register::V(1)));
+        assert_eq!(register_at(1, 11), Some(Register::V(9)));
+        assert_eq!(register_at(1, 6), None);
+        assert_eq!(register_at(8, 3), Some(Register::I));
+        assert_eq!(register_at(9, 6), Some(Register::Pc));
+        // timers are read only
+        assert_eq!(register_at(10, 0), None);
+    }
+
+    #[test]
+    fn disassembly_marks_pc_and_breakpoints() {
+        let lines = disassembly(&snapshot(), 4);
+        assert_eq!(lines[0], (0x1FC, "  01FC 0000 SYS 0x000".to_string()));
+        assert_eq!(lines[3].0, 0x202);
+
+        let lines = disassembly(&snapshot(), 8);
+        assert_eq!(lines[5], (0x200, " *0200 602A LD V0, 0x2A".to_string()));
+        assert_eq!(lines[6], (0x202, "> 0202 1200 JP 0x200".to_string()));
+
+        // the window stays inside RAM
+        let mut at_end = snapshot();
+        at_end.pc = 0xFFE;
+        let lines = disassembly(&at_end, 4);
+        assert_eq!(lines.last().unwrap().0, 0xFFE);
+        assert_eq!(lines.len(), 4);
+    }
+
+    #[test]
+    fn memory_rows_and_byte_columns() {
+        let lines = memory(&snapshot(), 0x200, 2);
+        assert_eq!(
+            lines[0],
+            (0x200, "0200 60 2A 12 00 00 00 00 00".to_string())
+        );
+        assert_eq!(lines[1].0, 0x208);
+        assert_eq!(memory(&snapshot(), 0xFF8, 4).len(), 1);
+
+        assert_eq!(memory_byte_at(4), None);
+        assert_eq!(memory_byte_at(5), Some(0));
+        assert_eq!(memory_byte_at(6), Some(0));
+        assert_eq!(memory_byte_at(7), None);
+        assert_eq!(memory_byte_at(8), Some(1));
+        assert_eq!(memory_byte_at(26), Some(7));
+        assert_eq!(memory_byte_at(29), None);
+    }
+
+    #[test]
+    fn stack_lists_the_innermost_call_first() {
+        assert_eq!(stack(&snapshot()), ["STACK", "0310", "0204"]);
+        assert_eq!(breakpoints(&snapshot()), ["BREAKPOINTS", "0200"]);
+    }
+}
@@ -0,0 +1,339 @@
+//! The VM thread. It runs frames at the timer rate, takes commands from the GUI over one channel
+//! and sends a Snapshot back over another after every frame.
+use std::{
+    sync::mpsc::{self, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+use chip_8::{
+    config::Config,
+    frame_timer::FrameTimer,
+    input::KEY_COUNT,
+    vm::{FrameEnd, HIRES_SCREEN_HEIGHT, HIRES_SCREEN_WIDTH},
+    NullRenderer, VM,
+};
+
+/// Requests from the GUI to the VM thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    // start running frames, or stop before the next one
+    SetRunning(bool),
+    // execute a single instruction while paused
+    Step,
+    // restart the loaded program
+    Reset,
+    Keys([bool; KEY_COUNT]),
+    ToggleBreakpoint(u16),
+    SetRegister(Register, u16),
+    WriteRam(u16, u8),
+    Quit,
+}
+
+/// Registers the GUI can edit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register {
+    V(usize),
+    I,
+    Pc,
+}
+
+/// The VM's state after a frame, everything the panels show.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    // palette indexes at hi-res resolution, see Screen::pixel_indexes
+    pub pixels: Vec<u8>,
+    pub registers: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub stack: Vec<u16>,
+    pub dt: u8,
+    pub st: u8,
+    pub ram: Vec<u8>,
+    // sorted
+    pub breakpoints: Vec<u16>,
+    pub running: bool,
+    pub cycle_count: u64,
+    // why the VM last stopped on its own, a breakpoint or an error
+    pub status: Option<String>,
+}
+
+/// A VM and whether it is running, driven by Commands. Lives on the VM thread.
+pub struct Machine {
+    vm: VM,
+    running: bool,
+    status: Option<String>,
+}
+
+impl Machine {
+    pub fn new(vm: VM) -> Self {
+        Self {
+            vm,
+            running: true,
+            status: None,
+        }
+    }
+
+    /// Apply a command, false once the thread should stop.
+    pub fn handle(&mut self, command: Command) -> bool {
+        match command {
+            Command::SetRunning(running) => {
+                self.running = running;
+                if running {
+                    self.status = None;
+                }
+            }
+            Command::Step if !self.running => self.step(),
+            Command::Step => {}
+            Command::Reset => {
+                self.vm.reset();
+                self.status = None;
+            }
+            Command::Keys(keys) => {
+                for (key, pressed) in keys.into_iter().enumerate() {
+                    self.vm.set_key(key, pressed);
+                }
+            }
+            Command::ToggleBreakpoint(addr) => {
+                if !self.vm.remove_breakpoint(addr) {
+                    self.vm.add_breakpoint(addr);
+                }
+            }
+            Command::SetRegister(Register::V(x), value) => self.vm.set_register(x, value as u8),
+            Command::SetRegister(Register::I, addr) => self.vm.set_i(addr),
+            Command::SetRegister(Register::Pc, addr) => {
+                if let Err(e) = self.vm.set_pc(addr) {
+                    self.status = Some(e.to_string());
+                }
+            }
+            Command::WriteRam(addr, value) => {
+                if let Err(e) = self.vm.write_ram(addr, value) {
+                    self.status = Some(e.to_string());
+                }
+            }
+            Command::Quit => return false,
+        }
+        true
+    }
+
+    // like debugger::step in the main binary: a step while a draw waits for vblank ends the frame
+    fn step(&mut self) {
+        if self.vm.waiting_for_vblank() {
+            self.vm.tick_timers();
+        } else if let Err(e) = self.vm.step() {
+            self.status = Some(e.to_string());
+        }
+    }
+
+    /// Run a frame if running, stopping at breakpoints and errors.
+    pub fn frame(&mut self, cycles: u32) {
+        if !self.running {
+            return;
+        }
+        match self.vm.run_frame(cycles) {
+            Ok(FrameEnd::Complete) => {}
+            Ok(FrameEnd::Breakpoint(addr)) => {
+                self.running = false;
+                self.status = Some(format!("breakpoint at 0x{addr:03X}"));
+            }
+            Err(e) => {
+                self.running = false;
+                self.status = Some(e.to_string());
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let mut breakpoints: Vec<u16> = self.vm.breakpoints().iter().copied().collect();
+        breakpoints.sort();
+        Snapshot {
+            pixels: self
+                .vm
+                .display
+                .pixel_indexes(HIRES_SCREEN_WIDTH, HIRES_SCREEN_HEIGHT),
+            registers: *self.vm.registers(),
+            i: self.vm.i(),
+            pc: self.vm.pc(),
+            stack: self.vm.stack().to_vec(),
+            dt: self.vm.dt(),
+            st: self.vm.st(),
+            ram: self.vm.ram().to_vec(),
+            breakpoints,
+            running: self.running,
+            cycle_count: self.vm.cycle_count(),
+            status: self.status.clone(),
+        }
+    }
+}
+
+/// Handle on the VM thread. Dropping it stops the thread.
+pub struct Session {
+    commands: Sender<Command>,
+    snapshots: Receiver<Snapshot>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Session {
+    /// Start running rom with config's variant, quirks and speed on a new thread.
+    pub fn spawn(rom: Vec<u8>, config: Config) -> Result<Self, String> {
+        let (commands, command_rx) = mpsc::channel();
+        let (snapshot_tx, snapshots) = mpsc::channel();
+        let (ready_tx, ready) = mpsc::channel();
+        // the VM holds trait objects that aren't Send, so it is built on its own thread
+        let thread = thread::spawn(move || {
+            let vm = VM::builder()
+                .display(NullRenderer)
+                .compat(config.variant)
+                .quirks(config.quirks.apply(config.variant.default_quirks()))
+                .build()
+                .and_then(|mut vm| vm.load_rom(&rom).map(|()| vm));
+            let mut machine = match vm {
+                Ok(vm) => {
+                    let _ = ready_tx.send(Ok(()));
+                    Machine::new(vm)
+                }
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e.to_string()));
+                    return;
+                }
+            };
+            let mut frame_timer = FrameTimer::new(config.timer_rate_hz);
+            loop {
+                // a closed channel means the GUI is gone
+                let keep_going = command_rx.try_iter().all(|command| machine.handle(command));
+                if !keep_going {
+                    return;
+                }
+                machine.frame(config.cycles_per_frame);
+                if snapshot_tx.send(machine.snapshot()).is_err() {
+                    return;
+                }
+                frame_timer.wait();
+            }
+        });
+        match ready.recv() {
+            Ok(Ok(())) => Ok(Self {
+                commands,
+                snapshots,
+                thread: Some(thread),
+            }),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err("VM thread stopped before starting".to_string()),
+        }
+    }
+
+    pub fn send(&self, command: Command) {
+        // only fails once the thread has stopped, which the next snapshot shows
+        let _ = self.commands.send(command);
+    }
+
+    /// The newest snapshot since the last call, None if no frame has run since.
+    pub fn latest(&self) -> Option<Snapshot> {
+        self.snapshots.try_iter().last()
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.send(Command::Quit);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    // V0 += 1 and jump back, the add is at 0x200
+    const COUNTER: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+
+    fn machine() -> Machine {
+        let mut vm = VM::new_headless();
+        vm.load_rom(&COUNTER).unwrap();
+        Machine::new(vm)
+    }
+
+    #[test]
+    fn breakpoints_pause_until_resumed() {
+        let mut machine = machine();
+        machine.handle(Command::ToggleBreakpoint(0x200));
+        machine.frame(10);
+        let snapshot = machine.snapshot();
+        assert!(!snapshot.running);
+        assert_eq!(snapshot.breakpoints, [0x200]);
+        assert_eq!(snapshot.status.as_deref(), Some("breakpoint at 0x200"));
+        assert_eq!(snapshot.registers[0], 1);
+
+        // paused frames don't run
+        machine.frame(10);
+        assert_eq!(machine.snapshot().cycle_count, 2);
+
+        machine.handle(Command::Step);
+        assert_eq!(machine.snapshot().registers[0], 2);
+        machine.handle(Command::ToggleBreakpoint(0x200));
+        machine.handle(Command::SetRunning(true));
+        machine.frame(10);
+        let snapshot = machine.snapshot();
+        assert!(snapshot.running && snapshot.status.is_none());
+        assert!(snapshot.breakpoints.is_empty());
+    }
+
+    #[test]
+    fn edits_reach_the_vm() {
+        let mut machine = machine();
+        machine.handle(Command::SetRunning(false));
+        machine.handle(Command::SetRegister(Register::V(3), 0x42));
+        machine.handle(Command::SetRegister(Register::I, 0x300));
+        machine.handle(Command::WriteRam(0x300, 0x99));
+        machine.handle(Command::SetRegister(Register::Pc, 0x202));
+        let snapshot = machine.snapshot();
+        assert_eq!(snapshot.registers[3], 0x42);
+        assert_eq!((snapshot.i, snapshot.pc), (0x300, 0x202));
+        assert_eq!(snapshot.ram[0x300], 0x99);
+
+        machine.handle(Command::SetRegister(Register::Pc, 0x203));
+        assert_eq!(machine.snapshot().pc, 0x202);
+        assert!(machine.snapshot().status.is_some());
+        assert!(!machine.handle(Command::Quit));
+    }
+
+    #[test]
+    fn errors_pause_with_the_reason() {
+        // 00EE with an empty stack
+        let mut vm = VM::new_headless();
+        vm.load_rom(&[0x00, 0xEE]).unwrap();
+        let mut machine = Machine::new(vm);
+        machine.frame(10);
+        let snapshot = machine.snapshot();
+        assert!(!snapshot.running);
+        assert_eq!(
+            snapshot.status,
+            Some(chip_8::Chip8Error::StackUnderflow.to_string())
+        );
+    }
+
+    #[test]
+    fn session_runs_on_its_own_thread() {
+        let session = Session::spawn(COUNTER.to_vec(), Config::default()).unwrap();
+        session.send(Command::SetRunning(false));
+        session.send(Command::SetRegister(Register::V(5), 7));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let snapshot = loop {
+            match session.latest() {
+                Some(snapshot) if snapshot.registers[5] == 7 => break snapshot,
+                _ if Instant::now() > deadline => panic!("no snapshot from the VM thread"),
+                _ => thread::sleep(Duration::from_millis(5)),
+            }
+        };
+        assert!(!snapshot.running);
+        assert_eq!(
+            snapshot.pixels.len(),
+            HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT
+        );
+
+        assert!(Session::spawn(Vec::new(), Config::default()).is_err());
+    }
+}
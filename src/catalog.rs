@@ -0,0 +1,276 @@
+//! Batch regression runs over a ROM library, described by a TOML `.chip8catalog` file:
+//!
+//! ```toml
+//! [[rom]]
+//! path = "roms/ibm-logo.ch8"        # relative to the catalog file
+//! sha256 = "..."                    # hex digest of the ROM file
+//! compat = "chip8"                  # optional: chip8, superchip or xochip
+//! quirks = { clip_sprites = false } # optional, unset fields keep the compat mode's default
+//! cycles = 1000
+//! cycles_per_frame = 15             # optional, instructions per 60Hz timer tick
+//! pixel_hash = "0123456789abcdef"   # Screen::pixel_hash after running, in hex
+//! reference_png = "ibm-logo.png"    # F12 screenshot in the default colors, at any scale
+//! strict = true                     # optional, fail on unknown opcodes like --strict
+//! ```
+//!
+//! An entry needs pixel_hash, reference_png or both. Entries run without touching the RPL
+//! flags file, so results don't depend on earlier runs.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::{Config, QuirksConfig},
+    display::{NullRenderer, DEFAULT_PALETTE},
+    png,
+    vm::{CompatMode, SCREEN_WIDTH, VM},
+};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Catalog {
+    #[serde(default)]
+    rom: Vec<CatalogEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CatalogEntry {
+    path: PathBuf,
+    sha256: String,
+    #[serde(default)]
+    compat: CompatMode,
+    #[serde(default)]
+    quirks: QuirksConfig,
+    cycles: u64,
+    // same speed as the frontend unless overridden
+    cycles_per_frame: Option<u32>,
+    pixel_hash: Option<String>,
+    // relative to the catalog file
+    reference_png: Option<PathBuf>,
+    // CXKK is seeded so entries are reproducible
+    #[serde(default)]
+    seed: u64,
+    // unknown opcodes are skipped unless set, the same as the frontend without --strict
+    #[serde(default)]
+    strict: bool,
+}
+
+pub struct CatalogResult {
+    pub rom: PathBuf,
+    // Err holds the reason the entry failed
+    pub outcome: Result<(), String>,
+}
+
+impl CatalogResult {
+    pub fn passed(&self) -> bool {
+        self.outcome.is_ok()
+    }
+}
+
+/// Run every entry of the catalog at path headlessly, in order.
+/// A catalog that can't be read or parsed is reported as a single failed result.
+pub fn run_catalog(path: &Path) -> Vec<CatalogResult> {
+    let catalog = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|text| toml::from_str::<Catalog>(&text).map_err(|e| e.to_string()));
+    let catalog = match catalog {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            return vec![CatalogResult {
+                rom: path.to_path_buf(),
+                outcome: Err(format!("unable to load catalog: {e}")),
+            }]
+        }
+    };
+
+    let base = path.parent().unwrap_or(Path::new("."));
+    catalog
+        .rom
+        .iter()
+        .map(|entry| CatalogResult {
+            rom: entry.path.clone(),
+            outcome: run_entry(base, entry),
+        })
+        .collect()
+}
+
+fn run_entry(base: &Path, entry: &CatalogEntry) -> Result<(), String> {
+    if entry.pixel_hash.is_none() && entry.reference_png.is_none() {
+        return Err("nothing to check, set pixel_hash or reference_png".to_string());
+    }
+    let rom = fs::read(base.join(&entry.path)).map_err(|e| format!("unable to read ROM: {e}"))?;
+
+    let sha256 = sha256_hex(&rom);
+    if !sha256.eq_ignore_ascii_case(&entry.sha256) {
+        return Err(format!(
+            "SHA-256 mismatch: expected {}, got {sha256}",
+            entry.sha256
+        ));
+    }
+
+    let mut vm = VM::builder()
+        .display(NullRenderer)
+        .compat(entry.compat)
+        .quirks(entry.quirks.apply(entry.compat.default_quirks()))
+        .random_seed(entry.seed)
+        .rpl_path(None)
+        .skip_unknown_opcodes(!entry.strict)
+        .build()
+        .map_err(|e| e.to_string())?;
+    vm.load_rom(&rom).map_err(|e| e.to_string())?;
+
+    let cycles_per_frame = entry
+        .cycles_per_frame
+        .unwrap_or(Config::default().cycles_per_frame)
+        .max(1) as u64;
+    for cycle in 0..entry.cycles {
+        vm.step()
+            .map_err(|e| format!("error after {cycle} cycles: {e}"))?;
+        if (cycle + 1) % cycles_per_frame == 0 {
+            vm.tick_timers();
+        }
+    }
+
+    if let Some(expected) = &entry.pixel_hash {
+        let pixel_hash = format!("{:016x}", vm.display.pixel_hash());
+        if !pixel_hash.eq_ignore_ascii_case(expected) {
+            return Err(format!(
+                "pixel hash mismatch: expected {expected}, got {pixel_hash}"
+            ));
+        }
+    }
+    if let Some(reference) = &entry.reference_png {
+        let reference = fs::read(base.join(reference))
+            .map_err(|e| format!("unable to read reference PNG: {e}"))?;
+        // screenshots are 64 pixels wide times the window scale
+        let scale = match png::dimensions(&reference) {
+            Some((width, _)) if width > 0 && (width as usize).is_multiple_of(SCREEN_WIDTH) => {
+                width as usize / SCREEN_WIDTH
+            }
+            _ => return Err("reference PNG is not a screenshot".to_string()),
+        };
+        if vm.display.to_png(&DEFAULT_PALETTE, scale) != reference {
+            return Err("screen doesn't match the reference PNG".to_string());
+        }
+    }
+    Ok(())
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LD V0, 0x0A; LD F, V0; DRW V0, V0, 5; JP 0x206
+    const ROM: [u8; 8] = [0x60, 0x0A, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06];
+    const CYCLES: u64 = 20;
+
+    // screen after running ROM for CYCLES like an entry with default settings
+    fn expected_vm() -> VM {
+        let mut vm = VM::new_with_seed(0);
+        vm.load_rom(&ROM).unwrap();
+        let cycles_per_frame = Config::default().cycles_per_frame as u64;
+        for cycle in 0..CYCLES {
+            vm.step().unwrap();
+            if (cycle + 1) % cycles_per_frame == 0 {
+                vm.tick_timers();
+            }
+        }
+        vm
+    }
+
+    // catalog.toml next to the ROM in a fresh directory, with entry's fields after path and sha256
+    fn write_catalog(name: &str, entry: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("crust8-catalog-{}-{name}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("rom.ch8"), ROM).unwrap();
+        let catalog = format!(
+            "[[rom]]\npath = \"rom.ch8\"\nsha256 = \"{}\"\ncycles = {CYCLES}\n{entry}",
+            sha256_hex(&ROM)
+        );
+        fs::write(dir.join("catalog.toml"), catalog).unwrap();
+        dir
+    }
+
+    fn outcome(dir: &Path) -> Result<(), String> {
+        let mut results = run_catalog(&dir.join("catalog.toml"));
+        assert_eq!(results.len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+        results.remove(0).outcome
+    }
+
+    #[test]
+    fn pixel_hash_is_checked() {
+        let hash = format!("{:016x}", expected_vm().display.pixel_hash());
+        let dir = write_catalog("hash", &format!("pixel_hash = \"{hash}\"\n"));
+        assert_eq!(outcome(&dir), Ok(()));
+
+        let dir = write_catalog("wrong-hash", "pixel_hash = \"0000000000000000\"\n");
+        assert!(outcome(&dir).unwrap_err().contains("pixel hash mismatch"));
+    }
+
+    #[test]
+    fn reference_png_is_checked_at_its_scale() {
+        let dir = write_catalog("png", "reference_png = \"expected.png\"\n");
+        fs::write(
+            dir.join("expected.png"),
+            expected_vm().display.to_png(&DEFAULT_PALETTE, 3),
+        )
+        .unwrap();
+        assert_eq!(outcome(&dir), Ok(()));
+
+        let dir = write_catalog("blank-png", "reference_png = \"expected.png\"\n");
+        let blank = VM::new_headless();
+        fs::write(
+            dir.join("expected.png"),
+            blank.display.to_png(&DEFAULT_PALETTE, 3),
+        )
+        .unwrap();
+        assert!(outcome(&dir).unwrap_err().contains("reference PNG"));
+    }
+
+    #[test]
+    fn entries_need_something_to_check() {
+        let dir = write_catalog("unchecked", "");
+        assert!(outcome(&dir).unwrap_err().contains("nothing to check"));
+    }
+
+    #[test]
+    fn sha256_mismatch_fails_before_running() {
+        let dir = write_catalog("sha", "pixel_hash = \"0000000000000000\"\n");
+        let catalog = fs::read_to_string(dir.join("catalog.toml")).unwrap();
+        let catalog = catalog.replace(&sha256_hex(&ROM), &sha256_hex(b"other"));
+        fs::write(dir.join("catalog.toml"), catalog).unwrap();
+        assert!(outcome(&dir).unwrap_err().contains("SHA-256 mismatch"));
+    }
+
+    #[test]
+    fn unset_quirks_keep_the_compat_default() {
+        let entry: CatalogEntry = toml::from_str(
+            "path = \"rom.ch8\"\nsha256 = \"\"\ncompat = \"superchip\"\ncycles = 1\n\
+             quirks = { clip_sprites = false }",
+        )
+        .unwrap();
+        let quirks = entry.quirks.apply(entry.compat.default_quirks());
+        assert!(!quirks.clip_sprites);
+        let superchip = CompatMode::SuperChip.default_quirks();
+        assert_eq!(quirks.shift_uses_vy, superchip.shift_uses_vy);
+        assert_eq!(quirks.jump_with_vx, superchip.jump_with_vx);
+        assert_eq!(
+            quirks.load_store_increments_i,
+            superchip.load_store_increments_i
+        );
+    }
+}
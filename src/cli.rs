@@ -0,0 +1,500 @@
+use std::path::PathBuf;
+
+use chip_8::{
+    config::{Config, QuirksConfig},
+    display::Theme,
+    speaker::Waveform,
+    vm::Quirks,
+};
+
+const DEFAULT_ROM: &str = "./chip8-roms/games/Pong [Paul Vervalin, 1990].ch8";
+
+const DEFAULT_HEADLESS_STEPS: u64 = 1000;
+
+// phosphor afterglow kept per frame with --effect phosphor
+const DEFAULT_DECAY: f32 = 0.75;
+
+// 30 seconds at 60Hz
+const DEFAULT_GIF_MAX_FRAMES: usize = 1800;
+
+const USAGE: &str = "usage: chip-8 [options] [rom]
+
+options:
+  --catalog <path>   run a .chip8catalog headlessly and exit with 0 if every entry passes
+  --analyze-rom      print static opcode frequencies for the ROM and exit
+  --disassemble, --disasm
+                     print a listing of the ROM and exit
+  --headless         run without a window for --steps instructions (default 1000), print the
+                     screen's pixel hash and exit, with 1 if the program hit an error
+  --steps <n>        instructions run by --headless
+  --renderer <sdl|terminal>
+                     draw in a window (default) or in the terminal with ANSI colors, the
+                     terminal renderer has no keyboard input besides --replay-input
+  --list-controllers print the game controllers SDL can see and exit
+  --dump-memory <start>,<len>
+                     print a hex dump of RAM with the ROM loaded and exit, e.g. 0x200,256
+  --cycles-per-frame <n>, --ipf <n>
+                     instructions executed per frame (default 10), the effective clock
+                     is n times the timer rate, 600 instructions a second by default
+  --record-input <path>, --record <path>
+                     write every keypad press and release to path on exit, for replaying the run
+  --replay-input <path>, --replay <path>
+                     play back a --record-input file instead of reading the keyboard, at the
+                     speed and with the seed it was recorded with
+  --replay-and-live  with --replay-input, also read the keyboard and controllers
+  --record-gif <path>
+                     record the screen from startup and write it to path as an animated GIF
+                     on exit, F9 records to a timestamped file instead
+  --gif-max-frames <n>
+                     stop a GIF recording after n frames (default 1800, 30 seconds)
+  --expect-pixel <x>,<y>=<true|false>
+                     with --headless, exit with 1 unless the pixel is lit (or unlit) at the end,
+                     can be repeated
+  --strict           stop on unknown opcodes instead of skipping them, and quit on any error
+                     instead of pausing in the debugger
+  --start-paused     start in the debugger, Escape or F1 resumes and F2/F10 step one instruction
+  --break <addr>     pause when PC reaches addr (hex), can be repeated. While running,
+                     type b <addr>, d <addr> or l to add, delete or list breakpoints
+  --seed <n>         seed CXKK's random numbers to replay a run exactly (default: the clock),
+                     the seed in use is shown by F4
+  --fg-color <rrggbb>, --bg-color <rrggbb>
+                     colors of lit and unlit pixels in hex, e.g. FFB000
+  --theme <name>     classic, amber, green or gameboy colors, F8 cycles through them
+  --beep-freq <hz>   buzzer pitch (default 440)
+  --volume <level>, --audio-volume <level>
+                     buzzer loudness from 0.0 to 1.0 (default 0.25), while running [ and ]
+                     turn it down and up and Ctrl+M mutes
+  --wave <shape>, --waveform <shape>
+                     buzzer waveform: square (default, like the original hardware), sine,
+                     triangle or sawtooth
+  --effect <name>    phosphor fades pixels out like a CRT's afterglow, none turns it off
+  --decay <d>        brightness a phosphor pixel keeps per frame, between 0 and 1 (default 0.75)
+  --scanline-alpha <n>
+                     overlay CRT scanlines, darkness from 1 to 255, 0 turns them off
+  --timer-rate <hz>  timer and frame rate (default 60, 50 for PAL machines like the ETI 660)
+
+Settings are read from ./crust8.toml or ~/.config/crust8/config.toml first, then any [[rom]]
+section matching the ROM's SHA-256, options override both.
+
+While running, Escape or F1 pauses and resumes, ` shows the registers and the RAM around PC,
+Alt+Enter toggles fullscreen, Ctrl+Q or closing the window quits.
+
+quirks (--quirk-<name> enables, --no-quirk-<name> disables, default follows the variant):
+  --profile <name>   set every quirk at once: vip (the original CHIP-8), schip, xochip or
+                     modern (CHIP-48 behavior without BXNN), --quirk options still apply on top
+  vf-reset           8XY1/8XY2/8XY3 reset VF to 0
+  i-increment        FX55/FX65 advance I by x + 1 (COSMAC VIP), disable for CHIP-48/SCHIP
+  shift-vy           8XY6/8XYE shift Vy into Vx (COSMAC VIP), disable to shift Vx in place
+  display-wait       DXYN waits for the next 60Hz tick (COSMAC VIP)
+  clip-sprites       DXYN drops sprite pixels past the right and bottom edges (COSMAC VIP,
+                     CHIP-48 and SCHIP), disable to wrap them to the opposite edge like XO-CHIP
+  key-release        FX0A completes when the key is released rather than pressed";
+
+/// Where frames are drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RendererKind {
+    Sdl,
+    Terminal,
+}
+
+/// Command line options.
+pub struct Args {
+    pub rom: PathBuf,
+    pub catalog: Option<PathBuf>,
+    pub analyze_rom: bool,
+    pub disassemble: bool,
+    pub dump_memory: Option<(u16, u16)>,
+    // Some when running headless, the number of instructions to run
+    pub headless_steps: Option<u64>,
+    pub list_controllers: bool,
+    pub renderer: RendererKind,
+    pub record_input: Option<PathBuf>,
+    pub replay_input: Option<PathBuf>,
+    pub replay_and_live: bool,
+    pub record_gif: Option<PathBuf>,
+    pub gif_max_frames: usize,
+    // pixels --headless checks at the end, x, y and whether they should be lit
+    pub expect_pixels: Vec<(usize, usize, bool)>,
+    pub strict: bool,
+    pub start_paused: bool,
+    pub breakpoints: Vec<u16>,
+    pub seed: Option<u64>,
+    // settings that override the config file when set
+    pub cycles_per_frame: Option<u32>,
+    pub timer_rate_hz: Option<f64>,
+    pub audio_freq: Option<f32>,
+    pub audio_volume: Option<f32>,
+    pub audio_waveform: Option<Waveform>,
+    // Some(0.0) turns the afterglow off
+    pub phosphor_decay: Option<f32>,
+    pub scanline_alpha: Option<u8>,
+    pub theme: Option<Theme>,
+    pub fg_color: Option<[u8; 3]>,
+    pub bg_color: Option<[u8; 3]>,
+    pub quirks: QuirksConfig,
+}
+
+impl Args {
+    pub fn parse() -> Result<Self, String> {
+        Self::parse_from(std::env::args().skip(1))
+    }
+
+    fn parse_from(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut rom = None;
+        let mut catalog = None;
+        let mut analyze_rom = false;
+        let mut disassemble = false;
+        let mut dump_memory = None;
+        let mut headless = false;
+        let mut steps = DEFAULT_HEADLESS_STEPS;
+        let mut list_controllers = false;
+        let mut renderer = RendererKind::Sdl;
+        let mut record_input = None;
+        let mut replay_input = None;
+        let mut replay_and_live = false;
+        let mut record_gif = None;
+        let mut gif_max_frames = DEFAULT_GIF_MAX_FRAMES;
+        let mut expect_pixels = Vec::new();
+        let mut strict = false;
+        let mut start_paused = false;
+        let mut breakpoints = Vec::new();
+        let mut seed = None;
+        let mut cycles_per_frame = None;
+        let mut timer_rate_hz = None;
+        let mut audio_freq = None;
+        let mut audio_volume = None;
+        let mut audio_waveform = None;
+        let mut effect = None;
+        let mut decay = None;
+        let mut scanline_alpha = None;
+        let mut theme = None;
+        let mut fg_color = None;
+        let mut bg_color = None;
+        let mut quirks = QuirksConfig::default();
+        let mut profile = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--catalog" => catalog = Some(PathBuf::from(value(&arg, args.next())?)),
+                "--analyze-rom" => analyze_rom = true,
+                "--disassemble" | "--disasm" => disassemble = true,
+                "--dump-memory" => {
+                    let range = value(&arg, args.next())?;
+                    dump_memory = Some(
+                        parse_range(&range)
+                            .ok_or_else(|| format!("{arg} expects <start>,<len>, got {range}"))?,
+                    );
+                }
+                "--headless" => headless = true,
+                "--steps" => {
+                    steps = value(&arg, args.next())?
+                        .parse()
+                        .map_err(|_| format!("{arg} expects a number"))?;
+                }
+                "--list-controllers" => list_controllers = true,
+                "--renderer" => {
+                    renderer = match value(&arg, args.next())?.as_str() {
+                        "sdl" => RendererKind::Sdl,
+                        "terminal" => RendererKind::Terminal,
+                        other => return Err(format!("{arg} expects sdl or terminal, got {other}")),
+                    };
+                }
+                "--record-input" | "--record" => {
+                    record_input = Some(PathBuf::from(value(&arg, args.next())?))
+                }
+                "--replay-input" | "--replay" => {
+                    replay_input = Some(PathBuf::from(value(&arg, args.next())?))
+                }
+                "--replay-and-live" => replay_and_live = true,
+                "--record-gif" => record_gif = Some(PathBuf::from(value(&arg, args.next())?)),
+                "--gif-max-frames" => {
+                    gif_max_frames = value(&arg, args.next())?
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| format!("{arg} expects a number of frames above 0"))?;
+                }
+                "--expect-pixel" => {
+                    let pixel = value(&arg, args.next())?;
+                    expect_pixels.push(parse_pixel(&pixel).ok_or_else(|| {
+                        format!("{arg} expects <x>,<y>=<true|false>, got {pixel}")
+                    })?);
+                }
+                "--strict" => strict = true,
+                "--start-paused" => start_paused = true,
+                "--break" => {
+                    let addr = value(&arg, args.next())?;
+                    breakpoints
+                        .push(parse_addr(&addr).ok_or_else(|| format!("invalid address {addr}"))?);
+                }
+                "--seed" => {
+                    seed = Some(
+                        value(&arg, args.next())?
+                            .parse()
+                            .map_err(|_| format!("{arg} expects a number"))?,
+                    );
+                }
+                "--timer-rate" => {
+                    timer_rate_hz = Some(
+                        value(&arg, args.next())?
+                            .parse()
+                            .ok()
+                            .filter(|&hz: &f64| hz > 0.0 && hz.is_finite())
+                            .ok_or_else(|| format!("{arg} expects a positive number"))?,
+                    );
+                }
+                "--beep-freq" => {
+                    audio_freq = Some(
+                        value(&arg, args.next())?
+                            .parse()
+                            .ok()
+                            .filter(|&hz: &f32| hz > 0.0 && hz.is_finite())
+                            .ok_or_else(|| format!("{arg} expects a positive number"))?,
+                    );
+                }
+                "--volume" | "--audio-volume" => {
+                    let volume: f32 = value(&arg, args.next())?
+                        .parse()
+                        .ok()
+                        .filter(|v: &f32| v.is_finite())
+                        .ok_or_else(|| format!("{arg} expects a number from 0.0 to 1.0"))?;
+                    audio_volume = Some(volume.clamp(0.0, 1.0));
+                }
+                "--wave" | "--waveform" => {
+                    let name = value(&arg, args.next())?;
+                    audio_waveform = Some(
+                        Waveform::by_name(&name)
+                            .ok_or_else(|| format!("unknown waveform {name}"))?,
+                    );
+                }
+                "--effect" => {
+                    let name = value(&arg, args.next())?;
+                    effect = match name.as_str() {
+                        "phosphor" => Some(true),
+                        "none" => Some(false),
+                        _ => return Err(format!("unknown effect {name}")),
+                    };
+                }
+                "--decay" => {
+                    decay = Some(
+                        value(&arg, args.next())?
+                            .parse()
+                            .ok()
+                            .filter(|&d: &f32| d > 0.0 && d < 1.0)
+                            .ok_or_else(|| format!("{arg} expects a number between 0 and 1"))?,
+                    );
+                }
+                "--scanline-alpha" => {
+                    scanline_alpha = Some(
+                        value(&arg, args.next())?
+                            .parse()
+                            .map_err(|_| format!("{arg} expects a number from 0 to 255"))?,
+                    );
+                }
+                "--theme" => {
+                    let name = value(&arg, args.next())?;
+                    theme =
+                        Some(Theme::by_name(&name).ok_or_else(|| format!("unknown theme {name}"))?);
+                }
+                "--fg-color" | "--bg-color" => {
+                    let hex = value(&arg, args.next())?;
+                    let color = parse_color(&hex).ok_or_else(|| {
+                        format!("{arg} expects a hex color like FFB000, got {hex}")
+                    })?;
+                    if arg == "--fg-color" {
+                        fg_color = Some(color);
+                    } else {
+                        bg_color = Some(color);
+                    }
+                }
+                "--cycles-per-frame" | "--ipf" => {
+                    cycles_per_frame = Some(
+                        value(&arg, args.next())?
+                            .parse()
+                            .ok()
+                            .filter(|&n| n > 0)
+                            .ok_or_else(|| format!("{arg} expects a positive number"))?,
+                    );
+                }
+                "--profile" => {
+                    let name = value(&arg, args.next())?;
+                    profile = Some(Quirks::preset(&name).ok_or_else(|| {
+                        format!("{arg} expects vip, schip, xochip or modern, got {name}")
+                    })?);
+                }
+                "--quirk-vf-reset" => quirks.vf_reset_on_bitwise = Some(true),
+                "--no-quirk-vf-reset" => quirks.vf_reset_on_bitwise = Some(false),
+                "--quirk-i-increment" => quirks.load_store_increments_i = Some(true),
+                "--no-quirk-i-increment" => quirks.load_store_increments_i = Some(false),
+                "--quirk-shift-vy" => quirks.shift_uses_vy = Some(true),
+                "--no-quirk-shift-vy" => quirks.shift_uses_vy = Some(false),
+                "--quirk-display-wait" => quirks.display_wait = Some(true),
+                "--no-quirk-display-wait" => quirks.display_wait = Some(false),
+                "--quirk-clip-sprites" => quirks.clip_sprites = Some(true),
+                "--no-quirk-clip-sprites" => quirks.clip_sprites = Some(false),
+                "--quirk-key-release" => quirks.wait_for_key_release = Some(true),
+                "--no-quirk-key-release" => quirks.wait_for_key_release = Some(false),
+                "-h" | "--help" => return Err(USAGE.to_string()),
+                flag if flag.starts_with('-') => {
+                    return Err(format!("unknown option {flag}\n\n{USAGE}"))
+                }
+                path => rom = Some(PathBuf::from(path)),
+            }
+        }
+        // the profile sets every quirk, individual options override it wherever they appear
+        if let Some(profile) = profile {
+            let mut all = QuirksConfig::from(profile);
+            all.merge(&quirks);
+            quirks = all;
+        }
+
+        Ok(Self {
+            rom: rom.unwrap_or_else(|| PathBuf::from(DEFAULT_ROM)),
+            catalog,
+            analyze_rom,
+            disassemble,
+            dump_memory,
+            headless_steps: headless.then_some(steps),
+            list_controllers,
+            renderer,
+            record_input,
+            replay_input,
+            replay_and_live,
+            record_gif,
+            gif_max_frames,
+            expect_pixels,
+            strict,
+            start_paused,
+            breakpoints,
+            seed,
+            cycles_per_frame,
+            timer_rate_hz,
+            audio_freq,
+            audio_volume,
+            audio_waveform,
+            phosphor_decay: match effect {
+                Some(false) => Some(0.0),
+                Some(true) => Some(decay.unwrap_or(DEFAULT_DECAY)),
+                None => decay,
+            },
+            scanline_alpha,
+            theme,
+            fg_color,
+            bg_color,
+            quirks,
+        })
+    }
+
+    /// Override the config file settings with the ones given on the command line.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(n) = self.cycles_per_frame {
+            config.cycles_per_frame = n;
+        }
+        if let Some(hz) = self.timer_rate_hz {
+            config.timer_rate_hz = hz;
+        }
+        config.audio_freq = self.audio_freq.unwrap_or(config.audio_freq);
+        config.audio_volume = self.audio_volume.unwrap_or(config.audio_volume);
+        config.audio_waveform = self.audio_waveform.unwrap_or(config.audio_waveform);
+        config.phosphor_decay = self.phosphor_decay.unwrap_or(config.phosphor_decay);
+        config.scanline_alpha = self.scanline_alpha.unwrap_or(config.scanline_alpha);
+        if let Some(theme) = self.theme {
+            config.fg_color = theme.fg;
+            config.bg_color = theme.bg;
+        }
+        config.fg_color = self.fg_color.unwrap_or(config.fg_color);
+        config.bg_color = self.bg_color.unwrap_or(config.bg_color);
+        config.quirks.merge(&self.quirks);
+    }
+}
+
+fn value(flag: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("{flag} expects a value\n\n{USAGE}"))
+}
+
+/// Parse a hex address, with or without a 0x prefix.
+pub fn parse_addr(s: &str) -> Option<u16> {
+    let digits = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+/// Parse start,len with start in hex and len in decimal (or hex with 0x).
+fn parse_range(s: &str) -> Option<(u16, u16)> {
+    let (start, len) = s.split_once(',')?;
+    let len = match len.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok()?,
+        None => len.parse().ok()?,
+    };
+    Some((parse_addr(start)?, len))
+}
+
+/// Parse x,y=true or x,y=false, coordinates in decimal.
+fn parse_pixel(s: &str) -> Option<(usize, usize, bool)> {
+    let (pos, lit) = s.split_once('=')?;
+    let (x, y) = pos.split_once(',')?;
+    Some((x.parse().ok()?, y.parse().ok()?, lit.parse().ok()?))
+}
+
+/// Parse an RGB color as 6 hex digits, with or without a leading #.
+fn parse_color(s: &str) -> Option<[u8; 3]> {
+    let digits = s.strip_prefix('#').unwrap_or(s);
+    if digits.len() != 6 {
+        return None;
+    }
+    let rgb = u32::from_str_radix(digits, 16).ok()?;
+    Some([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])
+}
+
+#[cfg(test)]
+mod tests {
+    use chip_8::vm::CompatMode;
+
+    use super::*;
+
+    // the quirks main runs with for these arguments, on a config file set to variant
+    fn quirks(args: &[&str], variant: CompatMode) -> Quirks {
+        let args = Args::parse_from(args.iter().map(|arg| arg.to_string())).unwrap();
+        let mut config = Config {
+            variant,
+            ..Config::default()
+        };
+        args.apply(&mut config);
+        config.quirks.apply(config.variant.default_quirks())
+    }
+
+    #[test]
+    fn profile_beats_the_compat_default() {
+        let schip = CompatMode::SuperChip.default_quirks();
+        assert_eq!(quirks(&["rom.ch8"], CompatMode::SuperChip), schip);
+        assert_eq!(
+            quirks(&["--profile", "vip", "rom.ch8"], CompatMode::SuperChip),
+            CompatMode::Chip8.default_quirks()
+        );
+    }
+
+    #[test]
+    fn explicit_quirks_beat_the_profile_in_either_order() {
+        let expected = Quirks {
+            shift_uses_vy: false,
+            display_wait: false,
+            ..CompatMode::Chip8.default_quirks()
+        };
+        let before = [
+            "--no-quirk-shift-vy",
+            "--profile",
+            "vip",
+            "--no-quirk-display-wait",
+        ];
+        let after = [
+            "--profile",
+            "vip",
+            "--no-quirk-shift-vy",
+            "--no-quirk-display-wait",
+        ];
+        assert_eq!(quirks(&before, CompatMode::SuperChip), expected);
+        assert_eq!(quirks(&after, CompatMode::XoChip), expected);
+    }
+}
@@ -0,0 +1,525 @@
+//! Emulator settings, read from a TOML file at startup:
+//!
+//! ```toml
+//! scale = 15                # window pixels per CHIP-8 pixel
+//! cycles_per_frame = 10
+//! timer_rate_hz = 60.0
+//! fg_color = [255, 255, 255]
+//! bg_color = [0, 0, 0]
+//! plane2_color = [170, 170, 170] # XO-CHIP pixels lit on plane 2 only
+//! both_color = [85, 85, 85]      # XO-CHIP pixels lit on both planes
+//! phosphor_decay = 0.75     # afterglow kept per frame, 0.0 turns it off
+//! scanline_alpha = 100      # darkness of CRT scanlines, 0 turns them off
+//! audio_freq = 440.0        # buzzer pitch in Hz
+//! audio_volume = 0.25       # 0.0 to 1.0
+//! audio_waveform = "square" # square, triangle, sawtooth or sine
+//! variant = "chip8"         # chip8, superchip or xochip
+//! gamepad_deadzone = 8000   # left stick travel ignored, 0 to 32767
+//!
+//! [quirks]                  # unset quirks keep the variant's default
+//! display_wait = false
+//!
+//! [key_map]                 # keyboard key to keypad key 0-F
+//! "X" = 0
+//!
+//! [gamepad_map]             # controller button to keypad key 0-F
+//! "a" = 5
+//!
+//! [[rom]]                   # overrides for the ROM with this SHA-256
+//! sha256 = "..."
+//! name = "Space Invaders"
+//! cycles_per_frame = 20
+//! quirks = { clip_sprites = false }
+//! key_map = { "Space" = 5 }
+//! ```
+//!
+//! Every field is optional, missing ones keep the default. Precedence, lowest first: built-in
+//! defaults, the file's global settings, the matching `[[rom]]` section, command line options.
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    catalog::sha256_hex,
+    display::DEFAULT_PALETTE,
+    input::DEFAULT_KEY_NAMES,
+    speaker::Waveform,
+    vm::{CompatMode, Quirks},
+};
+
+/// Emulator settings that aren't part of the VM's state.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // window pixels per CHIP-8 pixel in low-res mode
+    pub scale: u32,
+    // instructions executed per timer tick, 10 runs the CPU at ~600Hz with 60Hz timers
+    pub cycles_per_frame: u32,
+    // DT/ST decrement rate, a frame is run per tick
+    pub timer_rate_hz: f64,
+    pub fg_color: [u8; 3],
+    pub bg_color: [u8; 3],
+    // XO-CHIP colors, fg_color is plane 1 on its own
+    pub plane2_color: [u8; 3],
+    pub both_color: [u8; 3],
+    // brightness pixels keep per frame after turning off, 0.0 disables the afterglow
+    pub phosphor_decay: f32,
+    // darkness of the CRT scanline overlay, 0 disables it
+    pub scanline_alpha: u8,
+    // buzzer tone, used until an XO-CHIP program loads an audio pattern
+    pub audio_freq: f32,
+    pub audio_volume: f32,
+    pub audio_waveform: Waveform,
+    // SDL key name to keypad key, empty keeps the built-in layout matched by key position
+    pub key_map: HashMap<String, usize>,
+    // SDL controller button names, empty uses input::DEFAULT_GAMEPAD_NAMES
+    pub gamepad_map: HashMap<String, usize>,
+    // left stick travel ignored before it counts as a d-pad press
+    pub gamepad_deadzone: u16,
+    pub variant: CompatMode,
+    pub quirks: QuirksConfig,
+    // per-ROM overrides, see Config::for_rom
+    pub rom: Vec<RomConfig>,
+}
+
+/// Settings for one ROM, a `[[rom]]` section matched by the SHA-256 of the ROM file.
+/// Set fields override the global ones.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RomConfig {
+    pub sha256: String,
+    pub name: Option<String>,
+    pub cycles_per_frame: Option<u32>,
+    pub quirks: QuirksConfig,
+    // merged key by key into the global key_map, or into input::DEFAULT_KEY_NAMES if that is empty
+    pub key_map: HashMap<String, usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            scale: 15,
+            cycles_per_frame: 10,
+            timer_rate_hz: 60.0,
+            fg_color: [255, 255, 255],
+            bg_color: [0, 0, 0],
+            plane2_color: DEFAULT_PALETTE[2],
+            both_color: DEFAULT_PALETTE[3],
+            phosphor_decay: 0.0,
+            scanline_alpha: 0,
+            audio_freq: 440.0,
+            audio_volume: 0.25,
+            audio_waveform: Waveform::Square,
+            key_map: HashMap::new(),
+            gamepad_map: HashMap::new(),
+            gamepad_deadzone: 8000,
+            variant: CompatMode::Chip8,
+            quirks: QuirksConfig::default(),
+            rom: Vec::new(),
+        }
+    }
+}
+
+/// Quirks set in the config file or on the command line, None keeps the variant's default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuirksConfig {
+    pub shift_uses_vy: Option<bool>,
+    #[serde(alias = "i_increment")]
+    pub load_store_increments_i: Option<bool>,
+    pub jump_with_vx: Option<bool>,
+    pub vf_reset_on_bitwise: Option<bool>,
+    pub clip_sprites: Option<bool>,
+    pub display_wait: Option<bool>,
+    pub wait_for_key_release: Option<bool>,
+}
+
+impl From<Quirks> for QuirksConfig {
+    /// Every quirk set, e.g. from a Quirks::preset.
+    fn from(quirks: Quirks) -> Self {
+        Self {
+            shift_uses_vy: Some(quirks.shift_uses_vy),
+            load_store_increments_i: Some(quirks.load_store_increments_i),
+            jump_with_vx: Some(quirks.jump_with_vx),
+            vf_reset_on_bitwise: Some(quirks.vf_reset_on_bitwise),
+            clip_sprites: Some(quirks.clip_sprites),
+            display_wait: Some(quirks.display_wait),
+            wait_for_key_release: Some(quirks.wait_for_key_release),
+        }
+    }
+}
+
+impl QuirksConfig {
+    /// Take every quirk other sets, keep the rest.
+    pub fn merge(&mut self, other: &QuirksConfig) {
+        let fields = [
+            (&mut self.shift_uses_vy, other.shift_uses_vy),
+            (
+                &mut self.load_store_increments_i,
+                other.load_store_increments_i,
+            ),
+            (&mut self.jump_with_vx, other.jump_with_vx),
+            (&mut self.vf_reset_on_bitwise, other.vf_reset_on_bitwise),
+            (&mut self.clip_sprites, other.clip_sprites),
+            (&mut self.display_wait, other.display_wait),
+            (&mut self.wait_for_key_release, other.wait_for_key_release),
+        ];
+        for (field, value) in fields {
+            if value.is_some() {
+                *field = value;
+            }
+        }
+    }
+
+    pub fn apply(&self, mut quirks: Quirks) -> Quirks {
+        let overrides = [
+            (self.shift_uses_vy, &mut quirks.shift_uses_vy),
+            (
+                self.load_store_increments_i,
+                &mut quirks.load_store_increments_i,
+            ),
+            (self.jump_with_vx, &mut quirks.jump_with_vx),
+            (self.vf_reset_on_bitwise, &mut quirks.vf_reset_on_bitwise),
+            (self.clip_sprites, &mut quirks.clip_sprites),
+            (self.display_wait, &mut quirks.display_wait),
+            (self.wait_for_key_release, &mut quirks.wait_for_key_release),
+        ];
+        for (value, quirk) in overrides {
+            if let Some(value) = value {
+                *quirk = value;
+            }
+        }
+        quirks
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    // config file exists but couldn't be read
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    // not valid TOML, an unknown field or a value of the wrong type
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    // parsed, but a value is out of range
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, source } => write!(f, "unable to read {}: {source}", path.display()),
+            Self::Parse { path, source } => write!(f, "{}: {source}", path.display()),
+            Self::Invalid(reason) => write!(f, "invalid config: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Files searched by load, the first one that exists is used.
+    pub fn search_paths() -> Vec<PathBuf> {
+        let mut paths = vec![PathBuf::from("crust8.toml")];
+        // ~/.config/crust8/config.toml on Linux, platform equivalent elsewhere
+        if let Some(dir) = dirs::config_dir() {
+            paths.push(dir.join("crust8").join("config.toml"));
+        }
+        paths
+    }
+
+    /// Settings from ./crust8.toml or the user config file, defaults if neither exists.
+    pub fn load() -> Result<Config, ConfigError> {
+        match Self::search_paths().into_iter().find(|path| path.exists()) {
+            Some(path) => Self::load_from(&path),
+            None => Ok(Config::default()),
+        }
+    }
+
+    pub fn load_from(path: &Path) -> Result<Config, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let config: Config = toml::from_str(&text).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// `[[rom]]` section for the ROM, matched by SHA-256.
+    pub fn rom_config(&self, rom: &[u8]) -> Option<&RomConfig> {
+        let sha256 = sha256_hex(rom);
+        self.rom
+            .iter()
+            .find(|entry| entry.sha256.eq_ignore_ascii_case(&sha256))
+    }
+
+    /// The global settings with the ROM's `[[rom]]` section, if any, applied on top.
+    pub fn for_rom(&self, rom: &[u8]) -> Config {
+        let mut config = self.clone();
+        if let Some(entry) = self.rom_config(rom) {
+            if let Some(n) = entry.cycles_per_frame {
+                config.cycles_per_frame = n;
+            }
+            config.quirks.merge(&entry.quirks);
+            if config.key_map.is_empty() && !entry.key_map.is_empty() {
+                // empty stands for the built-in layout, which the section only adds keys to
+                config.key_map = DEFAULT_KEY_NAMES
+                    .iter()
+                    .enumerate()
+                    .map(|(key, &name)| (name.to_string(), key))
+                    .collect();
+            }
+            config.key_map.extend(entry.key_map.clone());
+        }
+        config
+    }
+
+    /// Check values that parse but can't be used.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |reason: String| Err(ConfigError::Invalid(reason));
+        if self.scale == 0 {
+            return invalid("scale must be at least 1".to_string());
+        }
+        if self.cycles_per_frame == 0 {
+            return invalid("cycles_per_frame must be at least 1".to_string());
+        }
+        if !(self.timer_rate_hz > 0.0 && self.timer_rate_hz.is_finite()) {
+            return invalid(format!(
+                "timer_rate_hz must be positive, got {}",
+                self.timer_rate_hz
+            ));
+        }
+        if !(self.audio_freq > 0.0 && self.audio_freq.is_finite()) {
+            return invalid(format!(
+                "audio_freq must be positive, got {}",
+                self.audio_freq
+            ));
+        }
+        if self.phosphor_decay != 0.0 && !(self.phosphor_decay > 0.0 && self.phosphor_decay < 1.0) {
+            return invalid(format!(
+                "phosphor_decay must be 0 or between 0 and 1, got {}",
+                self.phosphor_decay
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.audio_volume) {
+            return invalid(format!(
+                "audio_volume must be between 0 and 1, got {}",
+                self.audio_volume
+            ));
+        }
+        let key_maps = std::iter::once(&self.key_map).chain(self.rom.iter().map(|r| &r.key_map));
+        for key_map in key_maps {
+            if let Some((key, _)) = key_map.iter().find(|(_, &value)| value > 0xF) {
+                return invalid(format!("key_map: {key} is mapped past keypad key F"));
+            }
+        }
+        if let Some((button, _)) = self.gamepad_map.iter().find(|(_, &value)| value > 0xF) {
+            return invalid(format!("gamepad_map: {button} is mapped past keypad key F"));
+        }
+        if self.gamepad_deadzone > i16::MAX as u16 {
+            return invalid(format!(
+                "gamepad_deadzone must be at most {}, got {}",
+                i16::MAX,
+                self.gamepad_deadzone
+            ));
+        }
+        if let Some(entry) = self.rom.iter().find(|r| r.cycles_per_frame == Some(0)) {
+            return invalid(format!(
+                "cycles_per_frame must be at least 1 for ROM {}",
+                entry.sha256
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_field() {
+        let config: Config = toml::from_str(
+            r#"
+            scale = 8
+            cycles_per_frame = 20
+            timer_rate_hz = 50.0
+            fg_color = [0, 255, 0]
+            bg_color = [16, 16, 16]
+            plane2_color = [255, 0, 0]
+            both_color = [0, 0, 255]
+            audio_freq = 220.0
+            audio_volume = 0.5
+            audio_waveform = "triangle"
+            variant = "superchip"
+            gamepad_deadzone = 4000
+
+            [quirks]
+            shift_uses_vy = false
+            i_increment = false
+            clip_sprites = true
+
+            [key_map]
+            "Q" = 4
+            "W" = 5
+
+            [gamepad_map]
+            "a" = 5
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.scale, 8);
+        assert_eq!(config.cycles_per_frame, 20);
+        assert_eq!(config.timer_rate_hz, 50.0);
+        assert_eq!(config.fg_color, [0, 255, 0]);
+        assert_eq!(config.bg_color, [16, 16, 16]);
+        assert_eq!(config.plane2_color, [255, 0, 0]);
+        assert_eq!(config.both_color, [0, 0, 255]);
+        assert_eq!(config.audio_freq, 220.0);
+        assert_eq!(config.audio_volume, 0.5);
+        assert_eq!(config.audio_waveform, Waveform::Triangle);
+        assert_eq!(config.variant, CompatMode::SuperChip);
+        assert_eq!(
+            config.quirks,
+            QuirksConfig {
+                shift_uses_vy: Some(false),
+                load_store_increments_i: Some(false),
+                clip_sprites: Some(true),
+                ..QuirksConfig::default()
+            }
+        );
+        assert_eq!(
+            config.key_map,
+            HashMap::from([("Q".to_string(), 4), ("W".to_string(), 5)])
+        );
+        assert_eq!(config.gamepad_map, HashMap::from([("a".to_string(), 5)]));
+        assert_eq!(config.gamepad_deadzone, 4000);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn missing_fields_keep_defaults() {
+        let config: Config = toml::from_str("scale = 4").unwrap();
+        assert_eq!(
+            config,
+            Config {
+                scale: 4,
+                ..Config::default()
+            }
+        );
+    }
+
+    #[test]
+    fn default_is_valid() {
+        let config = Config::default();
+        config.validate().unwrap();
+        // SDL rejects zero sized windows and audio frequencies it can't play
+        assert!(config.scale > 0);
+        assert!(config.audio_freq > 0.0 && config.audio_freq < 22050.0);
+    }
+
+    #[test]
+    fn rejects_unknown_fields_and_bad_values() {
+        assert!(toml::from_str::<Config>("speed = 3").is_err());
+        assert!(toml::from_str::<Config>("scale = \"big\"").is_err());
+        let config: Config = toml::from_str("audio_volume = 2.0").unwrap();
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+        let config: Config = toml::from_str("phosphor_decay = 1.0").unwrap();
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn for_rom_applies_the_matching_section() {
+        let invaders = [0x12, 0x00];
+        let tetris = [0x13, 0x00];
+        let config: Config = toml::from_str(&format!(
+            r#"
+            cycles_per_frame = 10
+            key_map = {{ "X" = 0 }}
+
+            [[rom]]
+            sha256 = "{}"
+            name = "Space Invaders"
+            cycles_per_frame = 20
+            quirks = {{ clip_sprites = false }}
+
+            [[rom]]
+            sha256 = "{}"
+            name = "Tetris"
+            cycles_per_frame = 30
+            key_map = {{ "Space" = 5 }}
+            "#,
+            sha256_hex(&invaders),
+            sha256_hex(&tetris).to_uppercase(),
+        ))
+        .unwrap();
+        config.validate().unwrap();
+
+        let name = |rom: &[u8]| config.rom_config(rom).and_then(|r| r.name.as_deref());
+        assert_eq!(name(&invaders), Some("Space Invaders"));
+        assert_eq!(name(&tetris), Some("Tetris"));
+        assert_eq!(name(&[0x00, 0xE0]), None);
+
+        let merged = config.for_rom(&invaders);
+        assert_eq!(merged.cycles_per_frame, 20);
+        assert_eq!(merged.quirks.clip_sprites, Some(false));
+
+        let merged = config.for_rom(&tetris);
+        assert_eq!(merged.cycles_per_frame, 30);
+        assert_eq!(merged.quirks.clip_sprites, None);
+        assert_eq!(merged.key_map.len(), 2);
+
+        assert_eq!(config.for_rom(&[0x00, 0xE0]), config);
+    }
+
+    #[test]
+    fn rom_key_map_adds_to_the_built_in_layout() {
+        let rom = [0x12, 0x00];
+        let config: Config = toml::from_str(&format!(
+            r#"
+            [[rom]]
+            sha256 = "{}"
+            key_map = {{ "Space" = 5 }}
+            "#,
+            sha256_hex(&rom),
+        ))
+        .unwrap();
+        assert!(config.key_map.is_empty());
+
+        let key_map = config.for_rom(&rom).key_map;
+        assert_eq!(key_map.len(), DEFAULT_KEY_NAMES.len() + 1);
+        assert_eq!(key_map["Space"], 5);
+        for (key, name) in DEFAULT_KEY_NAMES.iter().enumerate() {
+            assert_eq!(key_map[*name], key);
+        }
+        // without a section the built-in layout is left to the frontend
+        assert!(config.for_rom(&[0x00, 0xE0]).key_map.is_empty());
+    }
+
+    #[test]
+    fn quirks_override_variant_defaults() {
+        let overrides = QuirksConfig {
+            display_wait: Some(false),
+            ..QuirksConfig::default()
+        };
+        let quirks = overrides.apply(CompatMode::Chip8.default_quirks());
+        assert!(!quirks.display_wait);
+        assert_eq!(
+            quirks.shift_uses_vy,
+            CompatMode::Chip8.default_quirks().shift_uses_vy
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use std::{
+    io::BufRead,
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use chip_8::{disasm::disassemble, error::Chip8Error, opcode::Opcode, VM};
+
+use crate::cli::parse_addr;
+
+/// Read debugger commands from stdin on a background thread, one per line.
+pub fn spawn_command_reader() -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// Run a command typed while paused:
+/// `b <addr>` adds a breakpoint, `d <addr>` deletes one and `l` lists them.
+pub fn handle_command(vm: &mut VM, line: &str) {
+    let mut parts = line.split_whitespace();
+    let command = parts.next();
+    let addr = parts.next().map(parse_addr);
+    match (command, addr) {
+        (Some("b"), Some(Some(addr))) => {
+            vm.add_breakpoint(addr);
+            eprintln!("breakpoint added at 0x{addr:03X}");
+        }
+        (Some("d"), Some(Some(addr))) => {
+            if vm.remove_breakpoint(addr) {
+                eprintln!("breakpoint removed at 0x{addr:03X}");
+            } else {
+                eprintln!("no breakpoint at 0x{addr:03X}");
+            }
+        }
+        (Some("l"), None) => {
+            let mut breakpoints: Vec<_> = vm.breakpoints().iter().collect();
+            breakpoints.sort();
+            for addr in breakpoints {
+                eprintln!("  0x{addr:03X}");
+            }
+        }
+        (None, _) => {}
+        _ => eprintln!("commands: b <addr>, d <addr>, l"),
+    }
+}
+
+// RAM shown by overlay_lines, in rows of 8 bytes
+const OVERLAY_RAM_ROWS: usize = 4;
+
+/// Registers, timers and the RAM around PC, for the on-screen overlay.
+/// The row holding PC is marked with >, and frozen timers get a line of their own.
+pub fn overlay_lines(vm: &VM) -> Vec<String> {
+    let registers = vm.registers();
+    let row = |name: &str, values: &[u8]| {
+        let values: Vec<String> = values.iter().map(|v| format!("{v:02X}")).collect();
+        format!("{name} {}", values.join(" "))
+    };
+    let mut lines = vec![
+        format!("PC {:04X} I {:04X} SP {}", vm.pc(), vm.i(), vm.sp()),
+        format!("DT {:02X} ST {:02X}", vm.dt(), vm.st()),
+    ];
+    if vm.timers_frozen() {
+        lines.push("timers frozen".to_string());
+    }
+    lines.extend([
+        row("V0", &registers[..8]),
+        row("V8", &registers[8..]),
+        String::new(),
+    ]);
+    let ram = vm.ram();
+    let pc_row = vm.pc() as usize & !7;
+    let first = pc_row
+        .saturating_sub(8)
+        .min(ram.len() - OVERLAY_RAM_ROWS * 8);
+    for start in (first..).step_by(8).take(OVERLAY_RAM_ROWS) {
+        let marker = if start == pc_row { ">" } else { " " };
+        lines.push(row(&format!("{marker}{start:04X}"), &ram[start..start + 8]));
+    }
+    lines
+}
+
+/// Report a breakpoint hit with the registers and the instruction about to run.
+pub fn print_breakpoint(vm: &VM, addr: u16) {
+    eprintln!("breakpoint at 0x{addr:03X}: {}", describe_current(vm));
+    let registers: Vec<String> = vm
+        .registers()
+        .iter()
+        .enumerate()
+        .map(|(x, v)| format!("V{x:X}={v:02X}"))
+        .collect();
+    eprintln!("  {} I={:03X}", registers.join(" "), vm.i());
+}
+
+/// Print the last count executed instructions, oldest first.
+pub fn print_trace(vm: &VM, count: usize) {
+    let history: Vec<_> = vm.history_iter().collect();
+    eprintln!("last {} instructions:", count.min(history.len()));
+    for entry in &history[history.len().saturating_sub(count)..] {
+        eprintln!(
+            "  0x{:03X}: {:04X} {}",
+            entry.pc,
+            entry.opcode,
+            disassemble(entry.opcode)
+        );
+    }
+}
+
+fn describe_current(vm: &VM) -> String {
+    match vm.current_instruction() {
+        Some(instruction) => format!("{instruction:04X} {}", disassemble(instruction)),
+        None => "past the end of RAM".to_string(),
+    }
+}
+
+/// Execute a single instruction, printing what it was to stderr.
+/// FX0A stays on the same PC until a key is pressed, which is reported.
+/// A step while a draw waits for vblank ends the frame instead.
+pub fn step(vm: &mut VM) -> Result<(), Chip8Error> {
+    if vm.waiting_for_vblank() {
+        // display_wait quirk, nothing executes until the frame ends
+        eprintln!("Waiting for vblank, ticking timers");
+        vm.tick_timers();
+        return Ok(());
+    }
+    let pc = vm.pc();
+    let Some(instruction) = vm.current_instruction() else {
+        return Err(Chip8Error::PcOutOfBounds(pc));
+    };
+    eprintln!("0x{pc:03X}: {instruction:04X} {}", disassemble(instruction));
+
+    vm.step()?;
+    if vm.pc() == pc && matches!(Opcode::decode(instruction), Some(Opcode::LdVxK { .. })) {
+        eprintln!("Waiting for key press");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chip_8::vm::FrameEnd;
+
+    use super::*;
+
+    // V0 = 1, V1 = 2, JP 0x208 over a data word, then ADD V0, 1 and spin
+    const ROM: [u8; 12] = [
+        0x60, 0x01, 0x61, 0x02, 0x12, 0x08, 0xFF, 0xFF, 0x70, 0x01, 0x12, 0x0A,
+    ];
+
+    #[test]
+    fn breakpoint_pauses_at_the_jump_target() {
+        let mut vm = VM::new_headless();
+        vm.load_rom(&ROM).unwrap();
+        handle_command(&mut vm, "b 0x208");
+        assert_eq!(vm.run_frame(10), Ok(FrameEnd::Breakpoint(0x208)));
+        assert_eq!(vm.pc(), 0x208);
+        assert_eq!(vm.cycle_count(), 3);
+        // paused before the instruction at the breakpoint ran
+        assert_eq!(vm.registers()[0], 1);
+
+        handle_command(&mut vm, "d 208");
+        assert_eq!(vm.run_frame(10), Ok(FrameEnd::Complete));
+        assert_eq!(vm.registers()[0], 2);
+    }
+
+    #[test]
+    fn commands_add_and_remove_breakpoints() {
+        let mut vm = VM::new_headless();
+        handle_command(&mut vm, "b 300");
+        handle_command(&mut vm, "b 0x2A4");
+        handle_command(&mut vm, "l");
+        assert_eq!(vm.breakpoints().len(), 2);
+        assert!(vm.breakpoints().contains(&0x2A4));
+
+        handle_command(&mut vm, "d 300");
+        // not a breakpoint, bad address or unknown command, all left alone
+        handle_command(&mut vm, "d 400");
+        handle_command(&mut vm, "b zz");
+        handle_command(&mut vm, "x 2A4");
+        handle_command(&mut vm, "");
+        assert_eq!(vm.breakpoints().iter().collect::<Vec<_>>(), [&0x2A4]);
+    }
+
+    #[test]
+    fn overlay_shows_frozen_timers() {
+        let mut vm = VM::new_headless();
+        assert!(!overlay_lines(&vm).contains(&"timers frozen".to_string()));
+        vm.freeze_timer(true);
+        let lines = overlay_lines(&vm);
+        assert_eq!(lines[1], "DT 00 ST 00");
+        assert_eq!(lines[2], "timers frozen");
+        assert_eq!(lines[3], "V0 00 00 00 00 00 00 00 00");
+    }
+}
@@ -0,0 +1,202 @@
+//! Mnemonics follow Cowgod's CHIP-8 technical reference, with the usual names for the
+//! SCHIP and XO-CHIP extensions.
+use std::fmt;
+
+use crate::{opcode::Opcode, rom::ROM_START};
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Cls => write!(f, "CLS"),
+            Self::Ret => write!(f, "RET"),
+            Self::Scd { n } => write!(f, "SCD {n}"),
+            Self::Scr => write!(f, "SCR"),
+            Self::Scl => write!(f, "SCL"),
+            Self::Low => write!(f, "LOW"),
+            Self::High => write!(f, "HIGH"),
+            Self::SelPlane { n } => write!(f, "PLANE {n}"),
+            Self::Sys { nnn } => write!(f, "SYS 0x{nnn:03X}"),
+            Self::Jp { nnn } => write!(f, "JP 0x{nnn:03X}"),
+            Self::Call { nnn } => write!(f, "CALL 0x{nnn:03X}"),
+            Self::SeVxKk { x, kk } => write!(f, "SE V{x:X}, 0x{kk:02X}"),
+            Self::SneVxKk { x, kk } => write!(f, "SNE V{x:X}, 0x{kk:02X}"),
+            Self::SeVxVy { x, y } => write!(f, "SE V{x:X}, V{y:X}"),
+            Self::LdIRange { x, y } => write!(f, "SAVE V{x:X} - V{y:X}"),
+            Self::LdRangeI { x, y } => write!(f, "LOAD V{x:X} - V{y:X}"),
+            Self::LdVxKk { x, kk } => write!(f, "LD V{x:X}, 0x{kk:02X}"),
+            Self::AddVxKk { x, kk } => write!(f, "ADD V{x:X}, 0x{kk:02X}"),
+            Self::LdVxVy { x, y } => write!(f, "LD V{x:X}, V{y:X}"),
+            Self::OrVxVy { x, y } => write!(f, "OR V{x:X}, V{y:X}"),
+            Self::AndVxVy { x, y } => write!(f, "AND V{x:X}, V{y:X}"),
+            Self::XorVxVy { x, y } => write!(f, "XOR V{x:X}, V{y:X}"),
+            Self::AddVxVy { x, y } => write!(f, "ADD V{x:X}, V{y:X}"),
+            Self::SubVxVy { x, y } => write!(f, "SUB V{x:X}, V{y:X}"),
+            Self::ShrVxVy { x, y } => write!(f, "SHR V{x:X}, V{y:X}"),
+            Self::SubnVxVy { x, y } => write!(f, "SUBN V{x:X}, V{y:X}"),
+            Self::ShlVxVy { x, y } => write!(f, "SHL V{x:X}, V{y:X}"),
+            Self::SneVxVy { x, y } => write!(f, "SNE V{x:X}, V{y:X}"),
+            Self::LdIAddr { nnn } => write!(f, "LD I, 0x{nnn:03X}"),
+            Self::LdILong { nnnn } => write!(f, "LD I, 0x{nnnn:04X}"),
+            Self::JpV0Addr { nnn } => write!(f, "JP V0, 0x{nnn:03X}"),
+            Self::RndVxKk { x, kk } => write!(f, "RND V{x:X}, 0x{kk:02X}"),
+            Self::DrwVxVyN { x, y, n } => write!(f, "DRW V{x:X}, V{y:X}, {n}"),
+            Self::SkpVx { x } => write!(f, "SKP V{x:X}"),
+            Self::SknpVx { x } => write!(f, "SKNP V{x:X}"),
+            Self::LdVxDt { x } => write!(f, "LD V{x:X}, DT"),
+            Self::LdVxK { x } => write!(f, "LD V{x:X}, K"),
+            Self::LdDtVx { x } => write!(f, "LD DT, V{x:X}"),
+            Self::LdStVx { x } => write!(f, "LD ST, V{x:X}"),
+            Self::AddIVx { x } => write!(f, "ADD I, V{x:X}"),
+            Self::LdFVx { x } => write!(f, "LD F, V{x:X}"),
+            Self::LdLfVx { x } => write!(f, "LD HF, V{x:X}"),
+            Self::LdBVx { x } => write!(f, "LD B, V{x:X}"),
+            Self::LdAudioI => write!(f, "AUDIO"),
+            Self::LdPitchVx { x } => write!(f, "PITCH V{x:X}"),
+            Self::LdIVx { x } => write!(f, "LD [I], V{x:X}"),
+            Self::LdVxI { x } => write!(f, "LD V{x:X}, [I]"),
+            Self::LdRVx { x } => write!(f, "LD R, V{x:X}"),
+            Self::LdVxR { x } => write!(f, "LD V{x:X}, R"),
+        }
+    }
+}
+
+/// Mnemonic for a single instruction word, e.g. 0xD125 is "DRW V1, V2, 5".
+/// F000 is shown without its address, which is in the following word.
+pub fn disassemble(opcode: u16) -> String {
+    if opcode == 0xF000 {
+        return "LD I, long".to_string();
+    }
+    match Opcode::decode(opcode) {
+        Some(decoded) => decoded.to_string(),
+        // not an instruction, most likely sprite or other data
+        None => format!("DW 0x{opcode:04X}"),
+    }
+}
+
+/// Listing of a program loaded at 0x200, as (address, mnemonic) pairs.
+/// Every word is treated as an instruction, so sprite data that happens to decode shows up
+/// as instructions too, anything else is listed as a DW data word.
+pub fn disassemble_rom(rom: &[u8]) -> Vec<(u16, String)> {
+    let mut listing = Vec::new();
+    let mut words = rom
+        .chunks_exact(2)
+        .map(|word| u16::from_be_bytes([word[0], word[1]]));
+    // bytes from the start of the ROM, kept wider than an address so that stepping past the
+    // last word of a ROM that fills 64KB of XO-CHIP RAM doesn't overflow
+    let mut offset = 0usize;
+
+    while let Some(instruction) = words.next() {
+        let addr = ROM_START.wrapping_add(offset as u16);
+        // XO-CHIP F000 NNNN is the only 4-byte instruction
+        let long = (instruction == 0xF000).then(|| words.next()).flatten();
+        match long {
+            Some(nnnn) => {
+                listing.push((addr, Opcode::LdILong { nnnn }.to_string()));
+                offset += 4;
+            }
+            None => {
+                listing.push((addr, disassemble(instruction)));
+                offset += 2;
+            }
+        }
+    }
+
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_opcode_class_has_a_mnemonic() {
+        let cases = [
+            (0x00E0, "CLS"),
+            (0x00EE, "RET"),
+            (0x00C3, "SCD 3"),
+            (0x00FB, "SCR"),
+            (0x00FC, "SCL"),
+            (0x00FE, "LOW"),
+            (0x00FF, "HIGH"),
+            (0x0123, "SYS 0x123"),
+            (0x1234, "JP 0x234"),
+            (0x2456, "CALL 0x456"),
+            (0x3A12, "SE VA, 0x12"),
+            (0x4B34, "SNE VB, 0x34"),
+            (0x5120, "SE V1, V2"),
+            (0x5122, "SAVE V1 - V2"),
+            (0x5123, "LOAD V1 - V2"),
+            (0x6A3F, "LD VA, 0x3F"),
+            (0x7C01, "ADD VC, 0x01"),
+            (0x8120, "LD V1, V2"),
+            (0x8121, "OR V1, V2"),
+            (0x8122, "AND V1, V2"),
+            (0x8123, "XOR V1, V2"),
+            (0x8124, "ADD V1, V2"),
+            (0x8125, "SUB V1, V2"),
+            (0x8126, "SHR V1, V2"),
+            (0x8127, "SUBN V1, V2"),
+            (0x812E, "SHL V1, V2"),
+            (0x9120, "SNE V1, V2"),
+            (0xA123, "LD I, 0x123"),
+            (0xB123, "JP V0, 0x123"),
+            (0xC20F, "RND V2, 0x0F"),
+            (0xD125, "DRW V1, V2, 5"),
+            (0xE39E, "SKP V3"),
+            (0xE3A1, "SKNP V3"),
+            (0xF000, "LD I, long"),
+            (0xF201, "PLANE 2"),
+            (0xF002, "AUDIO"),
+            (0xF207, "LD V2, DT"),
+            (0xF20A, "LD V2, K"),
+            (0xF215, "LD DT, V2"),
+            (0xF218, "LD ST, V2"),
+            (0xF21E, "ADD I, V2"),
+            (0xF229, "LD F, V2"),
+            (0xF230, "LD HF, V2"),
+            (0xF233, "LD B, V2"),
+            (0xF23A, "PITCH V2"),
+            (0xF255, "LD [I], V2"),
+            (0xF265, "LD V2, [I]"),
+            (0xF275, "LD R, V2"),
+            (0xF285, "LD V2, R"),
+        ];
+        for (opcode, mnemonic) in cases {
+            assert_eq!(disassemble(opcode), mnemonic, "{opcode:04X}");
+        }
+    }
+
+    #[test]
+    fn unknown_words_are_data() {
+        // there is no FX9E, the font instruction is FX29
+        assert_eq!(disassemble(0xF29E), "DW 0xF29E");
+        assert_eq!(disassemble(0x5001), "DW 0x5001");
+        assert_eq!(disassemble(0xE1FF), "DW 0xE1FF");
+    }
+
+    #[test]
+    fn rom_listing_steps_over_long_loads() {
+        let rom = [0x00, 0xE0, 0xF0, 0x00, 0xAB, 0xCD, 0x12, 0x00, 0xFF];
+        assert_eq!(
+            disassemble_rom(&rom),
+            [
+                (0x200, "CLS".to_string()),
+                (0x202, "LD I, 0xABCD".to_string()),
+                (0x206, "JP 0x200".to_string()),
+            ]
+        );
+        // F000 with nothing after it
+        assert_eq!(
+            disassemble_rom(&[0xF0, 0x00]),
+            [(0x200, "LD I, long".to_string())]
+        );
+    }
+
+    #[test]
+    fn rom_filling_xochip_ram_ends_at_the_last_address() {
+        let rom = vec![0; 0x10000 - ROM_START as usize];
+        let listing = disassemble_rom(&rom);
+        assert_eq!(listing.len(), rom.len() / 2);
+        assert_eq!(listing.last().unwrap().0, 0xFFFE);
+    }
+}
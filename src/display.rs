@@ -1,62 +1,640 @@
-use sdl2::{pixels::Color, rect::Rect, render::Canvas, video::Window};
+use std::{fs, io, path::Path};
 
 use crate::{
-    vm::{SCREEN_HEIGHT, SCREEN_WIDTH},
-    SCALE,
+    png,
+    vm::{HIRES_SCREEN_HEIGHT, HIRES_SCREEN_WIDTH, SCREEN_HEIGHT, SCREEN_WIDTH},
 };
 
+/// Presents the Screen's pixels, keeps the VM independent of the windowing backend.
+/// See `sdl::SdlRenderer` for the default implementation.
+pub trait Renderer {
+    fn render(&mut self, screen: &Screen) -> Result<(), String>;
+
+    /// Update the window title, if the backend has one.
+    fn set_title(&mut self, _title: &str) {}
+
+    /// Colors indexed by Screen::pixel_color, for backends that draw in color.
+    /// Takes effect on the next render, see Screen::mark_dirty.
+    fn set_palette(&mut self, _palette: [[u8; 3]; 4]) {}
+
+    /// Switch between a window and fullscreen, letterboxed to keep the aspect ratio.
+    /// Takes effect on the next render, see Screen::mark_dirty.
+    fn set_fullscreen(&mut self, _fullscreen: bool) -> Result<(), String> {
+        Err("this renderer has no fullscreen mode".to_string())
+    }
+
+    /// Text drawn on top of the frame, see overlay::render_text. No lines removes it.
+    /// Takes effect on the next render, see Screen::mark_dirty.
+    fn set_overlay(&mut self, _lines: &[String]) {}
+}
+
+/// Discards frames, for running the VM headless.
+pub struct NullRenderer;
+
+impl Renderer for NullRenderer {
+    fn render(&mut self, _screen: &Screen) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Colors indexed by Screen::pixel_color: background, plane 1, plane 2, both planes.
+pub const DEFAULT_PALETTE: [[u8; 3]; 4] =
+    [[0, 0, 0], [255, 255, 255], [170, 170, 170], [85, 85, 85]];
+
+/// Named foreground and background colors, selected with --theme.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Theme {
+    pub name: &'static str,
+    pub fg: [u8; 3],
+    pub bg: [u8; 3],
+}
+
+pub const THEMES: [Theme; 4] = [
+    Theme {
+        name: "classic",
+        fg: [0xFF, 0xFF, 0xFF],
+        bg: [0x00, 0x00, 0x00],
+    },
+    Theme {
+        name: "amber",
+        fg: [0xFF, 0xB0, 0x00],
+        bg: [0x1C, 0x10, 0x00],
+    },
+    Theme {
+        name: "green",
+        fg: [0x33, 0xFF, 0x33],
+        bg: [0x00, 0x11, 0x00],
+    },
+    Theme {
+        name: "gameboy",
+        fg: [0x9B, 0xBC, 0x0F],
+        bg: [0x0F, 0x38, 0x0F],
+    },
+];
+
+impl Theme {
+    pub fn by_name(name: &str) -> Option<Theme> {
+        THEMES
+            .into_iter()
+            .find(|theme| theme.name.eq_ignore_ascii_case(name))
+    }
+}
+
+// sized for SCHIP extended mode, only the top-left 64x32 is used in low-res mode
+type Plane = [[bool; HIRES_SCREEN_WIDTH]; HIRES_SCREEN_HEIGHT];
+
+// XO-CHIP bitplanes, plane 1 is the only one used by CHIP-8 and SCHIP programs
+pub const PLANE_COUNT: usize = 2;
+
+// Screen::save_state size: mode, selected planes and every plane packed 8 pixels per byte
+pub const SCREEN_STATE_LEN: usize = 2 + PLANE_COUNT * HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT / 8;
+
 pub struct Screen {
-    pixels: [[bool; SCREEN_WIDTH]; SCREEN_HEIGHT],
+    planes: [Plane; PLANE_COUNT],
+    // bitmask of planes affected by drawing, clearing and scrolling, bit 0 is plane 1
+    selected_planes: u8,
+    hires: bool,
     draw_flag: bool,
-    canvas: Canvas<Window>,
+    // pixel_hash as of the last render, to catch changes that didn't call mark_dirty
+    #[cfg(debug_assertions)]
+    prev_pixel_hash: u64,
 }
 
 impl Screen {
-    pub fn new(canvas: Canvas<Window>) -> Self {
+    pub fn new() -> Self {
         Self {
-            pixels: [[false; SCREEN_WIDTH]; SCREEN_HEIGHT],
+            planes: [[[false; HIRES_SCREEN_WIDTH]; HIRES_SCREEN_HEIGHT]; PLANE_COUNT],
+            selected_planes: 0b01,
+            hires: false,
             draw_flag: true,
-            canvas,
+            #[cfg(debug_assertions)]
+            prev_pixel_hash: 0,
         }
     }
 
+    /// Clear the selected planes.
     pub fn clear(&mut self) {
-        self.pixels = [[false; SCREEN_WIDTH]; SCREEN_HEIGHT];
-        self.draw_flag = true;
+        for plane in self.selected() {
+            self.planes[plane] = [[false; HIRES_SCREEN_WIDTH]; HIRES_SCREEN_HEIGHT];
+        }
+        self.mark_dirty();
     }
 
-    pub fn draw(&mut self) -> Result<(), String> {
+    /// Present the pixels through renderer, skipped if nothing changed since the last draw.
+    pub fn draw(&mut self, renderer: &mut dyn Renderer) -> Result<(), String> {
         if !self.draw_flag {
+            #[cfg(debug_assertions)]
+            debug_assert_eq!(
+                self.pixel_hash(),
+                self.prev_pixel_hash,
+                "pixels changed without Screen::mark_dirty"
+            );
             return Ok(());
         }
-        let mut pixel: u8;
-        let pt = |p: usize| (p as i32) * (SCALE as i32);
+        renderer.render(self)?;
+        self.draw_flag = false;
+        #[cfg(debug_assertions)]
+        {
+            self.prev_pixel_hash = self.pixel_hash();
+        }
+        Ok(())
+    }
+
+    /// Flag the pixels as changed so the next draw presents them.
+    /// Every method that modifies the planes must call this.
+    pub fn mark_dirty(&mut self) {
+        self.draw_flag = true;
+    }
+
+    /// Switch between low-res (64x32) and SCHIP extended (128x64) mode. Clears every plane.
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.planes = [[[false; HIRES_SCREEN_WIDTH]; HIRES_SCREEN_HEIGHT]; PLANE_COUNT];
+        self.mark_dirty();
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_WIDTH
+        } else {
+            SCREEN_WIDTH
+        }
+    }
 
-        for y in 0..32 {
-            for x in 0..64 {
-                pixel = if self.pixels[y][x] { 255 } else { 0 };
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_HEIGHT
+        } else {
+            SCREEN_HEIGHT
+        }
+    }
+
+    /// Select the planes affected by later draw, clear and scroll operations. (XO-CHIP)
+    pub fn select_planes(&mut self, mask: u8) {
+        self.selected_planes = mask & 0b11;
+    }
+
+    /// Indices of the currently selected planes, in drawing order.
+    pub fn selected(&self) -> impl Iterator<Item = usize> {
+        let mask = self.selected_planes;
+        (0..PLANE_COUNT).filter(move |plane| mask & (1 << plane) != 0)
+    }
 
-                self.canvas.set_draw_color(Color::RGB(pixel, pixel, pixel));
-                self.canvas
-                    .fill_rect(Some(Rect::new(pt(x), pt(y), SCALE as u32, SCALE as u32)))?;
+    /// Shift the selected planes down n rows, vacated rows at the top are cleared.
+    pub fn scroll_down(&mut self, n: usize) {
+        let height = self.height();
+        for plane in self.selected() {
+            let pixels = &mut self.planes[plane];
+            for y in (0..height).rev() {
+                pixels[y] = if y >= n {
+                    pixels[y - n]
+                } else {
+                    [false; HIRES_SCREEN_WIDTH]
+                };
             }
         }
+        self.mark_dirty();
+    }
 
-        self.canvas.present();
-        self.draw_flag = false;
-        Ok(())
+    /// Shift the selected planes right n columns, vacated columns on the left are cleared.
+    pub fn scroll_right(&mut self, n: usize) {
+        let (width, height) = (self.width(), self.height());
+        for plane in self.selected() {
+            for row in self.planes[plane].iter_mut().take(height) {
+                for x in (0..width).rev() {
+                    row[x] = x >= n && row[x - n];
+                }
+            }
+        }
+        self.mark_dirty();
     }
 
-    pub fn set_draw_flag(&mut self, draw_flag: bool) {
-        self.draw_flag = draw_flag;
+    /// Shift the selected planes left n columns, vacated columns on the right are cleared.
+    pub fn scroll_left(&mut self, n: usize) {
+        let (width, height) = (self.width(), self.height());
+        for plane in self.selected() {
+            for row in self.planes[plane].iter_mut().take(height) {
+                for x in 0..width {
+                    row[x] = x + n < width && row[x + n];
+                }
+            }
+        }
+        self.mark_dirty();
     }
 
-    pub fn get_pixel_state(&self, x: usize, y: usize) -> bool {
-        self.pixels[y][x]
+    pub fn get_pixel_state(&self, plane: usize, x: usize, y: usize) -> bool {
+        self.planes[plane][y][x]
     }
 
-    pub fn xor_pixel(&mut self, x: usize, y: usize, state: bool) {
-        self.pixels[y][x] ^= state
+    pub fn xor_pixel(&mut self, plane: usize, x: usize, y: usize, state: bool) {
+        self.planes[plane][y][x] ^= state;
+        if state {
+            self.mark_dirty();
+        }
     }
 
+    /// Number of visible pixels lit on any plane.
+    pub fn lit_pixel_count(&self) -> usize {
+        let (width, height) = (self.width(), self.height());
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .filter(|&(x, y)| self.pixel_color(x, y) != 0)
+            .count()
+    }
+
+    /// FNV-1a hash of the visible pixels of every plane.
+    /// Stable across runs and platforms, so it can be stored to compare frames later.
+    pub fn pixel_hash(&self) -> u64 {
+        const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+        const FNV_PRIME: u64 = 0x100000001b3;
+
+        let (width, height) = (self.width(), self.height());
+        let mut hash = FNV_OFFSET;
+        for plane in &self.planes {
+            for row in plane.iter().take(height) {
+                for &pixel in row.iter().take(width) {
+                    hash ^= pixel as u64;
+                    hash = hash.wrapping_mul(FNV_PRIME);
+                }
+            }
+        }
+        hash
+    }
+
+    /// Append the mode and pixels to a VM save state, see VM::save_state.
+    pub(crate) fn save_state(&self, out: &mut Vec<u8>) {
+        out.push(self.hires as u8);
+        out.push(self.selected_planes);
+        for plane in &self.planes {
+            for row in plane {
+                for byte in row.chunks(8) {
+                    out.push(byte.iter().fold(0, |acc, &lit| (acc << 1) | lit as u8));
+                }
+            }
+        }
+    }
+
+    /// Restore from the SCREEN_STATE_LEN bytes written by save_state.
+    pub(crate) fn load_state(&mut self, state: &[u8]) {
+        self.hires = state[0] != 0;
+        self.selected_planes = state[1] & 0b11;
+        let mut bytes = state[2..].iter();
+        for plane in self.planes.iter_mut() {
+            for row in plane.iter_mut() {
+                for pixels in row.chunks_mut(8) {
+                    let byte = bytes.next().copied().unwrap_or_default();
+                    for (bit, pixel) in pixels.iter_mut().enumerate() {
+                        *pixel = byte >> (7 - bit) & 1 == 1;
+                    }
+                }
+            }
+        }
+        self.mark_dirty();
+    }
+
+    /// Palette index for a pixel: bit 0 is set by plane 1, bit 1 by plane 2.
+    pub fn pixel_color(&self, x: usize, y: usize) -> usize {
+        (0..PLANE_COUNT)
+            .filter(|&plane| self.planes[plane][y][x])
+            .fold(0, |color, plane| color | (1 << plane))
+    }
+
+    /// Palette index of every pixel, row by row, stretched to width x height.
+    pub fn pixel_indexes(&self, width: usize, height: usize) -> Vec<u8> {
+        let mut indexes = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let color = self.pixel_color(x * self.width() / width, y * self.height() / height);
+                indexes.push(color as u8);
+            }
+        }
+        indexes
+    }
+
+    /// Encode the pixels as a PNG the size of a scale-times window: 64x32 CHIP-8 pixels
+    /// scale wide, so hi-res pixels come out at half the size like on screen.
+    /// In hi-res mode scale is at least 2, so every hi-res pixel gets at least one image pixel.
+    pub fn to_png(&self, palette: &[[u8; 3]; 4], scale: usize) -> Vec<u8> {
+        let scale = if self.hires { scale.max(2) } else { scale };
+        let (width, height) = (SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale);
+        let rgb: Vec<u8> = self
+            .pixel_indexes(width, height)
+            .into_iter()
+            .flat_map(|color| palette[color as usize])
+            .collect();
+        png::encode_rgb(width as u32, height as u32, &rgb)
+    }
+
+    /// Write the pixels to path as a PNG, see to_png.
+    pub fn screenshot(
+        &self,
+        path: impl AsRef<Path>,
+        palette: &[[u8; 3]; 4],
+        scale: usize,
+    ) -> io::Result<()> {
+        fs::write(path, self.to_png(palette, scale))
+    }
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// CRT afterglow: lit pixels glow at full brightness and fade by decay every frame once they
+/// turn off, instead of going dark at once.
+pub struct Phosphor {
+    decay: f32,
+    // brightness 0.0 to 1.0 of every pixel in the current mode, row by row
+    brightness: Vec<f32>,
+}
+
+impl Phosphor {
+    /// decay is the brightness kept per frame, between 0.0 and 1.0 exclusive.
+    pub fn new(decay: f32) -> Self {
+        Self {
+            decay,
+            brightness: Vec::new(),
+        }
+    }
+
+    /// Advance one frame with the pixels now on screen. Switching resolution starts over.
+    pub fn update(&mut self, screen: &Screen) {
+        let (width, height) = (screen.width(), screen.height());
+        if self.brightness.len() != width * height {
+            self.brightness = vec![0.0; width * height];
+        }
+        for y in 0..height {
+            for x in 0..width {
+                let glow = &mut self.brightness[y * width + x];
+                *glow = if screen.pixel_color(x, y) != 0 {
+                    1.0
+                } else {
+                    *glow * self.decay
+                };
+            }
+        }
+    }
+
+    /// Brightness of the pixel as of the last update.
+    pub fn brightness(&self, width: usize, x: usize, y: usize) -> f32 {
+        self.brightness.get(y * width + x).copied().unwrap_or(0.0)
+    }
+
+    /// Color of a pixel: lit pixels keep their palette color, dark ones blend from the
+    /// background toward the foreground by their remaining glow.
+    pub fn color(&self, screen: &Screen, palette: &[[u8; 3]; 4], x: usize, y: usize) -> [u8; 3] {
+        let color = screen.pixel_color(x, y);
+        if color != 0 {
+            return palette[color];
+        }
+        let glow = self.brightness(screen.width(), x, y);
+        let [bg, fg] = [palette[0], palette[1]];
+        std::array::from_fn(|c| (bg[c] as f32 + (fg[c] as f32 - bg[c] as f32) * glow).round() as u8)
+    }
+}
+
+/// RGBA pixels for a CRT scanline overlay of width x height: even rows transparent, odd rows
+/// black at alpha, to be blended over the frame.
+pub fn scanline_overlay(width: usize, height: usize, alpha: u8) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        let rgba = [0, 0, 0, if y % 2 == 1 { alpha } else { 0 }];
+        for _ in 0..width {
+            pixels.extend(rgba);
+        }
+    }
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // lit pixels of plane in the current resolution, row by row
+    fn lit(screen: &Screen, plane: usize) -> Vec<(usize, usize)> {
+        (0..screen.height())
+            .flat_map(|y| (0..screen.width()).map(move |x| (x, y)))
+            .filter(|&(x, y)| screen.get_pixel_state(plane, x, y))
+            .collect()
+    }
+
+    // one pixel in the middle and a full line along every edge
+    fn framed_screen(hires: bool) -> Screen {
+        let mut screen = Screen::new();
+        screen.set_hires(hires);
+        let (width, height) = (screen.width(), screen.height());
+        screen.xor_pixel(0, 10, 5, true);
+        for x in 0..width {
+            screen.xor_pixel(0, x, 0, true);
+            screen.xor_pixel(0, x, height - 1, true);
+        }
+        for y in 1..height - 1 {
+            screen.xor_pixel(0, 0, y, true);
+            screen.xor_pixel(0, width - 1, y, true);
+        }
+        screen
+    }
+
+    #[test]
+    fn scroll_down_moves_rows_and_clears_the_top() {
+        let mut screen = framed_screen(true);
+        screen.scroll_down(3);
+        assert!(screen.get_pixel_state(0, 10, 8));
+        assert!(!screen.get_pixel_state(0, 10, 5));
+        // the top line moved down, the bottom line scrolled off
+        assert!((0..128).all(|x| screen.get_pixel_state(0, x, 3)));
+        assert!((0..3).all(|y| (0..128).all(|x| !screen.get_pixel_state(0, x, y))));
+        assert!(!screen.get_pixel_state(0, 10, 63));
+    }
+
+    #[test]
+    fn scroll_right_moves_columns_and_clears_the_left() {
+        let mut screen = framed_screen(true);
+        screen.scroll_right(4);
+        assert!(screen.get_pixel_state(0, 14, 5));
+        assert!(!screen.get_pixel_state(0, 10, 5));
+        assert!((1..63).all(|y| screen.get_pixel_state(0, 4, y)));
+        assert!((0..64).all(|y| (0..4).all(|x| !screen.get_pixel_state(0, x, y))));
+        assert!(!screen.get_pixel_state(0, 127, 5));
+    }
+
+    #[test]
+    fn scroll_left_moves_columns_and_clears_the_right() {
+        let mut screen = framed_screen(true);
+        screen.scroll_left(4);
+        assert!(screen.get_pixel_state(0, 6, 5));
+        assert!(!screen.get_pixel_state(0, 10, 5));
+        assert!((1..63).all(|y| screen.get_pixel_state(0, 123, y)));
+        assert!((0..64).all(|y| (124..128).all(|x| !screen.get_pixel_state(0, x, y))));
+        assert!(!screen.get_pixel_state(0, 0, 5));
+    }
+
+    #[test]
+    fn lores_scrolls_by_lores_pixels_within_64x32() {
+        // amounts are in pixels of the current mode, so 00FB moves 4 low-res pixels
+        // (8 high-res ones) like XO-CHIP and modern SCHIP, not 2 like SCHIP 1.1 on the HP48
+        let mut screen = framed_screen(false);
+        screen.scroll_right(4);
+        assert!(screen.get_pixel_state(0, 14, 5));
+        assert!((1..31).all(|y| screen.get_pixel_state(0, 4, y)));
+        assert!((0..32).all(|y| (0..4).all(|x| !screen.get_pixel_state(0, x, y))));
+        // the right edge went off the 64 pixel screen rather than into the hi-res area
+        assert!(!screen.get_pixel_state(0, 67, 5));
+
+        let mut screen = framed_screen(false);
+        screen.scroll_left(4);
+        assert!(screen.get_pixel_state(0, 6, 5));
+        assert!((0..32).all(|y| (60..64).all(|x| !screen.get_pixel_state(0, x, y))));
+
+        let mut screen = framed_screen(false);
+        screen.scroll_down(2);
+        assert!(screen.get_pixel_state(0, 10, 7));
+        assert!((0..2).all(|y| (0..64).all(|x| !screen.get_pixel_state(0, x, y))));
+        assert!(!screen.get_pixel_state(0, 10, 33));
+        assert_eq!(lit(&screen, 0).len(), 64 + 2 * 29 + 1);
+    }
+
+    #[test]
+    fn operations_only_touch_the_selected_planes() {
+        let mut screen = Screen::new();
+        screen.xor_pixel(0, 10, 5, true);
+        screen.xor_pixel(1, 20, 5, true);
+
+        screen.select_planes(0b01);
+        assert_eq!(screen.selected().collect::<Vec<_>>(), [0]);
+        screen.scroll_right(4);
+        assert_eq!(lit(&screen, 0), [(14, 5)]);
+        assert_eq!(lit(&screen, 1), [(20, 5)]);
+        screen.clear();
+        assert_eq!(lit(&screen, 0), []);
+        assert_eq!(lit(&screen, 1), [(20, 5)]);
+
+        screen.select_planes(0b10);
+        screen.scroll_down(1);
+        assert_eq!(lit(&screen, 1), [(20, 6)]);
+        screen.select_planes(0b11);
+        assert_eq!(screen.selected().collect::<Vec<_>>(), [0, 1]);
+        screen.clear();
+        assert_eq!(lit(&screen, 1), []);
+    }
+
+    #[test]
+    fn planes_composite_into_palette_indexes() {
+        let mut screen = Screen::new();
+        screen.xor_pixel(0, 1, 0, true);
+        screen.xor_pixel(1, 2, 0, true);
+        screen.xor_pixel(0, 3, 0, true);
+        screen.xor_pixel(1, 3, 0, true);
+        let colors: Vec<usize> = (0..4).map(|x| screen.pixel_color(x, 0)).collect();
+        assert_eq!(colors, [0, 1, 2, 3]);
+        assert_eq!(screen.pixel_indexes(4, 1), [0, 0, 0, 0]);
+        assert_eq!(screen.pixel_indexes(SCREEN_WIDTH, 1)[..5], [0, 1, 2, 3, 0]);
+        // every plane counts towards the hash
+        let both = screen.pixel_hash();
+        screen.xor_pixel(1, 3, 0, true);
+        assert_ne!(screen.pixel_hash(), both);
+    }
+
+    #[test]
+    fn phosphor_fades_after_a_pixel_turns_off() {
+        let decay = 0.75_f32;
+        let mut phosphor = Phosphor::new(decay);
+        let mut screen = Screen::new();
+        screen.xor_pixel(0, 3, 2, true);
+        phosphor.update(&screen);
+        assert_eq!(phosphor.brightness(SCREEN_WIDTH, 3, 2), 1.0);
+        assert_eq!(phosphor.color(&screen, &DEFAULT_PALETTE, 3, 2), [255; 3]);
+
+        screen.xor_pixel(0, 3, 2, true);
+        let frames = (0.01_f32.ln() / decay.ln()).ceil() as usize;
+        let mut last = 1.0;
+        for frame in 1..=frames {
+            phosphor.update(&screen);
+            let glow = phosphor.brightness(SCREEN_WIDTH, 3, 2);
+            assert!(glow < last, "frame {frame}: {glow} didn't fade");
+            last = glow;
+        }
+        assert!(last < 0.01);
+        // blends toward the background, which is black, under 1% of full white is left
+        let color = phosphor.color(&screen, &DEFAULT_PALETTE, 3, 2);
+        assert!(color.iter().all(|&c| c <= 2), "{color:?}");
+        assert_eq!(phosphor.brightness(SCREEN_WIDTH, 0, 0), 0.0);
+    }
+
+    #[test]
+    fn phosphor_blends_between_bg_and_fg() {
+        let mut phosphor = Phosphor::new(0.5);
+        let mut screen = Screen::new();
+        screen.xor_pixel(0, 0, 0, true);
+        phosphor.update(&screen);
+        screen.xor_pixel(0, 0, 0, true);
+        phosphor.update(&screen);
+        let mut palette = DEFAULT_PALETTE;
+        palette[0] = [0, 0, 100];
+        palette[1] = [200, 100, 0];
+        assert_eq!(phosphor.color(&screen, &palette, 0, 0), [100, 50, 50]);
+    }
+
+    #[test]
+    fn scanline_overlay_darkens_every_other_row() {
+        let (width, height) = (SCREEN_WIDTH * 15, SCREEN_HEIGHT * 15);
+        let overlay = scanline_overlay(width, height, 100);
+        assert_eq!(overlay.len(), width * height * 4);
+
+        let rows: Vec<&[u8]> = overlay.chunks(width * 4).collect();
+        let alpha_of = |row: &[u8]| {
+            let alphas: Vec<u8> = row.chunks(4).map(|rgba| rgba[3]).collect();
+            assert!(alphas.iter().all(|&a| a == alphas[0]), "row isn't uniform");
+            alphas[0]
+        };
+        let dark = rows.iter().filter(|row| alpha_of(row) == 100).count();
+        let clear = rows.iter().filter(|row| alpha_of(row) == 0).count();
+        assert_eq!((dark, clear), (height / 2, height / 2));
+        assert!(overlay.chunks(4).all(|rgba| rgba[..3] == [0, 0, 0]));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "pixels changed without Screen::mark_dirty")]
+    fn changing_pixels_without_mark_dirty_panics_in_debug() {
+        let mut screen = Screen::new();
+        screen.draw(&mut NullRenderer).unwrap();
+        // bypasses xor_pixel, which would mark the screen dirty
+        screen.planes[0][3][4] = true;
+        screen.draw(&mut NullRenderer).unwrap();
+    }
+
+    #[test]
+    fn hires_png_at_scale_1_keeps_every_pixel() {
+        let mut screen = Screen::new();
+        screen.set_hires(true);
+        // odd x, dropped when sampling down to 64x32
+        screen.xor_pixel(0, 3, 1, true);
+        let png = screen.to_png(&DEFAULT_PALETTE, 1);
+        assert_eq!(
+            png::dimensions(&png),
+            Some((HIRES_SCREEN_WIDTH as u32, HIRES_SCREEN_HEIGHT as u32))
+        );
+
+        // one stored block: rows start after the signature, IHDR, the IDAT length and type,
+        // the zlib header and the block header, each with a filter byte
+        let stride = 1 + HIRES_SCREEN_WIDTH * 3;
+        let rgb = |x: usize, y: usize| {
+            let offset = 8 + 25 + 8 + 2 + 5 + y * stride + 1 + x * 3;
+            <[u8; 3]>::try_from(&png[offset..offset + 3]).unwrap()
+        };
+        assert_eq!(rgb(3, 1), DEFAULT_PALETTE[1]);
+        assert_eq!(rgb(2, 1), DEFAULT_PALETTE[0]);
+        assert_eq!(rgb(3, 0), DEFAULT_PALETTE[0]);
+
+        // low-res keeps the requested size
+        let png = Screen::new().to_png(&DEFAULT_PALETTE, 1);
+        assert_eq!(png::dimensions(&png), Some((64, 32)));
+    }
 }
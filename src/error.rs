@@ -0,0 +1,68 @@
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Chip8Error {
+    // FX75/FX85 with x > 7, SCHIP only has 8 RPL user flags
+    RplFlagOutOfRange(u8),
+    // instruction fetch would read past the end of RAM
+    PcOutOfBounds(u16),
+    // PC set to an odd address, instructions are word aligned
+    PcMisaligned(u16),
+    // memory access through I would read or write past the end of RAM
+    MemoryOutOfBounds(u16),
+    // 2NNN with all 16 stack levels in use
+    StackOverflow,
+    // 00EE with an empty stack
+    StackUnderflow,
+    // program doesn't fit in RAM between 0x200 and the end
+    RomTooLarge { size: usize, max: usize },
+    // program shorter than one instruction
+    RomTooSmall(usize),
+    // instruction at pc doesn't decode to any known opcode
+    UnknownOpcode { instruction: u16, pc: u16 },
+    // VmBuilder was given settings that can't be combined
+    InvalidConfig(String),
+    // VM::load_state was given something that isn't a save state
+    BadStateMagic,
+    // save state written by a version of the format this build can't read
+    UnsupportedStateVersion(u8),
+    // VM::load_state was given a blob it can't restore
+    InvalidState(String),
+    // Recording::from_bytes was given a file it can't read
+    InvalidRecording(String),
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RplFlagOutOfRange(x) => {
+                write!(f, "RPL user flags only cover V0-V7, got V{x:X}")
+            }
+            Self::PcOutOfBounds(pc) => write!(f, "PC 0x{pc:04X} is outside of RAM"),
+            Self::PcMisaligned(pc) => write!(f, "PC 0x{pc:04X} is not word aligned"),
+            Self::MemoryOutOfBounds(addr) => {
+                write!(f, "memory access at 0x{addr:04X} is outside of RAM")
+            }
+            Self::StackOverflow => write!(f, "stack overflow, more than 16 nested calls"),
+            Self::StackUnderflow => write!(f, "stack underflow, return without a call"),
+            Self::RomTooLarge { size, max } => {
+                write!(f, "ROM is {size} bytes, at most {max} fit in RAM")
+            }
+            Self::RomTooSmall(size) => {
+                write!(f, "ROM is {size} bytes, too short to hold an instruction")
+            }
+            Self::UnknownOpcode { instruction, pc } => {
+                write!(f, "unknown opcode {instruction:04X} at 0x{pc:04X}")
+            }
+            Self::InvalidConfig(reason) => write!(f, "invalid VM configuration: {reason}"),
+            Self::BadStateMagic => write!(f, "not a save state"),
+            Self::UnsupportedStateVersion(version) => {
+                write!(f, "unsupported save state version {version}")
+            }
+            Self::InvalidState(reason) => write!(f, "invalid save state: {reason}"),
+            Self::InvalidRecording(reason) => write!(f, "invalid input recording: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {}
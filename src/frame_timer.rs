@@ -0,0 +1,102 @@
+use std::time::{Duration, Instant};
+
+/// Source of time for FrameTimer, so tests can run frames without waiting on the real clock.
+pub trait Clock {
+    fn now(&self) -> Instant;
+    fn sleep(&mut self, duration: Duration);
+}
+
+/// The wall clock, sleeping the current thread.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Paces the main loop to one frame per timer tick.
+pub struct FrameTimer<C: Clock = SystemClock> {
+    clock: C,
+    interval: Duration,
+    next_frame: Instant,
+}
+
+impl FrameTimer {
+    /// rate_hz is the timer frequency, 60Hz on the COSMAC VIP, 50Hz on PAL machines like the ETI 660.
+    pub fn new(rate_hz: f64) -> Self {
+        Self::with_clock(rate_hz, SystemClock)
+    }
+}
+
+impl<C: Clock> FrameTimer<C> {
+    pub fn with_clock(rate_hz: f64, clock: C) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / rate_hz),
+            next_frame: clock.now(),
+            clock,
+        }
+    }
+
+    /// Sleep off whatever is left of the current frame.
+    /// If the loop fell behind, the schedule restarts from now instead of bursting frames to catch up.
+    pub fn wait(&mut self) {
+        self.next_frame += self.interval;
+        let now = self.clock.now();
+        if self.next_frame > now {
+            self.clock.sleep(self.next_frame - now);
+        } else {
+            self.next_frame = now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+    use crate::vm::VM;
+
+    // advances only when slept on, the elapsed time is shared with the test
+    struct FakeClock {
+        start: Instant,
+        elapsed: Rc<Cell<Duration>>,
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.start + self.elapsed.get()
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.elapsed.set(self.elapsed.get() + duration);
+        }
+    }
+
+    #[test]
+    fn a_simulated_second_runs_cycles_per_frame_times_60_instructions() {
+        let elapsed = Rc::new(Cell::new(Duration::ZERO));
+        let clock = FakeClock {
+            start: Instant::now(),
+            elapsed: Rc::clone(&elapsed),
+        };
+        let mut timer = FrameTimer::with_clock(60.0, clock);
+        let mut vm = VM::new_headless();
+        // ADD V0, 1 and jump back, never idles
+        vm.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        let cycles_per_frame = 15;
+        let mut frames = 0;
+        while elapsed.get() < Duration::from_secs(1) {
+            vm.run_frame(cycles_per_frame).unwrap();
+            timer.wait();
+            frames += 1;
+        }
+        assert_eq!(frames, 60);
+        assert_eq!(vm.cycle_count(), cycles_per_frame as u64 * 60);
+    }
+}
@@ -0,0 +1,345 @@
+//! Animated GIF recording of the screen. Frames are palette indexes, the screen only ever
+//! uses the four palette colors.
+
+use std::collections::HashMap;
+
+use crate::{
+    display::Screen,
+    vm::{HIRES_SCREEN_HEIGHT, HIRES_SCREEN_WIDTH, SCREEN_HEIGHT, SCREEN_WIDTH},
+};
+
+// 4 colors take 2 bits per pixel
+const MIN_CODE_SIZE: u8 = 2;
+const CLEAR_CODE: u16 = 1 << MIN_CODE_SIZE;
+const END_CODE: u16 = CLEAR_CODE + 1;
+const MAX_CODES: u16 = 4096;
+
+/// Collects frames while recording and encodes them into a looping GIF on finish.
+/// Frames are kept at hi-res resolution, so low-res and hi-res parts of a recording share it.
+pub struct Recorder {
+    palette: [[u8; 3]; 4],
+    scale: usize,
+    // set once a hi-res frame is captured, the GIF is then at least 128x64
+    hires: bool,
+    // seconds each captured frame is shown for
+    frame_time: f64,
+    // distinct frames with how many captures in a row each lasted
+    frames: Vec<(Vec<u8>, u32)>,
+}
+
+impl Recorder {
+    /// Frames come in at frame_rate_hz and are encoded scale times the low-res screen size,
+    /// like the window. Recordings with hi-res frames use a scale of at least 2.
+    pub fn new(palette: [[u8; 3]; 4], scale: usize, frame_rate_hz: f64) -> Self {
+        Self {
+            palette,
+            scale: scale.max(1),
+            hires: false,
+            frame_time: 1.0 / frame_rate_hz,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Add the screen as the next frame, a repeat of the last frame only extends it.
+    pub fn capture(&mut self, screen: &Screen) {
+        let pixels = screen.pixel_indexes(HIRES_SCREEN_WIDTH, HIRES_SCREEN_HEIGHT);
+        self.hires |= screen.is_hires();
+        match self.frames.last_mut() {
+            Some((last, count)) if *last == pixels => *count += 1,
+            _ => self.frames.push((pixels, 1)),
+        }
+    }
+
+    /// Number of frames captured, repeats included.
+    pub fn len(&self) -> usize {
+        self.frames.iter().map(|&(_, count)| count as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        let scale = if self.hires {
+            self.scale.max(2)
+        } else {
+            self.scale
+        };
+        let (width, height) = (SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale);
+        let mut gif = b"GIF89a".to_vec();
+        gif.extend((width as u16).to_le_bytes());
+        gif.extend((height as u16).to_le_bytes());
+        // global color table of 4 entries, 8 bits per primary
+        gif.extend([0xF1, 0, 0]);
+        for color in self.palette {
+            gif.extend(color);
+        }
+        // loop forever
+        gif.extend([0x21, 0xFF, 0x0B]);
+        gif.extend(b"NETSCAPE2.0");
+        gif.extend([0x03, 0x01, 0x00, 0x00, 0x00]);
+
+        // delays are whole centiseconds, round the running total so the rate stays right
+        let mut elapsed = 0;
+        let mut shown = 0;
+        for (pixels, count) in &self.frames {
+            elapsed += count;
+            let end = (elapsed as f64 * self.frame_time * 100.0).round() as u32;
+            let delay = (end - shown).min(u16::MAX as u32) as u16;
+            shown = end;
+
+            gif.extend([0x21, 0xF9, 0x04, 0x00]);
+            gif.extend(delay.to_le_bytes());
+            gif.extend([0x00, 0x00]);
+            gif.push(0x2C);
+            gif.extend([0, 0, 0, 0]);
+            gif.extend((width as u16).to_le_bytes());
+            gif.extend((height as u16).to_le_bytes());
+            gif.push(0);
+            gif.push(MIN_CODE_SIZE);
+            let data = lzw_encode(&scale_frame(pixels, width, height));
+            for block in data.chunks(255) {
+                gif.push(block.len() as u8);
+                gif.extend(block);
+            }
+            gif.push(0);
+        }
+        gif.push(0x3B);
+        gif
+    }
+}
+
+// nearest neighbour from the stored hi-res frame to the output size
+fn scale_frame(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut scaled = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = y * HIRES_SCREEN_HEIGHT / height * HIRES_SCREEN_WIDTH;
+        scaled.extend((0..width).map(|x| pixels[row + x * HIRES_SCREEN_WIDTH / width]));
+    }
+    scaled
+}
+
+/// Packs variable width codes least significant bit first.
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.acc |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.acc as u8);
+        }
+        self.bytes
+    }
+}
+
+fn lzw_encode(indexes: &[u8]) -> Vec<u8> {
+    let mut out = BitWriter {
+        bytes: Vec::new(),
+        acc: 0,
+        bits: 0,
+    };
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut size = MIN_CODE_SIZE + 1;
+    let mut next = END_CODE + 1;
+    out.write(CLEAR_CODE, size);
+
+    let mut pixels = indexes.iter();
+    let Some(&first) = pixels.next() else {
+        out.write(END_CODE, size);
+        return out.finish();
+    };
+    let mut prefix = first as u16;
+    for &pixel in pixels {
+        if let Some(&code) = codes.get(&(prefix, pixel)) {
+            prefix = code;
+            continue;
+        }
+        out.write(prefix, size);
+        if next == MAX_CODES {
+            // table full, start over
+            out.write(CLEAR_CODE, size);
+            codes.clear();
+            size = MIN_CODE_SIZE + 1;
+            next = END_CODE + 1;
+        } else {
+            codes.insert((prefix, pixel), next);
+            next += 1;
+            // the decoder adds each code one step later, so it widens one code after us
+            if next > 1 << size && size < 12 {
+                size += 1;
+            }
+        }
+        prefix = pixel as u16;
+    }
+    out.write(prefix, size);
+    out.write(END_CODE, size);
+    out.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // reference GIF LZW decoder
+    fn lzw_decode(data: &[u8]) -> Vec<u8> {
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let reset = |table: &mut Vec<Vec<u8>>| {
+            *table = (0..CLEAR_CODE).map(|c| vec![c as u8]).collect();
+            table.extend([Vec::new(), Vec::new()]);
+        };
+        reset(&mut table);
+        let mut size = MIN_CODE_SIZE + 1;
+        let (mut pos, mut prev, mut out) = (0usize, None::<usize>, Vec::new());
+        loop {
+            let mut code = 0usize;
+            for bit in 0..size as usize {
+                let byte = data[(pos + bit) / 8];
+                code |= ((byte >> ((pos + bit) % 8)) as usize & 1) << bit;
+            }
+            pos += size as usize;
+            if code == CLEAR_CODE as usize {
+                reset(&mut table);
+                size = MIN_CODE_SIZE + 1;
+                prev = None;
+                continue;
+            }
+            if code == END_CODE as usize {
+                return out;
+            }
+            let entry = match prev {
+                None => table[code].clone(),
+                Some(prev) => {
+                    let entry = if code < table.len() {
+                        table[code].clone()
+                    } else {
+                        let mut entry = table[prev].clone();
+                        entry.push(table[prev][0]);
+                        entry
+                    };
+                    let mut added = table[prev].clone();
+                    added.push(entry[0]);
+                    table.push(added);
+                    if table.len() == 1 << size && size < 12 {
+                        size += 1;
+                    }
+                    entry
+                }
+            };
+            out.extend(&entry);
+            prev = Some(code);
+        }
+    }
+
+    #[test]
+    fn lzw_round_trips() {
+        // enough varied pixels to fill the table and clear it several times
+        let mut state = 1u32;
+        let noise: Vec<u8> = (0..60_000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 29) as u8 & 3
+            })
+            .collect();
+        for pixels in [vec![], vec![3], vec![0; 10_000], noise] {
+            assert_eq!(lzw_decode(&lzw_encode(&pixels)), pixels);
+        }
+    }
+
+    #[test]
+    fn repeated_frames_extend_the_last_one() {
+        let mut recorder = Recorder::new([[0; 3]; 4], 2, 60.0);
+        let mut screen = Screen::new();
+        recorder.capture(&screen);
+        recorder.capture(&screen);
+        screen.set_hires(true);
+        recorder.capture(&screen);
+        assert_eq!(recorder.len(), 3);
+        // a cleared hi-res screen looks like a cleared low-res one
+        assert_eq!(recorder.frames.len(), 1);
+
+        let gif = recorder.finish();
+        assert_eq!(&gif[..6], b"GIF89a");
+        assert_eq!(gif[6..10], [128, 0, 64, 0]);
+        assert_eq!(gif.last(), Some(&0x3B));
+    }
+
+    // palette indexes of the first frame and the logical screen size
+    fn first_frame(gif: &[u8]) -> (Vec<u8>, [u8; 4]) {
+        let control = gif
+            .windows(3)
+            .position(|w| w == [0x21, 0xF9, 0x04])
+            .unwrap();
+        let descriptor = control + 8;
+        let mut data = Vec::new();
+        let mut block = descriptor + 11;
+        while gif[block] != 0 {
+            let len = gif[block] as usize;
+            data.extend(&gif[block + 1..block + 1 + len]);
+            block += len + 1;
+        }
+        (lzw_decode(&data), gif[6..10].try_into().unwrap())
+    }
+
+    #[test]
+    fn hires_recording_at_scale_1_keeps_every_pixel() {
+        let mut recorder = Recorder::new([[0; 3]; 4], 1, 60.0);
+        let mut screen = Screen::new();
+        screen.set_hires(true);
+        // odd x, dropped when sampling down to 64x32
+        screen.xor_pixel(0, 3, 1, true);
+        recorder.capture(&screen);
+        let (pixels, size) = first_frame(&recorder.finish());
+        assert_eq!(size, [128, 0, 64, 0]);
+        assert_eq!(pixels.len(), 128 * 64);
+        assert_eq!(pixels[128 + 3], 1);
+        assert_eq!(pixels.iter().filter(|&&p| p != 0).count(), 1);
+    }
+
+    #[test]
+    fn frames_map_pixels_to_palette_indexes() {
+        let mut palette = [[0; 3]; 4];
+        palette[1] = [255, 176, 0];
+        let mut recorder = Recorder::new(palette, 1, 60.0);
+        let mut screen = Screen::new();
+        screen.xor_pixel(0, 3, 1, true);
+        recorder.capture(&screen);
+        let gif = recorder.finish();
+
+        // color table follows the 13 byte header
+        assert_eq!(gif[13..25], [0, 0, 0, 255, 176, 0, 0, 0, 0, 0, 0, 0]);
+        // graphic control extension, a 60th of a second rounds to 2 centiseconds
+        let control = gif
+            .windows(3)
+            .position(|w| w == [0x21, 0xF9, 0x04])
+            .unwrap();
+        assert_eq!(gif[control + 4..control + 6], [2, 0]);
+
+        let descriptor = control + 8;
+        assert_eq!(gif[descriptor], 0x2C);
+        assert_eq!(gif[descriptor + 5..descriptor + 9], [64, 0, 32, 0]);
+        let mut data = Vec::new();
+        let mut block = descriptor + 11;
+        while gif[block] != 0 {
+            let len = gif[block] as usize;
+            data.extend(&gif[block + 1..block + 1 + len]);
+            block += len + 1;
+        }
+        let pixels = lzw_decode(&data);
+        assert_eq!(pixels.len(), 64 * 32);
+        assert_eq!(pixels[64 + 3], 1);
+        assert_eq!(pixels.iter().filter(|&&p| p != 0).count(), 1);
+    }
+}
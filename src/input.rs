@@ -0,0 +1,280 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+// the hex keypad, keys 0 through F
+pub const KEY_COUNT: usize = 16;
+
+/// Reports the state of the hex keypad. See `sdl::SdlInput` for the default implementation.
+/// Frontends without one can call `VM::set_key` directly instead.
+pub trait InputSource {
+    /// Overwrite keys with which keypad keys are currently held down.
+    fn poll(&mut self, keys: &mut [bool; KEY_COUNT]);
+}
+
+/// Key names of the default layout, indexed by keypad key: the left side of a QWERTY keyboard.
+pub const DEFAULT_KEY_NAMES: [&str; KEY_COUNT] = [
+    "X", "1", "2", "3", "Q", "W", "E", "A", "S", "D", "Z", "C", "4", "R", "F", "V",
+];
+
+/// Controller buttons of the default gamepad layout, by SDL name, and the keypad key each
+/// presses. The d-pad covers the 2/4/6/8 directions most games use, with A on 5.
+pub const DEFAULT_GAMEPAD_NAMES: [(&str, usize); 12] = [
+    ("dpup", 0x2),
+    ("dpleft", 0x4),
+    ("dpright", 0x6),
+    ("dpdown", 0x8),
+    ("a", 0x5),
+    ("b", 0x0),
+    ("x", 0x1),
+    ("y", 0x3),
+    ("leftshoulder", 0x7),
+    ("rightshoulder", 0x9),
+    ("back", 0xA),
+    ("start", 0xB),
+];
+
+/// Resolve a `key_map` of key names to keypad keys with the frontend's name lookup,
+/// e.g. SDL's `Keycode::from_name`. Unknown names are an error.
+pub fn build_key_map<K: Eq + Hash>(
+    names: &HashMap<String, usize>,
+    lookup: impl Fn(&str) -> Option<K>,
+) -> Result<HashMap<K, usize>, String> {
+    names
+        .iter()
+        .map(|(name, &key)| match lookup(name) {
+            Some(code) => Ok((code, key)),
+            None => Err(format!("key_map: unknown key {name}")),
+        })
+        .collect()
+}
+
+/// Problems with a `key_map` that still leave it usable: keypad keys bound to several
+/// keyboard keys, and keypad keys with no binding at all.
+pub fn key_map_warnings(names: &HashMap<String, usize>) -> Vec<String> {
+    let mut bound: [Vec<&str>; KEY_COUNT] = Default::default();
+    for (name, &key) in names {
+        if let Some(names) = bound.get_mut(key) {
+            names.push(name);
+        }
+    }
+
+    let mut warnings = Vec::new();
+    for (key, names) in bound.iter_mut().enumerate() {
+        match names.len() {
+            0 => warnings.push(format!("keypad key {key:X} is not bound to any key")),
+            1 => {}
+            _ => {
+                names.sort();
+                warnings.push(format!(
+                    "keypad key {key:X} is bound to several keys: {}",
+                    names.join(", ")
+                ));
+            }
+        }
+    }
+    warnings
+}
+
+/// Keypad state driven by controller events. Buttons are bound through a `gamepad_map`
+/// resolved with build_key_map, the left stick presses whatever the d-pad is bound to
+/// once it leaves the deadzone.
+pub struct Gamepad<B> {
+    buttons: HashMap<B, usize>,
+    held: HashSet<B>,
+    // keypad keys for stick up, down, left and right
+    stick_keys: [Option<usize>; 4],
+    stick: (i16, i16),
+    deadzone: i16,
+}
+
+impl<B: Eq + Hash + Copy> Gamepad<B> {
+    /// dpad holds the up, down, left and right buttons, whose bindings the stick shares.
+    pub fn new(buttons: HashMap<B, usize>, dpad: [B; 4], deadzone: u16) -> Self {
+        let stick_keys = dpad.map(|button| buttons.get(&button).copied());
+        Self {
+            buttons,
+            held: HashSet::new(),
+            stick_keys,
+            stick: (0, 0),
+            deadzone: deadzone.min(i16::MAX as u16) as i16,
+        }
+    }
+
+    pub fn button(&mut self, button: B, pressed: bool) {
+        if pressed {
+            self.held.insert(button);
+        } else {
+            self.held.remove(&button);
+        }
+    }
+
+    /// Left stick position on each axis runs from -32768 to 32767, negative is left or up.
+    pub fn stick_x(&mut self, x: i16) {
+        self.stick.0 = x;
+    }
+
+    pub fn stick_y(&mut self, y: i16) {
+        self.stick.1 = y;
+    }
+
+    /// Let go of everything, for when the controller is unplugged.
+    pub fn release_all(&mut self) {
+        self.held.clear();
+        self.stick = (0, 0);
+    }
+}
+
+impl<B: Eq + Hash + Copy> InputSource for Gamepad<B> {
+    fn poll(&mut self, keys: &mut [bool; KEY_COUNT]) {
+        keys.fill(false);
+        for button in &self.held {
+            if let Some(&key) = self.buttons.get(button) {
+                keys[key] = true;
+            }
+        }
+        let (x, y) = self.stick;
+        let directions = [
+            y < -self.deadzone,
+            y > self.deadzone,
+            x < -self.deadzone,
+            x > self.deadzone,
+        ];
+        for (held, key) in directions.into_iter().zip(self.stick_keys) {
+            if let (true, Some(key)) = (held, key) {
+                keys[key] = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VM;
+
+    fn names(pairs: &[(&str, usize)]) -> HashMap<String, usize> {
+        pairs
+            .iter()
+            .map(|&(name, key)| (name.to_string(), key))
+            .collect()
+    }
+
+    #[test]
+    fn default_layout_binds_every_key_once() {
+        let default = names(
+            &DEFAULT_KEY_NAMES
+                .iter()
+                .enumerate()
+                .map(|(key, &name)| (name, key))
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(default.len(), KEY_COUNT);
+        assert!(key_map_warnings(&default).is_empty());
+    }
+
+    #[test]
+    fn azerty_map_resolves_through_lookup() {
+        // same physical keys as the default, labelled for AZERTY
+        let azerty = names(&[
+            ("X", 0x0),
+            ("&", 0x1),
+            ("é", 0x2),
+            ("\"", 0x3),
+            ("A", 0x4),
+            ("Z", 0x5),
+            ("E", 0x6),
+            ("Q", 0x7),
+            ("S", 0x8),
+            ("D", 0x9),
+            ("W", 0xA),
+            ("C", 0xB),
+            ("'", 0xC),
+            ("R", 0xD),
+            ("F", 0xE),
+            ("V", 0xF),
+        ]);
+        let map = build_key_map(&azerty, |name| Some(name.to_string())).unwrap();
+        assert_eq!(map["A"], 0x4);
+        assert_eq!(map["Z"], 0x5);
+        assert_eq!(map["W"], 0xA);
+        assert!(key_map_warnings(&azerty).is_empty());
+    }
+
+    #[test]
+    fn unknown_names_are_an_error() {
+        let map = names(&[("Nope", 1)]);
+        assert!(build_key_map(&map, |_| None::<u32>).is_err());
+    }
+
+    fn default_gamepad() -> Gamepad<&'static str> {
+        let names = DEFAULT_GAMEPAD_NAMES
+            .iter()
+            .map(|&(name, key)| (name.to_string(), key))
+            .collect();
+        let buttons = build_key_map(&names, |name| {
+            DEFAULT_GAMEPAD_NAMES
+                .iter()
+                .find(|(button, _)| *button == name)
+                .map(|(button, _)| *button)
+        })
+        .unwrap();
+        Gamepad::new(buttons, ["dpup", "dpdown", "dpleft", "dpright"], 8000)
+    }
+
+    #[test]
+    fn gamepad_buttons_press_keypad_keys() {
+        let mut pad = default_gamepad();
+        let mut vm = VM::new_headless();
+
+        pad.button("a", true);
+        pad.button("dpleft", true);
+        vm.poll_input(&mut pad);
+        assert_eq!(vm.await_key(), Some(0x4));
+        let mut keys = [false; KEY_COUNT];
+        pad.poll(&mut keys);
+        assert!(keys[0x5] && keys[0x4]);
+        assert_eq!(keys.iter().filter(|&&held| held).count(), 2);
+
+        pad.button("dpleft", false);
+        vm.poll_input(&mut pad);
+        assert_eq!(vm.await_key(), Some(0x5));
+        pad.button("a", false);
+        vm.poll_input(&mut pad);
+        assert_eq!(vm.await_key(), None);
+    }
+
+    #[test]
+    fn gamepad_stick_respects_deadzone() {
+        let mut pad = default_gamepad();
+        let mut vm = VM::new_headless();
+
+        pad.stick_x(-8000);
+        pad.stick_y(7999);
+        vm.poll_input(&mut pad);
+        assert_eq!(vm.await_key(), None);
+
+        pad.stick_x(-20000);
+        vm.poll_input(&mut pad);
+        assert_eq!(vm.await_key(), Some(0x4));
+
+        pad.stick_x(0);
+        pad.stick_y(20000);
+        vm.poll_input(&mut pad);
+        assert_eq!(vm.await_key(), Some(0x8));
+
+        pad.button("start", true);
+        pad.release_all();
+        vm.poll_input(&mut pad);
+        assert_eq!(vm.await_key(), None);
+    }
+
+    #[test]
+    fn warns_about_duplicate_and_missing_bindings() {
+        let warnings = key_map_warnings(&names(&[("W", 5), ("Up", 5)]));
+        assert!(warnings.contains(&"keypad key 5 is bound to several keys: Up, W".to_string()));
+        assert!(warnings.contains(&"keypad key 0 is not bound to any key".to_string()));
+        assert_eq!(warnings.len(), KEY_COUNT);
+    }
+}
@@ -0,0 +1,53 @@
+//! CHIP-8, SUPER-CHIP and XO-CHIP interpreter.
+//!
+//! The VM draws through a [`Renderer`] and plays sound through an [`AudioSink`], so it can be
+//! embedded in any frontend or run headless. SDL2 implementations live in `sdl`, behind the
+//! default `sdl` feature.
+//! A minimal loop, running one 60Hz frame at a time:
+//!
+//! ```no_run
+//! use chip_8::{NullRenderer, VM};
+//!
+//! let rom = std::fs::read("game.ch8").unwrap();
+//! let mut vm = VM::builder().display(NullRenderer).build().unwrap();
+//! vm.load_rom(&rom).unwrap();
+//! loop {
+//!     // forward key presses with vm.set_key(key, pressed) or vm.poll_input(&mut input)
+//!     for _ in 0..10 {
+//!         vm.step().unwrap();
+//!     }
+//!     vm.tick_timers();
+//!     vm.draw().unwrap();
+//!     // wait for the next frame
+//! }
+//! ```
+//!
+//! [`VM::run_frame`] does the step and tick part of that loop in one call.
+
+pub mod catalog;
+pub mod config;
+pub mod disasm;
+pub mod display;
+pub mod error;
+pub mod frame_timer;
+pub mod gif;
+pub mod input;
+pub mod opcode;
+pub mod overlay;
+pub mod png;
+pub mod recording;
+pub mod rewind;
+pub mod rom;
+pub mod rpl;
+#[cfg(feature = "sdl")]
+pub mod sdl;
+pub mod speaker;
+pub mod terminal;
+pub mod vm;
+
+pub use display::{NullRenderer, Renderer, Screen};
+pub use error::Chip8Error;
+pub use input::InputSource;
+pub use opcode::Opcode;
+pub use speaker::{AudioSink, NullAudio};
+pub use vm::{CompatMode, FrameEnd, Quirks, VmBuilder, VM};
@@ -1,29 +1,480 @@
-mod display;
-mod speaker;
-mod vm;
-use std::fs;
+mod cli;
+mod debugger;
 
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use chip_8::{
+    catalog,
+    config::Config,
+    disasm,
+    display::{NullRenderer, Renderer, THEMES},
+    error::Chip8Error,
+    frame_timer::FrameTimer,
+    gif,
+    input::key_map_warnings,
+    recording::{Recording, Replay},
+    rewind::Rewind,
+    rom::{OpcodeFrequencyMap, Rom},
+    sdl::{self, SdlAudio, SdlInput, SdlRenderer},
+    speaker::PatternWave,
+    terminal::TerminalRenderer,
+    vm::{FrameEnd, TraceEntry, HISTORY_LEN, SCREEN_HEIGHT, SCREEN_WIDTH, VM},
+};
+use cli::{Args, RendererKind};
 use sdl2::{
-    audio::AudioSpecDesired, event::Event, keyboard::Keycode, pixels::Color, render::Canvas,
+    audio::{AudioDevice, AudioSpecDesired},
+    event::Event,
+    keyboard::{Keycode, Mod},
+    pixels::Color,
+    render::Canvas,
     video::Window,
 };
-use speaker::SquareWave;
-use vm::{SCREEN_HEIGHT, SCREEN_WIDTH, VM};
 
-fn setup(canvas: Canvas<Window>, audio_device: sdl2::audio::AudioDevice<SquareWave>) -> VM {
-    let file =
-        fs::read("./chip8-roms/games/Pong [Paul Vervalin, 1990].ch8").expect("Unable to read file");
-    let mut vm = VM::new(canvas, audio_device);
-    vm.load_rom(&file);
-    vm
+fn setup(
+    args: &Args,
+    config: &Config,
+    rom: &Rom,
+    canvas: Canvas<Window>,
+    audio_device: AudioDevice<PatternWave>,
+) -> Result<VM, String> {
+    let self_modifying = rom.detect_self_modifying_code();
+    if !self_modifying.is_empty() {
+        let addrs: Vec<String> = self_modifying
+            .iter()
+            .map(|a| format!("0x{a:03X}"))
+            .collect();
+        eprintln!(
+            "warning: possible self-modifying code (FX55/FX65 into ROM) at {}",
+            addrs.join(", ")
+        );
+    }
+
+    let mut renderer = SdlRenderer::new(canvas, config.scale as usize);
+    renderer.set_palette(palette(config));
+    renderer.set_phosphor_decay(config.phosphor_decay);
+    renderer.set_scanline_alpha(config.scanline_alpha);
+
+    let mut builder = VM::builder()
+        .display(renderer)
+        .audio(SdlAudio::new(audio_device))
+        .compat(config.variant)
+        .quirks(config.quirks.apply(config.variant.default_quirks()))
+        .skip_unknown_opcodes(!args.strict);
+    if let Some(seed) = args.seed {
+        builder = builder.random_seed(seed);
+    }
+    let mut vm = builder.build().map_err(|e| e.to_string())?;
+    vm.load_rom(rom.data()).map_err(|e| e.to_string())?;
+    Ok(vm)
 }
 
-const SCALE: usize = 15;
+// indexed by Screen::pixel_color, so CHIP-8 and SCHIP programs only use bg_color and fg_color
+fn palette(config: &Config) -> [[u8; 3]; 4] {
+    [
+        config.bg_color,
+        config.fg_color,
+        config.plane2_color,
+        config.both_color,
+    ]
+}
+
+// frames of history kept for rewinding, 10 seconds at 60Hz
+const REWIND_FRAMES: usize = 600;
+// instructions shown when execution stops on an error
+const CRASH_TRACE_LEN: usize = 32;
+
+// change in buzzer volume per [ or ] press
+const VOLUME_STEP: f32 = 0.05;
+
+// rounded to the step so repeated presses land back on the same levels
+fn adjust_volume(volume: f32, delta: f32) -> f32 {
+    (((volume + delta) / VOLUME_STEP).round() * VOLUME_STEP).clamp(0.0, 1.0)
+}
+
+// seconds since the epoch, names screenshots and recordings
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+// how long messages like "Loaded: Pong.ch8" are shown over the game
+const NOTICE_TIME: Duration = Duration::from_secs(2);
+
+// text drawn over the game: the notice, if any, above the debugger overlay
+fn overlay_text(notice: Option<&str>, debugger: Vec<String>) -> Vec<String> {
+    match notice {
+        Some(notice) if debugger.is_empty() => vec![notice.to_string()],
+        Some(notice) => [vec![notice.to_string(), String::new()], debugger].concat(),
+        None => debugger,
+    }
+}
+
+fn window_title(config: &Config, rom_path: &Path, paused: bool, fullscreen: bool) -> String {
+    let rom = rom_path.file_name().map_or_else(
+        || rom_path.display().to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    let paused = if paused { " [PAUSED]" } else { "" };
+    let fullscreen = if fullscreen { " [Fullscreen]" } else { "" };
+    format!(
+        "Crust-8 - {rom} - {} cycles/frame{paused}{fullscreen}",
+        config.cycles_per_frame
+    )
+}
+
+/// Replace the running program with the ROM at path, restarting the machine with the
+/// ROM's [[rom]] config. The window and colors stay as they are. On error nothing changes.
+fn switch_rom(
+    vm: &mut VM,
+    input: &mut SdlInput,
+    path: &Path,
+    global_config: &Config,
+    args: &Args,
+    config: &mut Config,
+) -> Result<(), String> {
+    let data = fs::read(path).map_err(|e| format!("unable to read {}: {e}", path.display()))?;
+    vm.load_rom(&data).map_err(|e| e.to_string())?;
+    warn_if_odd_size(path, &data);
+    vm.reset();
+    let mut rom_config = global_config.for_rom(&data);
+    args.apply(&mut rom_config);
+    if rom_config.variant != vm.compat() {
+        eprintln!(
+            "warning: {} is configured for {:?}, restart to switch variants",
+            path.display(),
+            rom_config.variant
+        );
+    }
+    vm.quirks = rom_config.quirks.apply(rom_config.variant.default_quirks());
+    warn_about_key_map(&rom_config);
+    if let Err(e) = input.set_key_map(&rom_config.key_map) {
+        eprintln!("warning: {e}, keeping the previous keys");
+    }
+    *config = Config {
+        scale: config.scale,
+        fg_color: config.fg_color,
+        bg_color: config.bg_color,
+        plane2_color: config.plane2_color,
+        both_color: config.both_color,
+        ..rom_config
+    };
+    Ok(())
+}
+
+// warns about each unknown opcode the VM skipped, once per address
+fn log_skipped_opcodes(vm: &mut VM, logged: &mut HashSet<u16>) {
+    for TraceEntry { pc, opcode } in vm.take_skipped_opcodes() {
+        if logged.insert(pc) {
+            let e = Chip8Error::UnknownOpcode {
+                instruction: opcode,
+                pc,
+            };
+            eprintln!("warning: {e}, skipped");
+        }
+    }
+}
+
+fn warn_about_key_map(config: &Config) {
+    // an empty map is the built-in layout, which binds every key once
+    if !config.key_map.is_empty() {
+        for warning in key_map_warnings(&config.key_map) {
+            eprintln!("warning: {warning}");
+        }
+    }
+}
+
+// instructions are 2 bytes, an odd size usually means a truncated or padded file
+fn warn_if_odd_size(path: &Path, data: &[u8]) {
+    if !data.len().is_multiple_of(2) {
+        eprintln!(
+            "warning: {} is {} bytes, an odd size for a CHIP-8 program",
+            path.display(),
+            data.len()
+        );
+    }
+}
+
+fn is_quit(event: &Event) -> bool {
+    match event {
+        Event::Quit { .. } => true,
+        Event::KeyDown {
+            keycode: Some(Keycode::Q),
+            keymod,
+            ..
+        } => keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD),
+        _ => false,
+    }
+}
+
+// Alt+Enter
+fn toggles_fullscreen(event: &Event) -> bool {
+    match event {
+        Event::KeyDown {
+            keycode: Some(Keycode::Return),
+            keymod,
+            repeat: false,
+            ..
+        } => keymod.intersects(Mod::LALTMOD | Mod::RALTMOD),
+        _ => false,
+    }
+}
+
+// Escape, or F1 like the other debugger keys
+fn toggles_pause(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::KeyDown {
+            keycode: Some(Keycode::Escape | Keycode::F1),
+            repeat: false,
+            ..
+        }
+    )
+}
+
+/// Writes the recording to path when dropped, so it is saved however the emulator exits.
+struct InputRecorder {
+    path: PathBuf,
+    recording: Recording,
+}
+
+impl Drop for InputRecorder {
+    fn drop(&mut self) {
+        match fs::write(&self.path, self.recording.to_bytes()) {
+            Ok(()) => eprintln!(
+                "recorded {} input events to {}",
+                self.recording.events.len(),
+                self.path.display()
+            ),
+            Err(e) => eprintln!("unable to write {}: {e}", self.path.display()),
+        }
+    }
+}
+
+/// Frames captured for a GIF, written out by save.
+struct GifRecording {
+    path: PathBuf,
+    frames: gif::Recorder,
+    max_frames: usize,
+}
+
+impl GifRecording {
+    fn new(path: PathBuf, config: &Config, max_frames: usize) -> Self {
+        Self {
+            path,
+            frames: gif::Recorder::new(
+                palette(config),
+                config.scale as usize,
+                config.timer_rate_hz,
+            ),
+            max_frames,
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.frames.len() >= self.max_frames
+    }
+
+    fn save(self) {
+        let len = self.frames.len();
+        match fs::write(&self.path, self.frames.finish()) {
+            Ok(()) => eprintln!("saved {len} frames to {}", self.path.display()),
+            Err(e) => eprintln!("unable to write {}: {e}", self.path.display()),
+        }
+    }
+}
+
+fn run_catalog(path: &Path) -> ! {
+    let results = catalog::run_catalog(path);
+    for result in &results {
+        match &result.outcome {
+            Ok(()) => println!("PASS {}", result.rom.display()),
+            Err(e) => println!("FAIL {}: {e}", result.rom.display()),
+        }
+    }
+    let passed = results.iter().filter(|r| r.passed()).count();
+    println!("{passed}/{} passed", results.len());
+    std::process::exit(if passed == results.len() { 0 } else { 1 });
+}
+
+/// Run steps instructions without a window and print the screen's pixel hash.
+/// Exits with 1 if the program hits an error or a pixel doesn't match --expect-pixel.
+fn run_headless(
+    args: &Args,
+    config: &Config,
+    rom: &Rom,
+    steps: u64,
+    mut replay: Option<Replay>,
+) -> ! {
+    let mut builder = VM::builder()
+        .display(NullRenderer)
+        .compat(config.variant)
+        .quirks(config.quirks.apply(config.variant.default_quirks()))
+        .skip_unknown_opcodes(!args.strict);
+    if let Some(seed) = args.seed {
+        builder = builder.random_seed(seed);
+    }
+    let outcome = builder.build().and_then(|mut vm| {
+        vm.load_rom(rom.data())?;
+        let cycles_per_frame = config.cycles_per_frame as u64;
+        let mut skipped = HashSet::new();
+        for step in 0..steps {
+            if let Some(replay) = &mut replay {
+                replay.advance(vm.cycle_count());
+                vm.poll_input(replay);
+            }
+            if let Err(e) = vm.step() {
+                eprintln!("error after {step} steps: {e}\n{vm}");
+                return Err(e);
+            }
+            log_skipped_opcodes(&mut vm, &mut skipped);
+            if (step + 1) % cycles_per_frame == 0 {
+                vm.tick_timers();
+            }
+        }
+        Ok(vm)
+    });
+    match outcome {
+        Ok(vm) => {
+            println!("{:016x}", vm.display.pixel_hash());
+            let screen = &vm.display;
+            let mut passed = true;
+            for &(x, y, lit) in &args.expect_pixels {
+                if x >= screen.width() || y >= screen.height() {
+                    eprintln!(
+                        "pixel {x},{y} is off the {}x{} screen",
+                        screen.width(),
+                        screen.height()
+                    );
+                    passed = false;
+                } else if (screen.pixel_color(x, y) != 0) != lit {
+                    eprintln!("pixel {x},{y} expected {lit}");
+                    passed = false;
+                }
+            }
+            std::process::exit(if passed { 0 } else { 1 });
+        }
+        Err(_) => std::process::exit(1),
+    }
+}
+
+/// Run the ROM drawing to the terminal until Ctrl+C, keys only come from a replay.
+fn run_terminal(args: &Args, config: &Config, rom: &Rom, mut replay: Option<Replay>) -> ! {
+    let mut renderer = TerminalRenderer::new(std::io::stdout());
+    renderer.set_palette(palette(config));
+    let mut builder = VM::builder()
+        .display(renderer)
+        .compat(config.variant)
+        .quirks(config.quirks.apply(config.variant.default_quirks()))
+        .skip_unknown_opcodes(!args.strict);
+    if let Some(seed) = args.seed {
+        builder = builder.random_seed(seed);
+    }
+    let run = || -> Result<(), String> {
+        let mut vm = builder.build().map_err(|e| e.to_string())?;
+        vm.load_rom(rom.data()).map_err(|e| e.to_string())?;
+        let mut frame_timer = FrameTimer::new(config.timer_rate_hz);
+        let mut skipped = HashSet::new();
+        loop {
+            if let Some(replay) = &mut replay {
+                replay.advance(vm.cycle_count());
+                vm.poll_input(replay);
+            }
+            if let Err(e) = vm.run_frame(config.cycles_per_frame) {
+                return Err(format!("{e}\n{vm}"));
+            }
+            log_skipped_opcodes(&mut vm, &mut skipped);
+            vm.draw()?;
+            frame_timer.wait();
+        }
+    };
+    if let Err(e) = run() {
+        eprintln!("error: {e}");
+    }
+    std::process::exit(1);
+}
 
 fn main() -> Result<(), String> {
+    let mut args = Args::parse()?;
+    if args.list_controllers {
+        let sdl_context = sdl2::init()?;
+        let controllers = sdl::list_controllers(&sdl_context.game_controller()?)?;
+        if controllers.is_empty() {
+            println!("no controllers found");
+        }
+        for controller in controllers {
+            println!("{controller}");
+        }
+        return Ok(());
+    }
+    if let Some(catalog) = &args.catalog {
+        run_catalog(catalog);
+    }
+    if args.analyze_rom || args.disassemble {
+        let file = fs::read(&args.rom)
+            .map_err(|e| format!("unable to read {}: {e}", args.rom.display()))?;
+        if args.analyze_rom {
+            println!("{}", OpcodeFrequencyMap::from_rom(&file));
+        }
+        if args.disassemble {
+            for (addr, mnemonic) in disasm::disassemble_rom(&file) {
+                println!("0x{addr:03X}: {mnemonic}");
+            }
+        }
+        return Ok(());
+    }
+    if let Some((start, len)) = args.dump_memory {
+        let file = fs::read(&args.rom)
+            .map_err(|e| format!("unable to read {}: {e}", args.rom.display()))?;
+        let mut vm = VM::new_headless();
+        vm.load_rom(&file).map_err(|e| e.to_string())?;
+        println!("{}", vm.dump_memory(start, len));
+        return Ok(());
+    }
+
+    let rom = Rom::new(
+        fs::read(&args.rom).map_err(|e| format!("unable to read {}: {e}", args.rom.display()))?,
+    );
+    warn_if_odd_size(&args.rom, rom.data());
+    let global_config = Config::load().map_err(|e| e.to_string())?;
+    if let Some(entry) = global_config.rom_config(rom.data()) {
+        let name = entry.name.as_deref().unwrap_or(&entry.sha256);
+        eprintln!("Applying config for {name}");
+    }
+    let mut config = global_config.for_rom(rom.data());
+    let mut replay = None;
+    if let Some(path) = &args.replay_input {
+        let file = fs::read(path).map_err(|e| format!("unable to read {}: {e}", path.display()))?;
+        let recording = Recording::from_bytes(&file).map_err(|e| e.to_string())?;
+        if !recording.matches_rom(rom.data()) {
+            eprintln!(
+                "warning: {} was recorded with a different ROM",
+                path.display()
+            );
+        }
+        // the recorded speed and seed, so events land on the same frames and CXKK rolls the
+        // same numbers, --ipf and --seed still override them
+        config.cycles_per_frame = recording.cycles_per_frame;
+        args.seed = args.seed.or(Some(recording.seed));
+        replay = Some(Replay::new(recording));
+    }
+    args.apply(&mut config);
+    if let Some(steps) = args.headless_steps {
+        run_headless(&args, &config, &rom, steps, replay);
+    }
+    if args.renderer == RendererKind::Terminal {
+        run_terminal(&args, &config, &rom, replay);
+    }
+
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
     let audio_subsystem = sdl_context.audio()?;
+    let controller_subsystem = sdl_context.game_controller()?;
 
     let desired_spec = AudioSpecDesired {
         freq: Some(44100),
@@ -32,14 +483,21 @@ fn main() -> Result<(), String> {
     };
 
     let audio_device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
-        SquareWave::new(440.0 / spec.freq as f32, 0.0, 0.25)
+        let mut wave = PatternWave::new(
+            spec.freq,
+            config.audio_freq / spec.freq as f32,
+            0.0,
+            config.audio_volume,
+        );
+        wave.set_waveform(config.audio_waveform);
+        wave
     })?;
 
     let window = video_subsystem
         .window(
             "Crust-8",
-            (SCREEN_WIDTH * SCALE) as u32,
-            (SCREEN_HEIGHT * SCALE) as u32,
+            SCREEN_WIDTH as u32 * config.scale,
+            SCREEN_HEIGHT as u32 * config.scale,
         )
         .position_centered()
         .opengl()
@@ -51,73 +509,473 @@ fn main() -> Result<(), String> {
     canvas.set_draw_color(Color::RGB(0, 0, 0));
     canvas.clear();
     canvas.present();
-    let mut event_pump = sdl_context.event_pump()?;
+    let event_pump = sdl_context.event_pump()?;
+    warn_about_key_map(&config);
+    let mut input = SdlInput::with_key_map(event_pump, &config.key_map)?.with_gamepad(
+        controller_subsystem,
+        &config.gamepad_map,
+        config.gamepad_deadzone,
+    )?;
 
-    let mut steps = 0;
-    let mut vm = setup(canvas, audio_device);
+    let mut vm = setup(&args, &config, &rom, canvas, audio_device)?;
+    let mut frame_timer = FrameTimer::new(config.timer_rate_hz);
+    // debugger, Escape or F1 toggles pause and F2 (or F10) steps a single instruction while paused
+    let mut paused = args.start_paused;
+    // Ctrl+M silences the buzzer, [ and ] turn it down and up
+    let mut muted = false;
+    // ` shows the registers and the RAM around PC over the game
+    let mut show_overlay = false;
+    let mut overlay = Vec::new();
+    let mut volume = config.audio_volume;
+    // Alt+Enter switches to desktop fullscreen and back
+    let mut fullscreen = false;
+    // refreshed whenever the speed, ROM or pause state changes
+    let mut title = String::new();
+    // shown over the game until NOTICE_TIME has passed
+    let mut notice: Option<(String, Instant)> = None;
+    // addresses of unknown opcodes already warned about
+    let mut skipped_opcodes = HashSet::new();
+    // dropping a file on the window switches to it, F3 re-reads it from disk
+    let mut rom_path = args.rom.clone();
+    for &addr in &args.breakpoints {
+        vm.add_breakpoint(addr);
+    }
+    // one snapshot per frame, holding Backspace plays them back in reverse
+    let mut rewind = Rewind::new(REWIND_FRAMES);
+    let mut rewinding = false;
+    // F6 saves a snapshot next to the ROM, F7 restores it
+    let mut state_path = rom_path.with_extension("ch8s");
+    // breakpoint commands typed into the terminal, see debugger::handle_command
+    let commands = debugger::spawn_command_reader();
+    // F8 steps through display::THEMES, starting after the one matching the current colors
+    let mut theme = THEMES
+        .iter()
+        .position(|theme| theme.fg == config.fg_color && theme.bg == config.bg_color);
+    // F9 starts recording frames, pressing it again writes them to a GIF
+    let mut gif_recorder = args
+        .record_gif
+        .clone()
+        .map(|path| GifRecording::new(path, &config, args.gif_max_frames));
+    let mut recorder = args.record_input.clone().map(|path| InputRecorder {
+        path,
+        recording: Recording::new(rom.data(), config.cycles_per_frame, vm.seed()),
+    });
 
     'running: loop {
-        for event in event_pump.poll_iter() {
+        let mut step_requested = false;
+        for event in input.poll_events() {
             match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
+                event if is_quit(&event) => break 'running,
+                event if toggles_fullscreen(&event) => {
+                    match vm.renderer_mut().set_fullscreen(!fullscreen) {
+                        Ok(()) => fullscreen = !fullscreen,
+                        Err(e) => eprintln!("unable to switch fullscreen: {e}"),
+                    }
+                    vm.display.mark_dirty();
+                }
+                event if toggles_pause(&event) => {
+                    paused = !paused;
+                    eprintln!("{}", if paused { "paused" } else { "resumed" });
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::T),
+                    keymod,
                     ..
-                } => break 'running,
+                } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                    vm.freeze_timer(!vm.timers_frozen());
+                    let state = if vm.timers_frozen() {
+                        "frozen"
+                    } else {
+                        "running"
+                    };
+                    eprintln!("timers {state}");
+                }
                 Event::KeyDown {
-                    keycode: Some(keycode),
+                    keycode: Some(Keycode::M),
+                    keymod,
+                    ..
+                } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                    muted = !muted;
+                    vm.audio_mut().set_muted(muted);
+                    eprintln!("{}", if muted { "muted" } else { "unmuted" });
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode @ (Keycode::LeftBracket | Keycode::RightBracket)),
+                    ..
+                } => {
+                    let delta = if keycode == Keycode::LeftBracket {
+                        -VOLUME_STEP
+                    } else {
+                        VOLUME_STEP
+                    };
+                    volume = adjust_volume(volume, delta);
+                    vm.audio_mut().set_volume(volume);
+                    eprintln!("volume {volume:.2}{}", if muted { " (muted)" } else { "" });
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2 | Keycode::F10),
+                    ..
+                } if paused => step_requested = true,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
                     ..
                 } => {
-                    if let Some(k) = keycode_to_input(keycode) {
-                        vm.set_key(k, true);
+                    if let Err(e) = switch_rom(
+                        &mut vm,
+                        &mut input,
+                        &rom_path,
+                        &global_config,
+                        &args,
+                        &mut config,
+                    ) {
+                        eprintln!("{e}, restarting the loaded copy");
+                        vm.reset();
                     }
+                    rewind.clear();
+                    eprintln!("reset");
                 }
-
+                Event::DropFile { filename, .. } => {
+                    let path = PathBuf::from(filename);
+                    let message = match switch_rom(
+                        &mut vm,
+                        &mut input,
+                        &path,
+                        &global_config,
+                        &args,
+                        &mut config,
+                    ) {
+                        Ok(()) => {
+                            rewind.clear();
+                            // a recording only replays against the ROM it was made with
+                            if recorder.take().is_some() {
+                                eprintln!("ROM changed, input recording stopped");
+                            }
+                            state_path = path.with_extension("ch8s");
+                            rom_path = path;
+                            let name = rom_path.file_name().unwrap_or_default();
+                            format!("Loaded: {}", name.to_string_lossy())
+                        }
+                        Err(e) => format!("Error: {e}"),
+                    };
+                    eprintln!("{message}");
+                    notice = Some((message, Instant::now()));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F4),
+                    ..
+                } => eprintln!("{vm}"),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => eprintln!("{}", vm.dump_memory(vm.i(), 256)),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    ..
+                } => match fs::write(&state_path, vm.save_state()) {
+                    Ok(()) => eprintln!("saved state to {}", state_path.display()),
+                    Err(e) => eprintln!("unable to write {}: {e}", state_path.display()),
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    ..
+                } => match fs::read(&state_path) {
+                    Ok(state) => match vm.load_state(&state) {
+                        Ok(()) => {
+                            // history from before the load no longer leads up to this state
+                            rewind.clear();
+                            eprintln!("loaded state from {}", state_path.display());
+                        }
+                        Err(e) => eprintln!("{}: {e}", state_path.display()),
+                    },
+                    Err(e) => eprintln!("unable to read {}: {e}", state_path.display()),
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backquote),
+                    repeat: false,
+                    ..
+                } => show_overlay = !show_overlay,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = true,
                 Event::KeyUp {
-                    keycode: Some(keycode),
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = false,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    ..
+                } => debugger::print_trace(&vm, HISTORY_LEN),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    ..
+                } => {
+                    let next = theme.map_or(0, |idx| (idx + 1) % THEMES.len());
+                    theme = Some(next);
+                    config.fg_color = THEMES[next].fg;
+                    config.bg_color = THEMES[next].bg;
+                    vm.renderer_mut().set_palette(palette(&config));
+                    vm.display.mark_dirty();
+                    eprintln!("theme {}", THEMES[next].name);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => match gif_recorder.take() {
+                    Some(recording) => recording.save(),
+                    None => {
+                        let path = PathBuf::from(format!("crust8-{}.gif", timestamp()));
+                        gif_recorder = Some(GifRecording::new(path, &config, args.gif_max_frames));
+                        eprintln!("recording GIF, F9 again to stop");
+                    }
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
                     ..
                 } => {
-                    if let Some(k) = keycode_to_input(keycode) {
-                        vm.set_key(k, false);
+                    let path = format!("crust8-{}.png", timestamp());
+                    match vm
+                        .display
+                        .screenshot(&path, &palette(&config), config.scale as usize)
+                    {
+                        Ok(()) => eprintln!("saved screenshot to {path}"),
+                        Err(e) => eprintln!("unable to write {path}: {e}"),
                     }
                 }
+                Event::KeyDown {
+                    keycode: Some(Keycode::PageUp),
+                    ..
+                } => {
+                    config.cycles_per_frame += 1;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::PageDown),
+                    ..
+                } => {
+                    config.cycles_per_frame = (config.cycles_per_frame - 1).max(1);
+                }
                 _ => {}
             }
         }
 
-        vm.decode();
-        // Timer: 1/clockspeed
-        if steps == 1 / (1000 / 60) {
-            vm.tick_timers();
-            vm.display.draw()?;
-            steps = 0;
+        while let Ok(command) = commands.try_recv() {
+            debugger::handle_command(&mut vm, &command);
+        }
+
+        let before = vm.keys();
+        match &mut replay {
+            Some(replay) => {
+                replay.advance(vm.cycle_count());
+                if args.replay_and_live {
+                    vm.poll_input(&mut input);
+                    for (key, held) in replay.keys().into_iter().enumerate() {
+                        if held {
+                            vm.set_key(key, true);
+                        }
+                    }
+                } else {
+                    vm.poll_input(replay);
+                }
+            }
+            None => vm.poll_input(&mut input),
+        }
+        if let Some(recorder) = &mut recorder {
+            recorder
+                .recording
+                .record_changes(vm.cycle_count(), &before, &vm.keys());
+        }
+        if rewinding {
+            if let Some(state) = rewind.pop() {
+                vm.load_state(&state)
+                    .expect("snapshots taken by save_state always load");
+            }
+        } else if !paused {
+            rewind.push(vm.save_state());
+            match vm.run_frame(config.cycles_per_frame) {
+                Ok(FrameEnd::Complete) => {}
+                Ok(FrameEnd::Breakpoint(addr)) => {
+                    debugger::print_breakpoint(&vm, addr);
+                    paused = true;
+                }
+                Err(e) if args.strict => {
+                    debugger::print_trace(&vm, CRASH_TRACE_LEN);
+                    return Err(format!("{e}\n{vm}"));
+                }
+                // halt in the debugger so the state can still be inspected
+                Err(e) => {
+                    eprintln!("error: {e}\n{vm}");
+                    debugger::print_trace(&vm, CRASH_TRACE_LEN);
+                    paused = true;
+                }
+            }
+        } else if step_requested {
+            if let Err(e) = debugger::step(&mut vm) {
+                eprintln!("error: {e}\n{vm}");
+            }
+        }
+        log_skipped_opcodes(&mut vm, &mut skipped_opcodes);
+        if config.phosphor_decay > 0.0 {
+            // fading pixels change every frame even when the program draws nothing
+            vm.display.mark_dirty();
+        }
+        if notice
+            .as_ref()
+            .is_some_and(|(_, shown)| shown.elapsed() >= NOTICE_TIME)
+        {
+            notice = None;
+        }
+        let new_overlay = overlay_text(
+            notice.as_ref().map(|(message, _)| message.as_str()),
+            if show_overlay {
+                debugger::overlay_lines(&vm)
+            } else {
+                Vec::new()
+            },
+        );
+        if new_overlay != overlay {
+            vm.renderer_mut().set_overlay(&new_overlay);
+            vm.display.mark_dirty();
+            overlay = new_overlay;
+        }
+        vm.draw()?;
+        let new_title = window_title(&config, &rom_path, paused, fullscreen);
+        if new_title != title {
+            vm.renderer_mut().set_title(&new_title);
+            title = new_title;
+        }
+        if let Some(recording) = &mut gif_recorder {
+            recording.frames.capture(&vm.display);
+        }
+        if let Some(recording) = gif_recorder.take_if(|recording| recording.is_full()) {
+            eprintln!("GIF reached {} frames", recording.max_frames);
+            recording.save();
         }
 
-        // TODO(aalhendi): Tickrate
-        std::thread::sleep(std::time::Duration::from_millis(2));
+        frame_timer.wait();
     }
 
+    if let Some(recording) = gif_recorder {
+        recording.save();
+    }
     Ok(())
 }
 
-fn keycode_to_input(key: Keycode) -> Option<usize> {
-    Some(match key {
-        Keycode::Num1 => 0x1,
-        Keycode::Num2 => 0x2,
-        Keycode::Num3 => 0x3,
-        Keycode::Num4 => 0xC,
-        Keycode::Q => 0x4,
-        Keycode::W => 0x5,
-        Keycode::E => 0x6,
-        Keycode::R => 0xD,
-        Keycode::A => 0x7,
-        Keycode::S => 0x8,
-        Keycode::D => 0x9,
-        Keycode::F => 0xE,
-        Keycode::Z => 0xA,
-        Keycode::X => 0x0,
-        Keycode::C => 0xB,
-        Keycode::V => 0xF,
-        _ => return None,
-    })
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_down(keycode: Keycode, keymod: Mod) -> Event {
+        Event::KeyDown {
+            timestamp: 0,
+            window_id: 0,
+            keycode: Some(keycode),
+            scancode: None,
+            keymod,
+            repeat: false,
+        }
+    }
+
+    #[test]
+    fn escape_toggles_pause_and_ctrl_q_quits() {
+        let escape = key_down(Keycode::Escape, Mod::NOMOD);
+        let mut paused = false;
+        for expected in [true, false, true] {
+            if toggles_pause(&escape) {
+                paused = !paused;
+            }
+            assert_eq!(paused, expected);
+        }
+        assert!(!is_quit(&escape));
+
+        assert!(is_quit(&key_down(Keycode::Q, Mod::LCTRLMOD)));
+        assert!(is_quit(&key_down(Keycode::Q, Mod::RCTRLMOD)));
+        assert!(!is_quit(&key_down(Keycode::Q, Mod::NOMOD)));
+        assert!(is_quit(&Event::Quit { timestamp: 0 }));
+        assert!(!toggles_pause(&Event::Quit { timestamp: 0 }));
+    }
+
+    #[test]
+    fn alt_enter_toggles_fullscreen() {
+        let mut fullscreen = false;
+        let alt_enter = key_down(Keycode::Return, Mod::LALTMOD);
+        for expected in [true, false] {
+            if toggles_fullscreen(&alt_enter) {
+                fullscreen = !fullscreen;
+            }
+            assert_eq!(fullscreen, expected);
+        }
+        assert!(toggles_fullscreen(&key_down(Keycode::Return, Mod::RALTMOD)));
+        assert!(!toggles_fullscreen(&key_down(Keycode::Return, Mod::NOMOD)));
+        assert!(!toggles_pause(&alt_enter));
+
+        // the renderers without a window refuse, so the flag wouldn't flip
+        assert!(NullRenderer.set_fullscreen(true).is_err());
+    }
+
+    #[test]
+    fn default_palette_is_unchanged() {
+        assert_eq!(
+            palette(&Config::default()),
+            chip_8::display::DEFAULT_PALETTE
+        );
+    }
+
+    #[test]
+    fn volume_steps_are_clamped() {
+        assert_eq!(adjust_volume(0.25, VOLUME_STEP), 0.3);
+        assert_eq!(adjust_volume(0.25, -VOLUME_STEP), 0.2);
+        assert_eq!(adjust_volume(0.02, -VOLUME_STEP), 0.0);
+        assert_eq!(adjust_volume(0.0, -VOLUME_STEP), 0.0);
+        assert_eq!(adjust_volume(0.98, VOLUME_STEP), 1.0);
+        assert_eq!(adjust_volume(1.0, VOLUME_STEP), 1.0);
+    }
+
+    #[test]
+    fn overlay_marks_the_pc_row() {
+        let mut vm = VM::new_headless();
+        vm.load_rom(&[0x60, 0x2A, 0x12, 0x02]).unwrap();
+        vm.step().unwrap();
+        let lines = debugger::overlay_lines(&vm);
+        assert_eq!(lines[0], "PC 0202 I 0000 SP 0");
+        assert_eq!(lines[2], "V0 2A 00 00 00 00 00 00 00");
+        assert!(lines.contains(&">0200 60 2A 12 02 00 00 00 00".to_string()));
+
+        // the window stays inside RAM at the top
+        vm.set_pc(0xFFE).unwrap();
+        let lines = debugger::overlay_lines(&vm);
+        assert!(lines.last().unwrap().starts_with(">0FF8"));
+    }
+
+    #[test]
+    fn notice_goes_above_the_debugger_overlay() {
+        let debugger = vec!["PC 0200".to_string()];
+        assert_eq!(overlay_text(None, Vec::new()), Vec::<String>::new());
+        assert_eq!(overlay_text(None, debugger.clone()), debugger);
+        assert_eq!(
+            overlay_text(Some("Loaded: Pong.ch8"), Vec::new()),
+            ["Loaded: Pong.ch8"]
+        );
+        assert_eq!(
+            overlay_text(Some("Loaded: Pong.ch8"), debugger),
+            ["Loaded: Pong.ch8", "", "PC 0200"]
+        );
+    }
+
+    #[test]
+    fn title_shows_pause() {
+        let config = Config::default();
+        let rom = Path::new("roms/Pong.ch8");
+        assert_eq!(
+            window_title(&config, rom, false, false),
+            "Crust-8 - Pong.ch8 - 10 cycles/frame"
+        );
+        assert_eq!(
+            window_title(&config, rom, true, false),
+            "Crust-8 - Pong.ch8 - 10 cycles/frame [PAUSED]"
+        );
+        assert_eq!(
+            window_title(&config, rom, true, true),
+            "Crust-8 - Pong.ch8 - 10 cycles/frame [PAUSED] [Fullscreen]"
+        );
+    }
 }
@@ -0,0 +1,236 @@
+/// A decoded CHIP-8 instruction, variants mirror the VM handler they execute.
+/// x and y are register indices, kk a byte, nnn a 12-bit address and n a nibble.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Opcode {
+    Cls,
+    Ret,
+    Scd { n: u8 },
+    Scr,
+    Scl,
+    Low,
+    High,
+    SelPlane { n: u8 },
+    Sys { nnn: u16 },
+    Jp { nnn: u16 },
+    Call { nnn: u16 },
+    SeVxKk { x: u8, kk: u8 },
+    SneVxKk { x: u8, kk: u8 },
+    SeVxVy { x: u8, y: u8 },
+    LdIRange { x: u8, y: u8 },
+    LdRangeI { x: u8, y: u8 },
+    LdVxKk { x: u8, kk: u8 },
+    AddVxKk { x: u8, kk: u8 },
+    LdVxVy { x: u8, y: u8 },
+    OrVxVy { x: u8, y: u8 },
+    AndVxVy { x: u8, y: u8 },
+    XorVxVy { x: u8, y: u8 },
+    AddVxVy { x: u8, y: u8 },
+    SubVxVy { x: u8, y: u8 },
+    ShrVxVy { x: u8, y: u8 },
+    SubnVxVy { x: u8, y: u8 },
+    ShlVxVy { x: u8, y: u8 },
+    SneVxVy { x: u8, y: u8 },
+    LdIAddr { nnn: u16 },
+    // F000 NNNN, decoded by the VM since the address is in the following word
+    LdILong { nnnn: u16 },
+    JpV0Addr { nnn: u16 },
+    RndVxKk { x: u8, kk: u8 },
+    DrwVxVyN { x: u8, y: u8, n: u8 },
+    SkpVx { x: u8 },
+    SknpVx { x: u8 },
+    LdVxDt { x: u8 },
+    LdVxK { x: u8 },
+    LdDtVx { x: u8 },
+    LdStVx { x: u8 },
+    AddIVx { x: u8 },
+    LdFVx { x: u8 },
+    LdLfVx { x: u8 },
+    LdBVx { x: u8 },
+    LdAudioI,
+    LdPitchVx { x: u8 },
+    LdIVx { x: u8 },
+    LdVxI { x: u8 },
+    LdRVx { x: u8 },
+    LdVxR { x: u8 },
+}
+
+/// Coarse grouping of opcodes by what they do, used for static ROM analysis.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OpcodeClass {
+    Graphics,
+    Flow,
+    Conditional,
+    Arithmetic,
+    Memory,
+    Timer,
+    Input,
+    Random,
+    Sound,
+    System,
+}
+
+impl Opcode {
+    /// Decode a raw 16-bit instruction, None if it isn't a known opcode.
+    pub fn decode(instruction: u16) -> Option<Self> {
+        let n1 = instruction >> 12; // & 0x000F not needed, shift operation alone aligns the target bits to the rightmost position
+        let nnn = instruction & 0x0FFF;
+        let x = ((instruction >> 8) & 0x000F) as u8;
+        let y = ((instruction >> 4) & 0x000F) as u8;
+        let n = (instruction & 0x000F) as u8; // No shift needed, already aligned
+        let kk = (instruction & 0x00FF) as u8;
+        Some(match n1 {
+            0x0 if nnn == 0x00E0 => Self::Cls,
+            0x0 if nnn == 0x00EE => Self::Ret,
+            0x0 if nnn & 0x0FF0 == 0x00C0 => Self::Scd { n },
+            0x0 if nnn == 0x00FB => Self::Scr,
+            0x0 if nnn == 0x00FC => Self::Scl,
+            0x0 if nnn == 0x00FE => Self::Low,
+            0x0 if nnn == 0x00FF => Self::High,
+            0x0 => Self::Sys { nnn },
+            0x1 => Self::Jp { nnn },
+            0x2 => Self::Call { nnn },
+            0x3 => Self::SeVxKk { x, kk },
+            0x4 => Self::SneVxKk { x, kk },
+            0x5 if n == 0x0 => Self::SeVxVy { x, y }, // TODO(aalhendi): Check if last n check needed
+            0x5 if n == 0x2 => Self::LdIRange { x, y },
+            0x5 if n == 0x3 => Self::LdRangeI { x, y },
+            0x6 => Self::LdVxKk { x, kk },
+            0x7 => Self::AddVxKk { x, kk },
+            0x8 if n == 0x0 => Self::LdVxVy { x, y },
+            0x8 if n == 0x1 => Self::OrVxVy { x, y },
+            0x8 if n == 0x2 => Self::AndVxVy { x, y },
+            0x8 if n == 0x3 => Self::XorVxVy { x, y },
+            0x8 if n == 0x4 => Self::AddVxVy { x, y },
+            0x8 if n == 0x5 => Self::SubVxVy { x, y },
+            0x8 if n == 0x6 => Self::ShrVxVy { x, y },
+            0x8 if n == 0x7 => Self::SubnVxVy { x, y },
+            0x8 if n == 0xE => Self::ShlVxVy { x, y },
+            0x9 if n == 0x0 => Self::SneVxVy { x, y }, // TODO(aalhendi): Check if last n check needed
+            0xA => Self::LdIAddr { nnn },
+            0xB => Self::JpV0Addr { nnn },
+            0xC => Self::RndVxKk { x, kk },
+            0xD => Self::DrwVxVyN { x, y, n },
+            0xE if kk == 0x9E => Self::SkpVx { x },
+            0xE if kk == 0xA1 => Self::SknpVx { x },
+            0xF if kk == 0x01 => Self::SelPlane { n: x },
+            0xF if x == 0 && kk == 0x02 => Self::LdAudioI,
+            0xF if kk == 0x07 => Self::LdVxDt { x },
+            0xF if kk == 0x0A => Self::LdVxK { x },
+            0xF if kk == 0x15 => Self::LdDtVx { x },
+            0xF if kk == 0x18 => Self::LdStVx { x },
+            0xF if kk == 0x1E => Self::AddIVx { x },
+            0xF if kk == 0x29 => Self::LdFVx { x },
+            0xF if kk == 0x30 => Self::LdLfVx { x },
+            0xF if kk == 0x33 => Self::LdBVx { x },
+            0xF if kk == 0x3A => Self::LdPitchVx { x },
+            0xF if kk == 0x55 => Self::LdIVx { x },
+            0xF if kk == 0x65 => Self::LdVxI { x },
+            0xF if kk == 0x75 => Self::LdRVx { x },
+            0xF if kk == 0x85 => Self::LdVxR { x },
+            _ => return None,
+        })
+    }
+
+    /// Instruction pattern as written in opcode tables, e.g. 8XY4.
+    pub fn pattern(&self) -> &'static str {
+        match self {
+            Self::Cls => "00E0",
+            Self::Ret => "00EE",
+            Self::Scd { .. } => "00CN",
+            Self::Scr => "00FB",
+            Self::Scl => "00FC",
+            Self::Low => "00FE",
+            Self::High => "00FF",
+            Self::SelPlane { .. } => "FN01",
+            Self::Sys { .. } => "0NNN",
+            Self::Jp { .. } => "1NNN",
+            Self::Call { .. } => "2NNN",
+            Self::SeVxKk { .. } => "3XKK",
+            Self::SneVxKk { .. } => "4XKK",
+            Self::SeVxVy { .. } => "5XY0",
+            Self::LdIRange { .. } => "5XY2",
+            Self::LdRangeI { .. } => "5XY3",
+            Self::LdVxKk { .. } => "6XKK",
+            Self::AddVxKk { .. } => "7XKK",
+            Self::LdVxVy { .. } => "8XY0",
+            Self::OrVxVy { .. } => "8XY1",
+            Self::AndVxVy { .. } => "8XY2",
+            Self::XorVxVy { .. } => "8XY3",
+            Self::AddVxVy { .. } => "8XY4",
+            Self::SubVxVy { .. } => "8XY5",
+            Self::ShrVxVy { .. } => "8XY6",
+            Self::SubnVxVy { .. } => "8XY7",
+            Self::ShlVxVy { .. } => "8XYE",
+            Self::SneVxVy { .. } => "9XY0",
+            Self::LdIAddr { .. } => "ANNN",
+            Self::LdILong { .. } => "F000",
+            Self::JpV0Addr { .. } => "BNNN",
+            Self::RndVxKk { .. } => "CXKK",
+            Self::DrwVxVyN { .. } => "DXYN",
+            Self::SkpVx { .. } => "EX9E",
+            Self::SknpVx { .. } => "EXA1",
+            Self::LdVxDt { .. } => "FX07",
+            Self::LdVxK { .. } => "FX0A",
+            Self::LdDtVx { .. } => "FX15",
+            Self::LdStVx { .. } => "FX18",
+            Self::AddIVx { .. } => "FX1E",
+            Self::LdFVx { .. } => "FX29",
+            Self::LdLfVx { .. } => "FX30",
+            Self::LdBVx { .. } => "FX33",
+            Self::LdAudioI => "F002",
+            Self::LdPitchVx { .. } => "FX3A",
+            Self::LdIVx { .. } => "FX55",
+            Self::LdVxI { .. } => "FX65",
+            Self::LdRVx { .. } => "FX75",
+            Self::LdVxR { .. } => "FX85",
+        }
+    }
+
+    pub fn class(&self) -> OpcodeClass {
+        match self {
+            Self::Cls
+            | Self::Scd { .. }
+            | Self::Scr
+            | Self::Scl
+            | Self::Low
+            | Self::High
+            | Self::SelPlane { .. }
+            | Self::DrwVxVyN { .. }
+            | Self::LdFVx { .. }
+            | Self::LdLfVx { .. } => OpcodeClass::Graphics,
+            Self::Ret | Self::Jp { .. } | Self::Call { .. } | Self::JpV0Addr { .. } => {
+                OpcodeClass::Flow
+            }
+            Self::SeVxKk { .. }
+            | Self::SneVxKk { .. }
+            | Self::SeVxVy { .. }
+            | Self::SneVxVy { .. } => OpcodeClass::Conditional,
+            Self::LdVxKk { .. }
+            | Self::AddVxKk { .. }
+            | Self::LdVxVy { .. }
+            | Self::OrVxVy { .. }
+            | Self::AndVxVy { .. }
+            | Self::XorVxVy { .. }
+            | Self::AddVxVy { .. }
+            | Self::SubVxVy { .. }
+            | Self::ShrVxVy { .. }
+            | Self::SubnVxVy { .. }
+            | Self::ShlVxVy { .. } => OpcodeClass::Arithmetic,
+            Self::LdIAddr { .. }
+            | Self::LdILong { .. }
+            | Self::AddIVx { .. }
+            | Self::LdBVx { .. }
+            | Self::LdIVx { .. }
+            | Self::LdVxI { .. }
+            | Self::LdIRange { .. }
+            | Self::LdRangeI { .. }
+            | Self::LdRVx { .. }
+            | Self::LdVxR { .. } => OpcodeClass::Memory,
+            Self::LdVxDt { .. } | Self::LdDtVx { .. } => OpcodeClass::Timer,
+            Self::SkpVx { .. } | Self::SknpVx { .. } | Self::LdVxK { .. } => OpcodeClass::Input,
+            Self::RndVxKk { .. } => OpcodeClass::Random,
+            Self::LdStVx { .. } | Self::LdAudioI | Self::LdPitchVx { .. } => OpcodeClass::Sound,
+            Self::Sys { .. } => OpcodeClass::System,
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Debug text drawn over the frame, with a built-in 3x5 font so no TTF library is needed.
+
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+// a glyph and the blank column and row after it
+const CELL_WIDTH: usize = GLYPH_WIDTH + 1;
+const CELL_HEIGHT: usize = GLYPH_HEIGHT + 1;
+
+const BACKGROUND: [u8; 4] = [0, 0, 0, 192];
+const TEXT: [u8; 4] = [255, 255, 255, 255];
+
+/// RGBA pixels, row-major, to be blended over the frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+/// Lines of text on a translucent black box, one pixel of padding around it.
+/// Letters are drawn uppercase and characters without a glyph as blanks.
+pub fn render_text(lines: &[String]) -> TextImage {
+    let columns = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0);
+    let width = 1 + columns * CELL_WIDTH;
+    let height = 1 + lines.len() * CELL_HEIGHT;
+    let mut pixels = BACKGROUND.repeat(width * height);
+    for (row, line) in lines.iter().enumerate() {
+        for (column, c) in line.chars().enumerate() {
+            for (dy, bits) in glyph(c).into_iter().enumerate() {
+                for dx in 0..GLYPH_WIDTH {
+                    if bits & (0b100 >> dx) != 0 {
+                        let x = 1 + column * CELL_WIDTH + dx;
+                        let y = 1 + row * CELL_HEIGHT + dy;
+                        let offset = (y * width + x) * 4;
+                        pixels[offset..offset + 4].copy_from_slice(&TEXT);
+                    }
+                }
+            }
+        }
+    }
+    TextImage {
+        width,
+        height,
+        pixels,
+    }
+}
+
+/// Line and column of the character cell under pixel x, y of a render_text image, e.g. to find
+/// what was clicked. The blank column and row after a glyph count as part of its cell.
+/// None on the padding at the top and left, past the end of the text isn't checked.
+pub fn cell_at(x: usize, y: usize) -> Option<(usize, usize)> {
+    Some((
+        (y.checked_sub(1)?) / CELL_HEIGHT,
+        (x.checked_sub(1)?) / CELL_WIDTH,
+    ))
+}
+
+// rows top to bottom, bit 2 is the left column
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '*' => [0b000, 0b101, 0b010, 0b101, 0b000],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(image: &TextImage, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * image.width + x) * 4;
+        image.pixels[offset..offset + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn draws_glyphs_in_cells() {
+        let image = render_text(&["1".to_string(), "i?".to_string()]);
+        assert_eq!((image.width, image.height), (9, 13));
+        assert_eq!(image.pixels.len(), 9 * 13 * 4);
+
+        // top row of the 1 is only lit in the middle
+        assert_eq!(pixel(&image, 1, 1), BACKGROUND);
+        assert_eq!(pixel(&image, 2, 1), TEXT);
+        // lowercase i is drawn as I, its top row is full
+        for x in 1..4 {
+            assert_eq!(pixel(&image, x, 7), TEXT);
+        }
+        // no glyph for ?, its cell stays blank
+        let lit = (5..8)
+            .flat_map(|x| (7..12).map(move |y| (x, y)))
+            .filter(|&(x, y)| pixel(&image, x, y) == TEXT)
+            .count();
+        assert_eq!(lit, 0);
+    }
+
+    #[test]
+    fn cells_are_found_by_pixel() {
+        assert_eq!(cell_at(0, 5), None);
+        assert_eq!(cell_at(5, 0), None);
+        assert_eq!(cell_at(1, 1), Some((0, 0)));
+        // the gap after the first glyph still belongs to it
+        assert_eq!(cell_at(4, 6), Some((0, 0)));
+        assert_eq!(cell_at(5, 7), Some((1, 1)));
+    }
+
+    #[test]
+    fn no_lines_is_just_the_padding() {
+        let image = render_text(&[]);
+        assert_eq!((image.width, image.height), (1, 1));
+    }
+}
@@ -0,0 +1,133 @@
+//! Minimal PNG encoder for screenshots: 8-bit RGB, stored (uncompressed) deflate blocks.
+//! Screens are small enough that compression isn't worth a dependency.
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+// largest stored deflate block
+const MAX_BLOCK: usize = 0xFFFF;
+
+/// Encode width x height pixels, rgb holds 3 bytes per pixel row by row.
+pub fn encode_rgb(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    let stride = width as usize * 3;
+    assert_eq!(
+        rgb.len(),
+        stride * height as usize,
+        "rgb doesn't match size"
+    );
+
+    let mut header = Vec::with_capacity(13);
+    header.extend(width.to_be_bytes());
+    header.extend(height.to_be_bytes());
+    // bit depth 8, color type 2 (RGB), default compression, filter and no interlace
+    header.extend([8, 2, 0, 0, 0]);
+
+    // every row starts with filter type 0, none
+    let mut raw = Vec::with_capacity(rgb.len() + height as usize);
+    for row in rgb.chunks(stride.max(1)).take(height as usize) {
+        raw.push(0);
+        raw.extend(row);
+    }
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Width and height from the header of a PNG, None if it doesn't start like one.
+pub fn dimensions(png: &[u8]) -> Option<(u32, u32)> {
+    if png.get(..8)? != SIGNATURE || png.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(png.get(16..20)?.try_into().ok()?);
+    let height = u32::from_be_bytes(png.get(20..24)?.try_into().ok()?);
+    Some((width, height))
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+// zlib stream of stored blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // deflate with a 32K window, no preset dictionary, check bits making the header divisible by 31
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend([1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend(block);
+    }
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_match_reference_values() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn encodes_header_and_splits_large_images() {
+        let (width, height) = (256, 128);
+        let png = encode_rgb(width, height, &vec![0x80; (width * height * 3) as usize]);
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..20], width.to_be_bytes());
+        assert_eq!(png[20..24], height.to_be_bytes());
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+
+        // 98432 bytes of filtered rows need two stored blocks
+        let idat_len = u32::from_be_bytes(png[33..37].try_into().unwrap()) as usize;
+        let raw_len = (width * 3 + 1) as usize * height as usize;
+        assert_eq!(idat_len, 2 + raw_len + 2 * 5 + 4);
+    }
+
+    #[test]
+    fn reads_dimensions_back() {
+        let png = encode_rgb(3, 2, &[0; 18]);
+        assert_eq!(dimensions(&png), Some((3, 2)));
+        assert_eq!(dimensions(&png[..20]), None);
+        assert_eq!(dimensions(b"GIF89a"), None);
+    }
+}
@@ -0,0 +1,284 @@
+//! Input recordings: keypad presses and releases timed by VM::cycle_count, for replaying a run.
+//!
+//! Layout, multi-byte values little-endian:
+//! magic "CH8IREC" and version, SHA-256 of the ROM, cycles per frame, RNG seed, then every event as
+//! cycle (u64), key, pressed (0 or 1).
+
+use std::collections::VecDeque;
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::Chip8Error,
+    input::{InputSource, KEY_COUNT},
+};
+
+pub const RECORDING_MAGIC: [u8; 8] = *b"CH8IREC\x02";
+
+const HEADER_LEN: usize = RECORDING_MAGIC.len() + 32 + 4 + 8;
+const EVENT_LEN: usize = 8 + 1 + 1;
+
+/// A keypad key going down or up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputEvent {
+    // VM::cycle_count when the change was polled
+    pub cycle: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Recording {
+    pub rom_sha256: [u8; 32],
+    // speed the run was recorded at, replays must match it for frames to line up
+    pub cycles_per_frame: u32,
+    // CXKK's seed, replaying with it makes the run fully deterministic
+    pub seed: u64,
+    pub events: Vec<InputEvent>,
+}
+
+impl Recording {
+    pub fn new(rom: &[u8], cycles_per_frame: u32, seed: u64) -> Self {
+        Self {
+            rom_sha256: Sha256::digest(rom).into(),
+            cycles_per_frame,
+            seed,
+            events: Vec::new(),
+        }
+    }
+
+    /// Append an event for every key that differs between before and after.
+    pub fn record_changes(
+        &mut self,
+        cycle: u64,
+        before: &[bool; KEY_COUNT],
+        after: &[bool; KEY_COUNT],
+    ) {
+        for (key, (&was, &is)) in before.iter().zip(after).enumerate() {
+            if was != is {
+                self.events.push(InputEvent {
+                    cycle,
+                    key: key as u8,
+                    pressed: is,
+                });
+            }
+        }
+    }
+
+    /// Whether this was recorded with rom loaded.
+    pub fn matches_rom(&self, rom: &[u8]) -> bool {
+        self.rom_sha256 == <[u8; 32]>::from(Sha256::digest(rom))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.events.len() * EVENT_LEN);
+        bytes.extend_from_slice(&RECORDING_MAGIC);
+        bytes.extend_from_slice(&self.rom_sha256);
+        bytes.extend_from_slice(&self.cycles_per_frame.to_le_bytes());
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        for event in &self.events {
+            bytes.extend_from_slice(&event.cycle.to_le_bytes());
+            bytes.push(event.key);
+            bytes.push(event.pressed as u8);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Chip8Error> {
+        let invalid = |reason: &str| Err(Chip8Error::InvalidRecording(reason.to_string()));
+        if bytes.len() < HEADER_LEN || bytes[..RECORDING_MAGIC.len()] != RECORDING_MAGIC {
+            return invalid("not an input recording");
+        }
+        let (header, body) = bytes.split_at(HEADER_LEN);
+        if body.len() % EVENT_LEN != 0 {
+            return invalid("truncated event");
+        }
+        let rom_sha256 = header[8..40].try_into().expect("32 bytes");
+        let cycles_per_frame = u32::from_le_bytes(header[40..44].try_into().expect("4 bytes"));
+        let seed = u64::from_le_bytes(header[44..52].try_into().expect("8 bytes"));
+
+        let mut events = Vec::with_capacity(body.len() / EVENT_LEN);
+        for event in body.chunks(EVENT_LEN) {
+            let cycle = u64::from_le_bytes(event[..8].try_into().expect("8 bytes"));
+            let (key, pressed) = (event[8], event[9]);
+            if key as usize >= KEY_COUNT || pressed > 1 {
+                return invalid("event for a key that doesn't exist");
+            }
+            if events
+                .last()
+                .is_some_and(|last: &InputEvent| last.cycle > cycle)
+            {
+                return invalid("events out of order");
+            }
+            events.push(InputEvent {
+                cycle,
+                key,
+                pressed: pressed == 1,
+            });
+        }
+        Ok(Self {
+            rom_sha256,
+            cycles_per_frame,
+            seed,
+            events,
+        })
+    }
+}
+
+/// Plays a recording back as keypad input: advance it to VM::cycle_count before polling.
+pub struct Replay {
+    // events still to come, oldest first
+    events: VecDeque<InputEvent>,
+    keys: [bool; KEY_COUNT],
+}
+
+impl Replay {
+    pub fn new(recording: Recording) -> Self {
+        let mut events = recording.events;
+        // from_bytes rejects unordered files, but recordings built in memory could be
+        events.sort_by_key(|event| event.cycle);
+        Self {
+            events: events.into(),
+            keys: [false; KEY_COUNT],
+        }
+    }
+
+    /// Apply every event recorded at or before cycle.
+    pub fn advance(&mut self, cycle: u64) {
+        while let Some(event) = self.events.front() {
+            if event.cycle > cycle {
+                break;
+            }
+            self.keys[event.key as usize] = event.pressed;
+            self.events.pop_front();
+        }
+    }
+
+    /// Keys held down as of the last advance.
+    pub fn keys(&self) -> [bool; KEY_COUNT] {
+        self.keys
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+impl InputSource for Replay {
+    fn poll(&mut self, keys: &mut [bool; KEY_COUNT]) {
+        *keys = self.keys;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VM;
+
+    const ROM: [u8; 4] = [0x12, 0x00, 0x00, 0xE0];
+
+    #[test]
+    fn round_trips_through_bytes() {
+        let mut recording = Recording::new(&ROM, 15, 42);
+        let mut keys = [false; KEY_COUNT];
+        for (cycle, key, pressed) in [(10, 5, true), (40, 5, false), (40, 2, true)] {
+            let before = keys;
+            keys[key] = pressed;
+            recording.record_changes(cycle, &before, &keys);
+        }
+        // one poll can change several keys
+        let mut after = [false; KEY_COUNT];
+        after[8] = true;
+        recording.record_changes(90, &keys, &after);
+        assert_eq!(recording.events.len(), 5);
+
+        let bytes = recording.to_bytes();
+        assert_eq!(bytes[..8], RECORDING_MAGIC);
+        assert_eq!(bytes.len(), HEADER_LEN + 5 * EVENT_LEN);
+        let loaded = Recording::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded, recording);
+        assert_eq!(loaded.cycles_per_frame, 15);
+        assert_eq!(loaded.seed, 42);
+        assert_eq!(
+            loaded.events[3..],
+            [
+                InputEvent {
+                    cycle: 90,
+                    key: 2,
+                    pressed: false
+                },
+                InputEvent {
+                    cycle: 90,
+                    key: 8,
+                    pressed: true
+                }
+            ]
+        );
+        assert!(loaded.matches_rom(&ROM));
+        assert!(!loaded.matches_rom(&[0x12, 0x02]));
+    }
+
+    #[test]
+    fn rejects_damaged_recordings() {
+        let mut recording = Recording::new(&ROM, 10, 0);
+        recording.record_changes(0, &[false; KEY_COUNT], &[true; KEY_COUNT]);
+        let bytes = recording.to_bytes();
+        let invalid = |bytes: &[u8]| {
+            matches!(
+                Recording::from_bytes(bytes),
+                Err(Chip8Error::InvalidRecording(_))
+            )
+        };
+
+        assert!(invalid(&bytes[..bytes.len() - 1]));
+        assert!(invalid(&bytes[..HEADER_LEN - 1]));
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert!(invalid(&bad_magic));
+        let mut bad_key = bytes.clone();
+        bad_key[HEADER_LEN + 8] = KEY_COUNT as u8;
+        assert!(invalid(&bad_key));
+    }
+
+    #[test]
+    fn replays_keys_at_the_recorded_cycles() {
+        // record three presses against a VM spinning on a jump to itself
+        let mut vm = VM::new_headless();
+        vm.load_rom(&ROM).unwrap();
+        let mut recording = Recording::new(&ROM, 10, 0);
+        for cycle in 0..100 {
+            let before = vm.keys();
+            match cycle {
+                10 => vm.set_key(0x1, true),
+                30 => vm.set_key(0xA, true),
+                50 => vm.set_key(0x1, false),
+                _ => {}
+            }
+            recording.record_changes(vm.cycle_count(), &before, &vm.keys());
+            vm.step().unwrap();
+        }
+        assert_eq!(recording.events.len(), 3);
+
+        let path = std::env::temp_dir().join(format!("crust8-replay-{}.rec", std::process::id()));
+        std::fs::write(&path, recording.to_bytes()).unwrap();
+        let loaded = Recording::from_bytes(&std::fs::read(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.matches_rom(&ROM));
+
+        let mut replay = Replay::new(loaded);
+        let mut vm = VM::new_headless();
+        vm.load_rom(&ROM).unwrap();
+        let mut held = Vec::new();
+        for _ in 0..100 {
+            replay.advance(vm.cycle_count());
+            vm.poll_input(&mut replay);
+            held.push(vm.keys());
+            vm.step().unwrap();
+        }
+        assert!(replay.is_finished());
+        let pressed =
+            |key: usize| -> Vec<usize> { (0..100).filter(|&cycle| held[cycle][key]).collect() };
+        assert_eq!(pressed(0x1), (10..50).collect::<Vec<_>>());
+        assert_eq!(pressed(0xA), (30..100).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::VecDeque;
+
+/// Ring buffer of recent VM::save_state snapshots, newest last.
+/// Once full, pushing a snapshot drops the oldest one, so memory use stays bounded.
+pub struct Rewind {
+    snapshots: VecDeque<Vec<u8>>,
+    capacity: usize,
+}
+
+impl Rewind {
+    /// capacity is the number of snapshots kept, 600 is ten seconds of frames at 60Hz.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            snapshots: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(state);
+    }
+
+    /// Take the newest snapshot, stepping one frame further back each call.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        self.snapshots.pop_back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VM;
+
+    #[test]
+    fn full_buffer_drops_the_oldest_snapshot() {
+        let mut rewind = Rewind::new(3);
+        for frame in 0..5u8 {
+            rewind.push(vec![frame]);
+        }
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.pop(), Some(vec![4]));
+        assert_eq!(rewind.pop(), Some(vec![3]));
+        assert_eq!(rewind.pop(), Some(vec![2]));
+        assert_eq!(rewind.pop(), None);
+
+        let mut disabled = Rewind::new(0);
+        disabled.push(vec![0]);
+        assert!(disabled.is_empty());
+    }
+
+    #[test]
+    fn popping_restores_the_previous_frames() {
+        let mut vm = VM::new_headless();
+        // ADD V0, 1 and jump back
+        vm.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        let mut rewind = Rewind::new(10);
+        for _ in 0..3 {
+            rewind.push(vm.save_state());
+            vm.run_frame(2).unwrap();
+        }
+        assert_eq!(vm.registers()[0], 3);
+
+        vm.load_state(&rewind.pop().unwrap()).unwrap();
+        assert_eq!(vm.registers()[0], 2);
+        assert_eq!(vm.pc(), 0x200);
+        vm.load_state(&rewind.pop().unwrap()).unwrap();
+        assert_eq!(vm.registers()[0], 1);
+    }
+}
@@ -0,0 +1,200 @@
+use std::{collections::BTreeMap, fmt};
+
+use crate::opcode::{Opcode, OpcodeClass};
+
+/// Address programs are loaded at, everything below is reserved for the interpreter.
+pub const ROM_START: u16 = 0x200;
+
+/// A CHIP-8 program image as read from disk.
+pub struct Rom {
+    data: Vec<u8>,
+}
+
+impl Rom {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self { data }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Addresses of FX55/FX65 (and XO-CHIP 5XY2/5XY3) instructions whose I may point into the program region (0x200+).
+    /// Static, linear pass with no execution: I is tracked through ANNN, F000 NNNN and font loads,
+    /// any other write to I (e.g. FX1E) makes it unknown, which is flagged as well.
+    /// Data interleaved with code may produce false positives.
+    pub fn detect_self_modifying_code(&self) -> Vec<u16> {
+        let mut flagged = Vec::new();
+        // last known value of I, None when unknown
+        let mut i: Option<u16> = Some(0);
+        let mut words = self
+            .data
+            .chunks_exact(2)
+            .map(|word| u16::from_be_bytes([word[0], word[1]]));
+        let mut addr = ROM_START;
+
+        while let Some(instruction) = words.next() {
+            let n1 = instruction >> 12;
+            let kk = instruction & 0x00FF;
+            match n1 {
+                0xA => i = Some(instruction & 0x0FFF),
+                // the address word belongs to the instruction and is not decoded on its own
+                0xF if instruction == 0xF000 => {
+                    i = words.next();
+                    addr = addr.wrapping_add(2);
+                }
+                0xF if kk == 0x1E => i = None,
+                // font sprites live below the program region
+                0xF if kk == 0x29 || kk == 0x30 => i = Some(0),
+                0xF if (kk == 0x55 || kk == 0x65) && i.is_none_or(|i| i >= ROM_START) => {
+                    flagged.push(addr);
+                }
+                0x5 if (instruction & 0x000F == 0x2 || instruction & 0x000F == 0x3)
+                    && i.is_none_or(|i| i >= ROM_START) =>
+                {
+                    flagged.push(addr);
+                }
+                _ => {}
+            }
+            addr = addr.wrapping_add(2);
+        }
+
+        flagged
+    }
+}
+
+/// Static opcode counts for a ROM, gives an idea of its character
+/// (e.g. lots of DXYN is graphics heavy, lots of skips is logic heavy).
+/// Every word is decoded without executing, so data in the ROM is counted too.
+#[derive(Debug, Default)]
+pub struct OpcodeFrequencyMap {
+    pub classes: BTreeMap<OpcodeClass, usize>,
+    // keyed by Opcode::pattern
+    pub opcodes: BTreeMap<&'static str, usize>,
+    // words that don't decode to any opcode, usually sprite or other data
+    pub unknown: usize,
+}
+
+impl OpcodeFrequencyMap {
+    pub fn from_rom(rom: &[u8]) -> Self {
+        let mut map = Self::default();
+        let mut words = rom
+            .chunks_exact(2)
+            .map(|word| u16::from_be_bytes([word[0], word[1]]));
+
+        while let Some(instruction) = words.next() {
+            let opcode = if instruction == 0xF000 {
+                // address word belongs to the instruction, not counted on its own
+                words.next().map(|nnnn| Opcode::LdILong { nnnn })
+            } else {
+                Opcode::decode(instruction)
+            };
+            match opcode {
+                Some(opcode) => {
+                    *map.classes.entry(opcode.class()).or_default() += 1;
+                    *map.opcodes.entry(opcode.pattern()).or_default() += 1;
+                }
+                None => map.unknown += 1,
+            }
+        }
+
+        map
+    }
+
+    pub fn total(&self) -> usize {
+        self.opcodes.values().sum::<usize>() + self.unknown
+    }
+}
+
+impl fmt::Display for OpcodeFrequencyMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total().max(1) as f32;
+        let mut classes: Vec<_> = self.classes.iter().collect();
+        classes.sort_by(|a, b| b.1.cmp(a.1));
+        let mut opcodes: Vec<_> = self.opcodes.iter().collect();
+        opcodes.sort_by(|a, b| b.1.cmp(a.1));
+
+        writeln!(f, "{} words", self.total())?;
+        writeln!(f, "\nby class:")?;
+        for (class, count) in classes {
+            let class = format!("{class:?}");
+            writeln!(
+                f,
+                "  {class:<12}{count:>6}  {:5.1}%",
+                *count as f32 / total * 100.0
+            )?;
+        }
+        writeln!(f, "\nby opcode:")?;
+        for (pattern, count) in opcodes {
+            writeln!(
+                f,
+                "  {pattern:<12}{count:>6}  {:5.1}%",
+                *count as f32 / total * 100.0
+            )?;
+        }
+        write!(
+            f,
+            "  {:<12}{:>6}  {:5.1}%",
+            "unknown",
+            self.unknown,
+            self.unknown as f32 / total * 100.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flagged(rom: &[u8]) -> Vec<u16> {
+        Rom::new(rom.to_vec()).detect_self_modifying_code()
+    }
+
+    #[test]
+    fn stores_below_the_program_are_not_flagged() {
+        // LD I, 0x100; LD [I], V3; LD F, V0; LD V3, [I]
+        assert!(flagged(&[0xA1, 0x00, 0xF3, 0x55, 0xF0, 0x29, 0xF3, 0x65]).is_empty());
+    }
+
+    #[test]
+    fn stores_into_the_program_are_flagged() {
+        // LD I, 0x300; LD [I], V3; LD I, 0x100; ADD I, V0; SAVE V0 - V1
+        let rom = [0xA3, 0x00, 0xF3, 0x55, 0xA1, 0x00, 0xF0, 0x1E, 0x50, 0x12];
+        assert_eq!(flagged(&rom), [0x202, 0x208]);
+    }
+
+    #[test]
+    fn long_load_address_is_not_decoded_as_an_instruction() {
+        // LD I, 0xF355 and LD [I], V3 at 0x204, the address word looks like FX55
+        let rom = [0xF0, 0x00, 0xF3, 0x55, 0xF3, 0x55];
+        assert_eq!(flagged(&rom), [0x204]);
+        // LD I, 0x0100 keeps the store below the program
+        let rom = [0xF0, 0x00, 0x01, 0x00, 0xF3, 0x55];
+        assert!(flagged(&rom).is_empty());
+    }
+
+    #[test]
+    fn frequency_map_counts_classes_and_opcodes() {
+        // CLS; LD V1, 0x05; LD V2, 0x06; DRW V1, V2, 5; JP 0x200; sprite byte pair
+        let rom = [
+            0x00, 0xE0, 0x61, 0x05, 0x62, 0x06, 0xD1, 0x25, 0x12, 0x00, 0xFF, 0xFF,
+        ];
+        let map = OpcodeFrequencyMap::from_rom(&rom);
+        assert_eq!(map.opcodes["6XKK"], 2);
+        assert_eq!(map.opcodes["00E0"], 1);
+        assert_eq!(map.opcodes["DXYN"], 1);
+        assert_eq!(map.classes[&OpcodeClass::Graphics], 2);
+        assert_eq!(map.classes[&OpcodeClass::Flow], 1);
+        assert_eq!(map.unknown, 1);
+        assert_eq!(map.total(), 6);
+    }
+
+    #[test]
+    fn frequency_map_skips_the_long_load_address() {
+        // LD I, 0xA123, the address word on its own would count as ANNN
+        let map = OpcodeFrequencyMap::from_rom(&[0xF0, 0x00, 0xA1, 0x23]);
+        assert_eq!(map.opcodes["F000"], 1);
+        assert!(!map.opcodes.contains_key("ANNN"));
+        assert_eq!(map.total(), 1);
+    }
+}
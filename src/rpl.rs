@@ -0,0 +1,28 @@
+//! Persistence for SCHIP RPL user flags (FX75/FX85) across emulator sessions.
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+pub const RPL_FLAG_COUNT: usize = 8;
+
+/// `~/.local/share/crust8/rpl_flags.bin` on Linux, platform equivalent elsewhere.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("crust8").join("rpl_flags.bin"))
+}
+
+/// Read previously saved flags, None if the file is missing or malformed.
+pub fn load(path: &Path) -> Option<[u8; RPL_FLAG_COUNT]> {
+    fs::read(path).ok()?.try_into().ok()
+}
+
+/// Write flags atomically: write a sibling `.tmp` file then rename it over the target,
+/// so a crash mid-write never leaves a truncated file behind.
+pub fn save(path: &Path, flags: &[u8; RPL_FLAG_COUNT]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, flags)?;
+    fs::rename(&tmp, path)
+}
@@ -0,0 +1,447 @@
+//! SDL2 backends for the display, buzzer and keypad.
+use std::collections::HashMap;
+
+use sdl2::{
+    audio::{AudioCallback, AudioDevice},
+    controller::{Axis, Button, GameController},
+    event::Event,
+    keyboard::{Keycode, Scancode},
+    pixels::{Color, PixelFormatEnum},
+    rect::Rect,
+    render::{BlendMode, Canvas, Texture, TextureCreator},
+    video::{FullscreenType, Window, WindowContext},
+    EventPump, GameControllerSubsystem,
+};
+
+use crate::{
+    display::{scanline_overlay, Phosphor, Renderer, Screen, DEFAULT_PALETTE},
+    input::{build_key_map, Gamepad, InputSource, DEFAULT_GAMEPAD_NAMES, KEY_COUNT},
+    overlay::{render_text, TextImage},
+    speaker::{AudioSink, PatternWave, PATTERN_BYTES},
+    vm::{SCREEN_HEIGHT, SCREEN_WIDTH},
+};
+
+/// Uploads the pixels to a streaming texture and stretches it over the canvas,
+/// one blit per frame instead of a rect per CHIP-8 pixel.
+pub struct SdlRenderer {
+    canvas: Canvas<Window>,
+    // leaked so the texture can borrow it for 'static, there is one renderer per window
+    texture_creator: &'static TextureCreator<WindowContext>,
+    // sized for the current mode, recreated when the program switches resolution
+    texture: Option<Texture<'static>>,
+    scale: usize,
+    palette: [[u8; 3]; 4],
+    // afterglow, None draws pixels as they are
+    phosphor: Option<Phosphor>,
+    // darkness of the CRT scanlines, 0 draws none
+    scanline_alpha: u8,
+    // built on first use at the size the frame is drawn at
+    scanlines: Option<Texture<'static>>,
+    // debugger text, uploaded on the next render
+    overlay: Option<TextImage>,
+    overlay_texture: Option<Texture<'static>>,
+}
+
+impl SdlRenderer {
+    pub fn new(canvas: Canvas<Window>, scale: usize) -> Self {
+        let texture_creator = Box::leak(Box::new(canvas.texture_creator()));
+        Self {
+            canvas,
+            texture_creator,
+            texture: None,
+            scale,
+            palette: DEFAULT_PALETTE,
+            phosphor: None,
+            scanline_alpha: 0,
+            scanlines: None,
+            overlay: None,
+            overlay_texture: None,
+        }
+    }
+
+    /// Darken every other window row like a CRT's scanlines, 0 turns them off.
+    pub fn set_scanline_alpha(&mut self, alpha: u8) {
+        self.scanline_alpha = alpha;
+        self.scanlines = None;
+    }
+
+    /// Fade pixels out by decay per frame after they turn off, see display::Phosphor.
+    /// The screen then needs rendering every frame, not only when it changes.
+    pub fn set_phosphor_decay(&mut self, decay: f32) {
+        self.phosphor = (decay > 0.0).then(|| Phosphor::new(decay));
+    }
+}
+
+impl Renderer for SdlRenderer {
+    fn render(&mut self, screen: &Screen) -> Result<(), String> {
+        let (width, height) = (screen.width(), screen.height());
+        let texture = match &mut self.texture {
+            Some(texture) if texture.query().width as usize == width => texture,
+            texture => texture.insert(
+                self.texture_creator
+                    .create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+                    .map_err(|e| e.to_string())?,
+            ),
+        };
+        let palette = &self.palette;
+        if let Some(phosphor) = &mut self.phosphor {
+            phosphor.update(screen);
+        }
+        let phosphor = self.phosphor.as_ref();
+        texture.with_lock(None, |pixels, pitch| {
+            for y in 0..height {
+                let row = &mut pixels[y * pitch..y * pitch + width * 3];
+                for (x, rgb) in row.chunks_exact_mut(3).enumerate() {
+                    let color = match phosphor {
+                        Some(phosphor) => phosphor.color(screen, palette, x, y),
+                        None => palette[screen.pixel_color(x, y)],
+                    };
+                    rgb.copy_from_slice(&color);
+                }
+            }
+        })?;
+
+        // black letterbox bars in fullscreen, the texture covers the rest
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+        // window is sized for low-res, hi-res pixels cover half as many window pixels
+        let dst = Rect::new(
+            0,
+            0,
+            (SCREEN_WIDTH * self.scale) as u32,
+            (SCREEN_HEIGHT * self.scale) as u32,
+        );
+        self.canvas.copy(texture, None, dst)?;
+        if self.scanline_alpha > 0 {
+            let scanlines = match &mut self.scanlines {
+                Some(scanlines) if scanlines.query().width == dst.width() => scanlines,
+                scanlines => {
+                    let (width, height) = (dst.width(), dst.height());
+                    let mut texture = self
+                        .texture_creator
+                        .create_texture_static(PixelFormatEnum::RGBA32, width, height)
+                        .map_err(|e| e.to_string())?;
+                    let pixels =
+                        scanline_overlay(width as usize, height as usize, self.scanline_alpha);
+                    texture
+                        .update(None, &pixels, width as usize * 4)
+                        .map_err(|e| e.to_string())?;
+                    texture.set_blend_mode(BlendMode::Blend);
+                    scanlines.insert(texture)
+                }
+            };
+            self.canvas.copy(scanlines, None, dst)?;
+        }
+        if let Some(overlay) = self.overlay.take() {
+            let (width, height) = (overlay.width as u32, overlay.height as u32);
+            let mut texture = self
+                .texture_creator
+                .create_texture_static(PixelFormatEnum::RGBA32, width, height)
+                .map_err(|e| e.to_string())?;
+            texture
+                .update(None, &overlay.pixels, overlay.width * 4)
+                .map_err(|e| e.to_string())?;
+            texture.set_blend_mode(BlendMode::Blend);
+            self.overlay_texture = Some(texture);
+        }
+        if let Some(overlay) = &self.overlay_texture {
+            // text pixels are half a low-res pixel, small enough to leave most of the game visible
+            let size = (self.scale / 2).max(1) as u32;
+            let query = overlay.query();
+            let dst = Rect::new(0, 0, query.width * size, query.height * size);
+            self.canvas.copy(overlay, None, dst)?;
+        }
+        self.canvas.present();
+        Ok(())
+    }
+
+    fn set_overlay(&mut self, lines: &[String]) {
+        self.overlay_texture = None;
+        self.overlay = (!lines.is_empty()).then(|| render_text(lines));
+    }
+
+    fn set_title(&mut self, title: &str) {
+        // only fails on interior nul bytes, the title is ours
+        let _ = self.canvas.window_mut().set_title(title);
+    }
+
+    fn set_palette(&mut self, palette: [[u8; 3]; 4]) {
+        self.palette = palette;
+    }
+
+    fn set_fullscreen(&mut self, fullscreen: bool) -> Result<(), String> {
+        let (mode, (width, height)) = if fullscreen {
+            let size = (SCREEN_WIDTH * self.scale, SCREEN_HEIGHT * self.scale);
+            (FullscreenType::Desktop, (size.0 as u32, size.1 as u32))
+        } else {
+            // a logical size of 0x0 turns scaling off again
+            (FullscreenType::Off, (0, 0))
+        };
+        // fails if e.g. the display the window was on is gone, the window stays as it was
+        self.canvas.window_mut().set_fullscreen(mode)?;
+        self.canvas
+            .set_logical_size(width, height)
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl AudioCallback for PatternWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.fill(out);
+    }
+}
+
+/// Plays the buzzer through an SDL audio device. The device runs the whole time, pausing it
+/// would cut the tone off mid-wave, the wave fades itself in and out instead.
+pub struct SdlAudio {
+    device: AudioDevice<PatternWave>,
+}
+
+impl SdlAudio {
+    pub fn new(device: AudioDevice<PatternWave>) -> Self {
+        device.resume();
+        Self { device }
+    }
+}
+
+impl AudioSink for SdlAudio {
+    fn set_playing(&mut self, playing: bool) {
+        self.device.lock().set_playing(playing);
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8; PATTERN_BYTES], pitch: u8) {
+        self.device.lock().set_pattern(pattern, pitch);
+    }
+
+    fn set_pitch(&mut self, pitch: u8) {
+        self.device.lock().set_pitch(pitch);
+    }
+
+    fn set_muted(&mut self, muted: bool) {
+        self.device.lock().set_muted(muted);
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        self.device.lock().set_volume(volume);
+    }
+}
+
+/// Reads the keypad from the keyboard, using the left side of a QWERTY layout:
+///
+/// ```text
+/// 1 2 3 4      1 2 3 C
+/// Q W E R  ->  4 5 6 D
+/// A S D F      7 8 9 E
+/// Z X C V      A 0 B F
+/// ```
+///
+/// Keys are matched by position, so other layouts use the same physical keys.
+/// A configured `key_map` binds keys by the name printed on them instead, see with_key_map.
+/// Controllers are read as well once with_gamepad is called.
+pub struct SdlInput {
+    event_pump: EventPump,
+    // keyboard key to keypad key, several keyboard keys may share a keypad key
+    key_map: HashMap<Scancode, usize>,
+    gamepad: Option<SdlGamepad>,
+}
+
+// open controllers by instance id, all of them feed the same keypad
+struct SdlGamepad {
+    subsystem: GameControllerSubsystem,
+    controllers: HashMap<u32, GameController>,
+    state: Gamepad<Button>,
+}
+
+impl SdlGamepad {
+    fn open(&mut self, joystick_index: u32) {
+        match self.subsystem.open(joystick_index) {
+            Ok(controller) => {
+                eprintln!("controller connected: {}", controller.name());
+                self.controllers
+                    .insert(controller.instance_id(), controller);
+            }
+            Err(e) => eprintln!("unable to open controller {joystick_index}: {e}"),
+        }
+    }
+
+    // true if the event was a controller event
+    fn handle_event(&mut self, event: &Event) -> bool {
+        match *event {
+            Event::ControllerButtonDown { button, .. } => self.state.button(button, true),
+            Event::ControllerButtonUp { button, .. } => self.state.button(button, false),
+            Event::ControllerAxisMotion {
+                axis: Axis::LeftX,
+                value,
+                ..
+            } => self.state.stick_x(value),
+            Event::ControllerAxisMotion {
+                axis: Axis::LeftY,
+                value,
+                ..
+            } => self.state.stick_y(value),
+            Event::ControllerDeviceAdded { which, .. } => self.open(which),
+            Event::ControllerDeviceRemoved { which, .. } => {
+                if let Some(controller) = self.controllers.remove(&which) {
+                    eprintln!("controller disconnected: {}", controller.name());
+                }
+                // the button up events for anything it held are never coming
+                self.state.release_all();
+            }
+            Event::ControllerAxisMotion { .. } => {}
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl SdlInput {
+    pub fn new(event_pump: EventPump) -> Self {
+        Self {
+            event_pump,
+            key_map: default_key_map(),
+            gamepad: None,
+        }
+    }
+
+    /// Bind keys by SDL key name (e.g. "W", "Up", "Space") in the current keyboard layout.
+    /// An empty map keeps the built-in layout.
+    pub fn with_key_map(
+        event_pump: EventPump,
+        names: &HashMap<String, usize>,
+    ) -> Result<Self, String> {
+        let mut input = Self::new(event_pump);
+        input.set_key_map(names)?;
+        Ok(input)
+    }
+
+    /// Replace the keyboard bindings, e.g. with the key_map of a newly loaded ROM.
+    /// An empty map restores the built-in layout. On error the current bindings are kept.
+    pub fn set_key_map(&mut self, names: &HashMap<String, usize>) -> Result<(), String> {
+        self.key_map = if names.is_empty() {
+            default_key_map()
+        } else {
+            build_key_map(names, |name| {
+                Keycode::from_name(name).and_then(Scancode::from_keycode)
+            })?
+        };
+        Ok(())
+    }
+
+    /// Also read the keypad from every connected controller, and any plugged in later.
+    /// names binds SDL button names (e.g. "a", "dpup", "leftshoulder") to keypad keys,
+    /// an empty map uses input::DEFAULT_GAMEPAD_NAMES. The left stick acts as the d-pad
+    /// past deadzone.
+    pub fn with_gamepad(
+        mut self,
+        subsystem: GameControllerSubsystem,
+        names: &HashMap<String, usize>,
+        deadzone: u16,
+    ) -> Result<Self, String> {
+        let defaults;
+        let names = if names.is_empty() {
+            defaults = DEFAULT_GAMEPAD_NAMES
+                .iter()
+                .map(|&(name, key)| (name.to_string(), key))
+                .collect();
+            &defaults
+        } else {
+            names
+        };
+        let buttons = build_key_map(names, Button::from_string)
+            .map_err(|e| e.replace("key_map", "gamepad_map"))?;
+        let dpad = [
+            Button::DPadUp,
+            Button::DPadDown,
+            Button::DPadLeft,
+            Button::DPadRight,
+        ];
+        let mut gamepad = SdlGamepad {
+            subsystem,
+            controllers: HashMap::new(),
+            state: Gamepad::new(buttons, dpad, deadzone),
+        };
+        for index in 0..gamepad.subsystem.num_joysticks()? {
+            if gamepad.subsystem.is_game_controller(index) {
+                gamepad.open(index);
+            }
+        }
+        self.gamepad = Some(gamepad);
+        Ok(self)
+    }
+
+    /// Pending window and keyboard events, for everything besides the keypad (quitting, hotkeys, ...).
+    /// Also refreshes the keyboard state read by poll and applies controller events.
+    pub fn poll_events(&mut self) -> Vec<Event> {
+        let events = self.event_pump.poll_iter();
+        match &mut self.gamepad {
+            Some(gamepad) => events.filter(|e| !gamepad.handle_event(e)).collect(),
+            None => events.collect(),
+        }
+    }
+}
+
+impl InputSource for SdlInput {
+    fn poll(&mut self, keys: &mut [bool; KEY_COUNT]) {
+        let keyboard = self.event_pump.keyboard_state();
+        keys.fill(false);
+        for (&scancode, &key) in &self.key_map {
+            if keyboard.is_scancode_pressed(scancode) {
+                keys[key] = true;
+            }
+        }
+        if let Some(gamepad) = &mut self.gamepad {
+            let mut pad_keys = [false; KEY_COUNT];
+            gamepad.state.poll(&mut pad_keys);
+            for (key, pressed) in keys.iter_mut().zip(pad_keys) {
+                *key |= pressed;
+            }
+        }
+    }
+}
+
+/// Joysticks SDL can see, with whether each one has a controller mapping, for --list-controllers.
+pub fn list_controllers(subsystem: &GameControllerSubsystem) -> Result<Vec<String>, String> {
+    (0..subsystem.num_joysticks()?)
+        .map(|index| {
+            let name = subsystem
+                .name_for_index(index)
+                .unwrap_or_else(|_| "unknown".to_string());
+            let kind = if subsystem.is_game_controller(index) {
+                "controller"
+            } else {
+                "joystick without a controller mapping, not used"
+            };
+            Ok(format!("{index}: {name} ({kind})"))
+        })
+        .collect()
+}
+
+fn default_key_map() -> HashMap<Scancode, usize> {
+    KEYPAD
+        .iter()
+        .enumerate()
+        .map(|(key, &scancode)| (scancode, key))
+        .collect()
+}
+
+// scancode for each keypad key, indexed by key, the positions of input::DEFAULT_KEY_NAMES
+const KEYPAD: [Scancode; KEY_COUNT] = [
+    Scancode::X,
+    Scancode::Num1,
+    Scancode::Num2,
+    Scancode::Num3,
+    Scancode::Q,
+    Scancode::W,
+    Scancode::E,
+    Scancode::A,
+    Scancode::S,
+    Scancode::D,
+    Scancode::Z,
+    Scancode::C,
+    Scancode::Num4,
+    Scancode::R,
+    Scancode::F,
+    Scancode::V,
+];
@@ -1,30 +1,294 @@
-pub struct SquareWave {
+use serde::Deserialize;
+
+// XO-CHIP audio pattern is 16 bytes, one bit per sample, most significant bit first
+pub const PATTERN_BYTES: usize = 16;
+pub const PATTERN_SAMPLES: usize = PATTERN_BYTES * 8;
+// pitch register value that plays the pattern at 4000 samples per second
+pub const DEFAULT_PITCH: u8 = 64;
+
+/// Plays the buzzer, driven by the sound timer. See `sdl::SdlAudio` for the default implementation.
+pub trait AudioSink {
+    /// Start or stop the tone, called on every timer tick.
+    fn set_playing(&mut self, playing: bool);
+
+    /// Play the XO-CHIP audio pattern at the rate given by pitch instead of the default tone.
+    fn set_pattern(&mut self, _pattern: &[u8; PATTERN_BYTES], _pitch: u8) {}
+
+    /// Change the playback rate of the audio pattern, see pattern_rate.
+    fn set_pitch(&mut self, _pitch: u8) {}
+
+    /// Silence the buzzer without touching the sound timer, e.g. for a mute hotkey.
+    fn set_muted(&mut self, _muted: bool) {}
+
+    /// Loudness from 0.0 to 1.0, values outside are clamped.
+    fn set_volume(&mut self, _volume: f32) {}
+}
+
+/// Silent buzzer, for running the VM headless.
+pub struct NullAudio;
+
+impl AudioSink for NullAudio {
+    fn set_playing(&mut self, _playing: bool) {}
+}
+
+/// Shape of the buzzer tone.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Waveform {
+    #[default]
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+impl Waveform {
+    /// Look up a waveform by its config name, e.g. "sine".
+    pub fn by_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "square" => Some(Self::Square),
+            "triangle" => Some(Self::Triangle),
+            "sawtooth" => Some(Self::Sawtooth),
+            "sine" => Some(Self::Sine),
+            _ => None,
+        }
+    }
+
+    /// Sample in -1.0..=1.0 at phase, 0.0..1.0 through the cycle.
+    pub fn sample(self, phase: f32) -> f32 {
+        match self {
+            Self::Square => {
+                if phase <= 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Self::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Self::Sawtooth => 2.0 * phase - 1.0,
+            Self::Sine => (phase * std::f32::consts::TAU).sin(),
+        }
+    }
+}
+
+// attack and release time of the buzzer, long enough to avoid clicks, short enough to sound
+// instant
+const RAMP_SECONDS: f32 = 0.005;
+
+/// Buzzer waveform generator, plays the XO-CHIP audio pattern on repeat once one is set,
+/// otherwise a plain tone. Starts silent, set_playing fades it in and out.
+pub struct PatternWave {
+    waveform: Waveform,
     phase_inc: f32,
     phase: f32,
     volume: f32,
+    muted: bool,
+    // envelope, amplitude steps toward 1.0 while playing and 0.0 otherwise
+    playing: bool,
+    amplitude: f32,
+    sample_rate: f32,
+    pattern: Option<[f32; PATTERN_SAMPLES]>,
+    // position in the pattern, in samples, and how far it moves per output sample
+    pattern_phase: f32,
+    pattern_phase_inc: f32,
 }
 
-impl SquareWave {
-    pub fn new(phase_inc: f32, phase:f32, volume: f32) -> Self {
+impl PatternWave {
+    pub fn new(sample_rate: i32, phase_inc: f32, phase: f32, volume: f32) -> Self {
         Self {
+            waveform: Waveform::Square,
             phase_inc,
             phase,
-            volume,
+            volume: volume.clamp(0.0, 1.0),
+            muted: false,
+            playing: false,
+            amplitude: 0.0,
+            sample_rate: sample_rate as f32,
+            pattern: None,
+            pattern_phase: 0.0,
+            pattern_phase_inc: 0.0,
         }
     }
-}
 
-impl sdl2::audio::AudioCallback for SquareWave {
-    type Channel = f32;
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        self.waveform = waveform;
+    }
+
+    /// Pitch of the plain tone in Hz.
+    pub fn set_frequency(&mut self, freq: f32) {
+        self.phase_inc = freq / self.sample_rate;
+    }
+
+    /// Clamped to 0.0..=1.0.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    /// Fade the tone in or out over RAMP_SECONDS.
+    pub fn set_playing(&mut self, playing: bool) {
+        self.playing = playing;
+    }
+
+    // next step of the envelope
+    fn envelope(&mut self) -> f32 {
+        let target = if self.playing { 1.0 } else { 0.0 };
+        let step = 1.0 / (RAMP_SECONDS * self.sample_rate);
+        self.amplitude = if self.amplitude < target {
+            (self.amplitude + step).min(target)
+        } else {
+            (self.amplitude - step).max(target)
+        };
+        self.amplitude
+    }
+
+    /// Replace the tone with the XO-CHIP pattern, played back at the rate given by pitch.
+    pub fn set_pattern(&mut self, buffer: &[u8; PATTERN_BYTES], pitch: u8) {
+        self.pattern = Some(pattern_to_samples(buffer));
+        self.set_pitch(pitch);
+    }
+
+    pub fn set_pitch(&mut self, pitch: u8) {
+        self.pattern_phase_inc = pattern_rate(pitch) / self.sample_rate;
+    }
+
+    /// Fill out with the next samples of the waveform.
+    pub fn fill(&mut self, out: &mut [f32]) {
+        let volume = if self.muted { 0.0 } else { self.volume };
+        if let Some(pattern) = self.pattern {
+            for x in out.iter_mut() {
+                let amplitude = self.envelope();
+                *x = pattern[self.pattern_phase as usize] * volume * amplitude;
+                self.pattern_phase =
+                    (self.pattern_phase + self.pattern_phase_inc) % PATTERN_SAMPLES as f32;
+            }
+            return;
+        }
 
-    fn callback(&mut self, out: &mut [f32]) {
         for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
+            let amplitude = self.envelope();
+            *x = self.waveform.sample(self.phase) * volume * amplitude;
             self.phase = (self.phase + self.phase_inc) % 1.0;
         }
     }
-}
\ No newline at end of file
+}
+
+/// Expand the 1-bit pattern into full scale samples, set bits are high.
+pub fn pattern_to_samples(buffer: &[u8; PATTERN_BYTES]) -> [f32; PATTERN_SAMPLES] {
+    let mut samples = [0.0; PATTERN_SAMPLES];
+    for (idx, sample) in samples.iter_mut().enumerate() {
+        let bit = buffer[idx / 8] >> (7 - idx % 8) & 1;
+        *sample = if bit == 1 { 1.0 } else { -1.0 };
+    }
+    samples
+}
+
+/// Pattern playback rate in samples per second for the XO-CHIP pitch register.
+pub fn pattern_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // start the tone and run past the attack
+    fn playing(mut wave: PatternWave) -> PatternWave {
+        wave.set_playing(true);
+        wave.fill(&mut [0.0; 1024]);
+        wave
+    }
+
+    #[test]
+    fn waveform_samples() {
+        let at = |wave: Waveform| [0.0, 0.25, 0.5, 0.75].map(|phase| wave.sample(phase));
+        assert_eq!(at(Waveform::Square), [1.0, 1.0, 1.0, -1.0]);
+        assert_eq!(at(Waveform::Triangle), [-1.0, 0.0, 1.0, 0.0]);
+        assert_eq!(at(Waveform::Sawtooth), [-1.0, -0.5, 0.0, 0.5]);
+        let sine = at(Waveform::Sine);
+        for (sample, expected) in sine.into_iter().zip([0.0, 1.0, 0.0, -1.0]) {
+            assert!((sample - expected).abs() < 1e-6, "{sine:?}");
+        }
+
+        assert_eq!(Waveform::by_name("Sine"), Some(Waveform::Sine));
+        assert_eq!(Waveform::by_name("sawtooth"), Some(Waveform::Sawtooth));
+        assert_eq!(Waveform::by_name("noise"), None);
+    }
+
+    #[test]
+    fn every_waveform_stays_within_the_volume() {
+        for waveform in [
+            Waveform::Square,
+            Waveform::Sine,
+            Waveform::Triangle,
+            Waveform::Sawtooth,
+        ] {
+            let mut wave = PatternWave::new(44100, 440.0 / 44100.0, 0.0, 0.5);
+            wave.set_waveform(waveform);
+            let mut wave = playing(wave);
+            let mut out = [0.0; 1024];
+            wave.fill(&mut out);
+            assert!(out.iter().all(|x| x.abs() <= 0.5), "{waveform:?}");
+            if waveform == Waveform::Square {
+                assert!(out.iter().all(|&x| x == 0.5 || x == -0.5));
+            }
+        }
+    }
+
+    #[test]
+    fn volume_is_clamped_and_mute_silences() {
+        let mut wave = playing(PatternWave::new(44100, 440.0 / 44100.0, 0.0, 2.0));
+        let mut out = [0.0; 64];
+        wave.fill(&mut out);
+        assert!(out.iter().all(|x| x.abs() <= 1.0));
+        assert!(out.contains(&1.0));
+
+        wave.set_volume(0.5);
+        wave.fill(&mut out);
+        assert!(out.iter().all(|x| x.abs() <= 0.5));
+
+        wave.set_muted(true);
+        wave.fill(&mut out);
+        assert!(out.iter().all(|&x| x == 0.0));
+        wave.set_muted(false);
+        wave.set_volume(-1.0);
+        wave.fill(&mut out);
+        assert!(out.iter().all(|&x| x == 0.0));
+    }
+
+    #[test]
+    fn frequency_sets_the_tone_period() {
+        // 11025Hz at 44100 samples a second repeats every 4 samples
+        let mut wave = PatternWave::new(44100, 0.0, 0.0, 1.0);
+        wave.set_frequency(11025.0);
+        let mut wave = playing(wave);
+        let mut out = [0.0; 8];
+        wave.fill(&mut out);
+        assert_eq!(out, [1.0, 1.0, 1.0, -1.0, 1.0, 1.0, 1.0, -1.0]);
+    }
+
+    #[test]
+    fn start_and_stop_ramp_without_jumps() {
+        // constant full-scale tone so the samples are the envelope itself
+        let mut wave = PatternWave::new(44100, 0.0, 0.0, 1.0);
+        let mut out = [0.0; 512];
+        wave.fill(&mut out);
+        assert!(out.iter().all(|&x| x == 0.0));
+
+        let ramp = (RAMP_SECONDS * 44100.0).ceil() as usize;
+        wave.set_playing(true);
+        wave.fill(&mut out);
+        assert!(out[0] > 0.0 && out[0] < 0.01);
+        assert!(out.windows(2).all(|w| w[1] >= w[0] && w[1] - w[0] < 0.01));
+        assert!(out[ramp..].iter().all(|&x| x == 1.0));
+
+        wave.set_playing(false);
+        wave.fill(&mut out);
+        assert!(out[0] < 1.0 && out[0] > 0.99);
+        assert!(out.windows(2).all(|w| w[1] <= w[0] && w[0] - w[1] < 0.01));
+        assert!(out[ramp..].iter().all(|&x| x == 0.0));
+    }
+}
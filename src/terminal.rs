@@ -0,0 +1,100 @@
+//! Terminal renderer: draws the screen with half block characters and 24-bit ANSI colors,
+//! for watching a ROM run over SSH.
+use std::io::Write;
+
+use crate::display::{Renderer, Screen, DEFAULT_PALETTE};
+
+// upper half block, the foreground color paints the top pixel and the background the bottom one
+const UPPER_HALF: char = '▀';
+
+/// Redraws every frame from the top-left corner, two pixel rows per terminal line.
+/// Lines are cleared to their end, so resizing the terminal only leaves blank space.
+pub struct TerminalRenderer<W: Write> {
+    out: W,
+    palette: [[u8; 3]; 4],
+    cleared: bool,
+}
+
+impl<W: Write> TerminalRenderer<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            palette: DEFAULT_PALETTE,
+            cleared: false,
+        }
+    }
+}
+
+impl<W: Write> Renderer for TerminalRenderer<W> {
+    fn render(&mut self, screen: &Screen) -> Result<(), String> {
+        let mut frame = String::new();
+        if !self.cleared {
+            frame.push_str("\x1b[2J");
+            self.cleared = true;
+        }
+        frame.push_str("\x1b[H");
+        for y in (0..screen.height()).step_by(2) {
+            for x in 0..screen.width() {
+                let [tr, tg, tb] = self.palette[screen.pixel_color(x, y)];
+                let [br, bg, bb] = self.palette[screen.pixel_color(x, y + 1)];
+                frame.push_str(&format!(
+                    "\x1b[38;2;{tr};{tg};{tb}m\x1b[48;2;{br};{bg};{bb}m{UPPER_HALF}"
+                ));
+            }
+            frame.push_str("\x1b[0m\x1b[K\r\n");
+        }
+        frame.push_str("\x1b[J");
+        self.out
+            .write_all(frame.as_bytes())
+            .and_then(|()| self.out.flush())
+            .map_err(|e| e.to_string())
+    }
+
+    fn set_palette(&mut self, palette: [[u8; 3]; 4]) {
+        self.palette = palette;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::Theme;
+
+    #[test]
+    fn packs_two_rows_per_line() {
+        let mut out = Vec::new();
+        let mut screen = Screen::new();
+        TerminalRenderer::new(&mut out).render(&screen).unwrap();
+        let frame = String::from_utf8(out).unwrap();
+        assert!(frame.starts_with("\x1b[2J\x1b[H"));
+        assert_eq!(frame.matches(UPPER_HALF).count(), 64 * 16);
+        assert_eq!(frame.matches("\r\n").count(), 16);
+
+        screen.set_hires(true);
+        let mut out = Vec::new();
+        TerminalRenderer::new(&mut out).render(&screen).unwrap();
+        let frame = String::from_utf8(out).unwrap();
+        assert_eq!(frame.matches(UPPER_HALF).count(), 128 * 32);
+    }
+
+    #[test]
+    fn lit_pixels_use_fg_and_unlit_use_bg() {
+        let amber = Theme::by_name("Amber").unwrap();
+        let mut palette = DEFAULT_PALETTE;
+        palette[0] = amber.bg;
+        palette[1] = amber.fg;
+
+        // top-left pixel lit, the one below it unlit
+        let mut screen = Screen::new();
+        screen.xor_pixel(0, 0, 0, true);
+        let mut out = Vec::new();
+        let mut renderer = TerminalRenderer::new(&mut out);
+        renderer.set_palette(palette);
+        renderer.render(&screen).unwrap();
+        let frame = String::from_utf8(out).unwrap();
+        let first = frame.split(UPPER_HALF).next().unwrap();
+        assert!(first.ends_with("\x1b[38;2;255;176;0m\x1b[48;2;28;16;0m"));
+        assert_eq!(frame.matches("38;2;255;176;0m").count(), 1);
+        assert_eq!(frame.matches("38;2;28;16;0m").count(), 64 * 16 - 1);
+    }
+}
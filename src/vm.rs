@@ -1,13 +1,32 @@
-use rand::random;
-use sdl2::{render::Canvas, video::Window};
+use std::{
+    collections::HashSet,
+    fmt,
+    ops::Range,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use crate::{display::Screen, SquareWave};
+use serde::Deserialize;
+
+use crate::{
+    disasm,
+    display::{NullRenderer, Renderer, Screen},
+    error::Chip8Error,
+    input::{InputSource, KEY_COUNT},
+    opcode::Opcode,
+    rpl::{self, RPL_FLAG_COUNT},
+    speaker::{AudioSink, NullAudio, DEFAULT_PITCH, PATTERN_BYTES},
+};
+
+mod state;
+
+pub use state::{STATE_MAGIC, STATE_VERSION};
 
 /// http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
 pub struct VM {
-    // 4KB (4,096 bytes) of RAM, from location 0x000 (0) to 0xFFF (4095)
+    // 4KB (4,096 bytes) of RAM, from location 0x000 (0) to 0xFFF (4095), 64KB for XO-CHIP
     // 0x000 to 0x1FF (512b) reserved for original interpreter, should not be used by programs
-    ram: [u8; 4096],
+    ram: Vec<u8>,
     // 16 general purpose 8-bit registers
     // usually referred to as Vx, where x is a hexadecimal digit (0 through F)
     registers: [u8; 16],
@@ -19,7 +38,7 @@ pub struct VM {
     dt: u8,
     st: u8, // as long as ST's value is greater than zero, the Chip-8 buzzer will sound
     // program counter (PC), stores the currently executing address
-    pub pc: u16,
+    pc: u16,
     // stack pointer (SP) can be 8-bit, it is used to point to the topmost level of the stack
     sp: usize,
     // array of 16 u16 values,
@@ -28,13 +47,200 @@ pub struct VM {
     stack: [u16; 16],
     // 64x32-pixel monochrome display with this format
     pub display: Screen,
-    speaker: sdl2::audio::AudioDevice<SquareWave>,
+    renderer: Box<dyn Renderer>,
+    audio: Box<dyn AudioSink>,
+    // XO-CHIP audio pattern, loaded by F002
+    audio_buffer: [u8; PATTERN_BYTES],
+    // playback rate of the audio pattern, set by FX3A
+    pitch: u8,
     // Keyboard was 16 keys
-    keys: [bool; 16],
+    keys: [bool; KEY_COUNT],
+    // SCHIP RPL user flags, persisted to rpl_path so they survive between sessions
+    rpl_flags: [u8; RPL_FLAG_COUNT],
+    rpl_path: Option<PathBuf>,
+    // see VmBuilder::skip_unknown_opcodes
+    skip_unknown_opcodes: bool,
+    // unknown opcodes skipped since the last take_skipped_opcodes, one entry per address
+    skipped_opcodes: Vec<TraceEntry>,
+    // interpreter-specific behaviors, see Quirks
+    pub quirks: Quirks,
+    compat: CompatMode,
+    // CXKK's 64-bit LCG, started from seed so a run can be replayed exactly
+    rng_state: u64,
+    seed: u64,
+    // address of the small font, the SCHIP large font follows it
+    font_start: u16,
+    // debugger time stop, DT and ST hold their values while set
+    timers_frozen: bool,
+    // set by DXYN with the display_wait quirk, execution stalls until the next timer tick
+    waiting_for_vblank: bool,
+    // set by a 1NNN that jumps to itself, run_frame skips the rest of the frame
+    idle: bool,
+    // key FX0A saw go down, stored once it is released
+    pending_key: Option<u8>,
+    // instructions executed since the VM was built, not rolled back by reset or load_state
+    cycle_count: u64,
+    // addresses run_frame stops at
+    breakpoints: HashSet<u16>,
+    // last program loaded, kept for reset and disassembly
+    rom: Vec<u8>,
+    // ring buffer of the last HISTORY_LEN instructions, history_head is the next slot written
+    history: [TraceEntry; HISTORY_LEN],
+    history_head: usize,
+    history_len: usize,
+}
+
+// executed instructions kept for crash analysis
+pub const HISTORY_LEN: usize = 256;
+
+/// An executed instruction, see VM::history_iter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub opcode: u16,
+}
+
+/// How VM::run_frame ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameEnd {
+    Complete,
+    // PC reached this breakpoint, the rest of the frame didn't run
+    Breakpoint(u16),
+}
+
+/// CHIP-8 variant the VM is configured for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompatMode {
+    #[default]
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl CompatMode {
+    /// Bytes of RAM, XO-CHIP programs can address all 64KB through F000 NNNN.
+    pub fn ram_size(self) -> usize {
+        match self {
+            Self::Chip8 | Self::SuperChip => 0x1000,
+            Self::XoChip => 0x10000,
+        }
+    }
+
+    /// Quirks matching the variant's reference interpreter.
+    pub fn default_quirks(self) -> Quirks {
+        match self {
+            Self::Chip8 => Quirks::default(),
+            Self::SuperChip => Quirks {
+                shift_uses_vy: false,
+                load_store_increments_i: false,
+                jump_with_vx: true,
+                vf_reset_on_bitwise: false,
+                clip_sprites: true,
+                display_wait: false,
+                wait_for_key_release: true,
+            },
+            Self::XoChip => Quirks {
+                shift_uses_vy: true,
+                load_store_increments_i: true,
+                jump_with_vx: false,
+                vf_reset_on_bitwise: false,
+                clip_sprites: false,
+                display_wait: false,
+                wait_for_key_release: true,
+            },
+        }
+    }
+}
+
+/// Behaviors that differ between CHIP-8 interpreters and variants.
+/// Defaults to the original COSMAC VIP interpretation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Quirks {
+    /// Source register of 8XY6/8XYE.
+    /// `true`: COSMAC VIP, Vy is shifted and the result stored in Vx.
+    /// `false`: CHIP-48/SCHIP, Vx is shifted in place and Vy is ignored.
+    pub shift_uses_vy: bool,
+    /// Whether FX55/FX65 leave I pointing past the last register transferred.
+    /// `true`: COSMAC VIP, I is incremented by x + 1.
+    /// `false`: CHIP-48/SCHIP, I is unchanged.
+    #[serde(alias = "i_increment")]
+    pub load_store_increments_i: bool,
+    /// Register added to the jump address by BNNN.
+    /// `false`: original CHIP-8, jump to nnn + V0.
+    /// `true`: CHIP-48/SCHIP, instruction is read as BXNN and jumps to XNN + VX.
+    pub jump_with_vx: bool,
+    /// Whether 8XY1/8XY2/8XY3 (OR, AND, XOR) reset VF to 0.
+    /// `true`: COSMAC VIP, `false`: CHIP-48 and later.
+    pub vf_reset_on_bitwise: bool,
+    /// What happens to sprite pixels past the right or bottom edge.
+    /// `true`: clipped, as on the COSMAC VIP and SCHIP. Only the starting position wraps.
+    /// `false`: wrapped around to the opposite edge, as on XO-CHIP.
+    pub clip_sprites: bool,
+    /// Whether DXYN waits for the next 60Hz timer tick (vblank) before execution continues.
+    /// `true`: COSMAC VIP, limits drawing to one sprite per frame.
+    /// `false`: CHIP-48 and later, execution continues immediately.
+    pub display_wait: bool,
+    /// When FX0A completes.
+    /// `true`: COSMAC VIP, once the pressed key is released.
+    /// `false`: as soon as any key is down, so a held key can satisfy several FX0A in a row.
+    pub wait_for_key_release: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_with_vx: false,
+            vf_reset_on_bitwise: true,
+            clip_sprites: true,
+            display_wait: true,
+            wait_for_key_release: true,
+        }
+    }
+}
+
+impl Quirks {
+    /// Named combination of every quirk, case insensitive:
+    ///
+    /// | quirk                   | vip | schip | xochip | modern |
+    /// |-------------------------|-----|-------|--------|--------|
+    /// | shift_uses_vy           | yes | no    | yes    | no     |
+    /// | load_store_increments_i | yes | no    | yes    | no     |
+    /// | jump_with_vx            | no  | yes   | no     | no     |
+    /// | vf_reset_on_bitwise     | yes | no    | no     | no     |
+    /// | clip_sprites            | yes | yes   | no     | yes    |
+    /// | display_wait            | yes | no    | no     | no     |
+    /// | wait_for_key_release    | yes | yes   | yes    | yes    |
+    ///
+    /// vip, schip and xochip are the variants' defaults. modern is what most CHIP-8 games
+    /// written since the CHIP-48 expect, without SCHIP's BXNN.
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name.to_ascii_lowercase().as_str() {
+            "vip" => Some(CompatMode::Chip8.default_quirks()),
+            "schip" => Some(CompatMode::SuperChip.default_quirks()),
+            "xochip" => Some(CompatMode::XoChip.default_quirks()),
+            "modern" => Some(Quirks {
+                shift_uses_vy: false,
+                load_store_increments_i: false,
+                jump_with_vx: false,
+                vf_reset_on_bitwise: false,
+                clip_sprites: true,
+                display_wait: false,
+                wait_for_key_release: true,
+            }),
+            _ => None,
+        }
+    }
 }
 
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
+// SCHIP extended screen mode
+pub const HIRES_SCREEN_WIDTH: usize = 128;
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
 
 const SPRITE_ZERO: [u8; 5] = [0xF0, 0x90, 0x90, 0x90, 0xF0];
 const SPRITE_ONE: [u8; 5] = [0x20, 0x60, 0x20, 0x20, 0x70];
@@ -73,16 +279,111 @@ const SPRITES: [u8; 80] = [
     SPRITE_F[0], SPRITE_F[1], SPRITE_F[2], SPRITE_F[3], SPRITE_F[4],
     ];
 
-impl VM {
-    pub fn new(canvas: Canvas<Window>, audio_device: sdl2::audio::AudioDevice<SquareWave>) -> Self {
-        let mut ram = [0; 4096];
+// SCHIP 8x10 font for digits 0-9, stored right after the small font
+
+#[rustfmt::skip]
+const LARGE_SPRITES: [u8; 100] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    ];
+
+/// Configures and constructs a VM, see `VM::builder()`.
+#[derive(Default)]
+pub struct VmBuilder {
+    quirks: Option<Quirks>,
+    compat: CompatMode,
+    random_seed: Option<u64>,
+    renderer: Option<Box<dyn Renderer>>,
+    audio: Option<Box<dyn AudioSink>>,
+    font_start: u16,
+    // None until set, then Some(None) keeps the flags in memory only
+    rpl_path: Option<Option<PathBuf>>,
+    skip_unknown_opcodes: bool,
+}
+
+impl VmBuilder {
+    /// Override the quirks, otherwise the compat mode's defaults are used.
+    pub fn quirks(mut self, q: Quirks) -> Self {
+        self.quirks = Some(q);
+        self
+    }
+
+    pub fn compat(mut self, m: CompatMode) -> Self {
+        self.compat = m;
+        self
+    }
+
+    /// Seed CXKK's random number generator for reproducible runs.
+    pub fn random_seed(mut self, s: u64) -> Self {
+        self.random_seed = Some(s);
+        self
+    }
+
+    pub fn display(mut self, d: impl Renderer + 'static) -> Self {
+        self.renderer = Some(Box::new(d));
+        self
+    }
+
+    /// Buzzer output, silent if not set.
+    pub fn audio(mut self, a: impl AudioSink + 'static) -> Self {
+        self.audio = Some(Box::new(a));
+        self
+    }
+
+    /// Address the fonts are loaded at, both must fit below 0x200. Defaults to 0x000.
+    pub fn font_start(mut self, addr: u16) -> Self {
+        self.font_start = addr;
+        self
+    }
+
+    /// File the SCHIP RPL flags are loaded from and saved to, None keeps them in memory only.
+    /// Defaults to rpl::default_path.
+    pub fn rpl_path(mut self, path: impl Into<Option<PathBuf>>) -> Self {
+        self.rpl_path = Some(path.into());
+        self
+    }
 
-        // TODO(aalhendi): maybe a faster way for this
-        for (i, &byte) in SPRITES.iter().enumerate() {
-            ram[i] = byte;
+    /// Carry on past undefined instructions like NOPs, rather than returning UnknownOpcode
+    /// from step. They are reported through VM::take_skipped_opcodes instead. Off by default.
+    pub fn skip_unknown_opcodes(mut self, skip: bool) -> Self {
+        self.skip_unknown_opcodes = skip;
+        self
+    }
+
+    pub fn build(self) -> Result<VM, Chip8Error> {
+        let renderer = self
+            .renderer
+            .ok_or_else(|| Chip8Error::InvalidConfig("a display is required".to_string()))?;
+
+        let font_start = self.font_start as usize;
+        let font_end = font_start + SPRITES.len() + LARGE_SPRITES.len();
+        if font_end > 0x200 {
+            return Err(Chip8Error::InvalidConfig(format!(
+                "fonts at 0x{font_start:03X} would overlap the program area at 0x200"
+            )));
         }
 
-        Self {
+        let ram = initial_ram(self.font_start, self.compat.ram_size());
+
+        let rpl_path = self.rpl_path.unwrap_or_else(rpl::default_path);
+        let rpl_flags = rpl_path.as_deref().and_then(rpl::load).unwrap_or_default();
+
+        // unseeded VMs differ run to run, the clock is random enough for games
+        let seed = self.random_seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64)
+        });
+
+        Ok(VM {
             ram,
             registers: [0; 16],
             i: 0,
@@ -91,30 +392,358 @@ impl VM {
             pc: 0x200,
             sp: 0,
             stack: [0; 16],
-            display: Screen::new(canvas),
-            keys: [false; 16],
-            speaker: audio_device
+            display: Screen::new(),
+            renderer,
+            keys: [false; KEY_COUNT],
+            audio: self.audio.unwrap_or_else(|| Box::new(NullAudio)),
+            audio_buffer: [0; PATTERN_BYTES],
+            pitch: DEFAULT_PITCH,
+            quirks: self.quirks.unwrap_or(self.compat.default_quirks()),
+            compat: self.compat,
+            rng_state: seed,
+            seed,
+            font_start: self.font_start,
+            timers_frozen: false,
+            waiting_for_vblank: false,
+            idle: false,
+            pending_key: None,
+            cycle_count: 0,
+            breakpoints: HashSet::new(),
+            rom: Vec::new(),
+            history: [TraceEntry::default(); HISTORY_LEN],
+            history_head: 0,
+            history_len: 0,
+            rpl_flags,
+            rpl_path,
+            skip_unknown_opcodes: self.skip_unknown_opcodes,
+            skipped_opcodes: Vec::new(),
+        })
+    }
+}
+
+/// RAM with the small font at font_start and the large font right after it.
+/// VmBuilder::build checks that both fit below 0x200.
+fn initial_ram(font_start: u16, size: usize) -> Vec<u8> {
+    let font_start = font_start as usize;
+    let large_start = font_start + SPRITES.len();
+    let mut ram = vec![0; size];
+    ram[font_start..large_start].copy_from_slice(&SPRITES);
+    ram[large_start..large_start + LARGE_SPRITES.len()].copy_from_slice(&LARGE_SPRITES);
+    ram
+}
+
+impl VM {
+    pub fn builder() -> VmBuilder {
+        VmBuilder::default()
+    }
+
+    /// VM with no display or audio that never touches the RPL flags file, for tests and tools.
+    pub fn new_headless() -> VM {
+        Self::headless(VM::builder())
+    }
+
+    /// new_headless with CXKK seeded, two VMs with the same seed and input run identically.
+    pub fn new_with_seed(seed: u64) -> VM {
+        Self::headless(VM::builder().random_seed(seed))
+    }
+
+    fn headless(builder: VmBuilder) -> VM {
+        builder
+            .display(NullRenderer)
+            .rpl_path(None)
+            .build()
+            .expect("default fonts fit below 0x200")
+    }
+
+    /// Copy a program into RAM at 0x200. It must hold at least one instruction.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        if rom.len() < 2 {
+            return Err(Chip8Error::RomTooSmall(rom.len()));
         }
+        let max = self.ram.len() - 0x200;
+        if rom.len() > max {
+            return Err(Chip8Error::RomTooLarge {
+                size: rom.len(),
+                max,
+            });
+        }
+        self.ram[0x200..0x200 + rom.len()].copy_from_slice(rom);
+        self.rom = rom.to_vec();
+        Ok(())
     }
 
-    pub fn load_rom(&mut self, rom: &[u8]) {
-        let len = rom.len();
-        self.ram[0x200..0x200 + len].copy_from_slice(rom);
+    /// Restart the loaded program as if the machine was switched off and on again.
+    /// RAM, registers, timers, stack and screen are cleared and the RNG goes back to its seed.
+    /// Settings, breakpoints and RPL flags are kept.
+    pub fn reset(&mut self) {
+        self.ram = initial_ram(self.font_start, self.ram.len());
+        self.ram[0x200..0x200 + self.rom.len()].copy_from_slice(&self.rom);
+        self.registers = [0; 16];
+        self.i = 0;
+        self.dt = 0;
+        self.st = 0;
+        self.pc = 0x200;
+        self.sp = 0;
+        self.stack = [0; 16];
+        self.display = Screen::new();
+        self.audio.set_playing(false);
+        self.audio_buffer = [0; PATTERN_BYTES];
+        self.pitch = DEFAULT_PITCH;
+        self.rng_state = self.seed;
+        self.waiting_for_vblank = false;
+        self.idle = false;
+        self.pending_key = None;
+        self.history_head = 0;
+        self.history_len = 0;
     }
 
     pub fn tick_timers(&mut self) {
+        self.waiting_for_vblank = false;
+        self.idle = false;
+        if self.timers_frozen {
+            return;
+        }
         if self.dt > 0 {
             self.dt -= 1;
         }
 
+        self.audio.set_playing(self.st > 0);
         if self.st > 0 {
-            self.speaker.resume();
             self.st -= 1;
-        } else {
-            self.speaker.pause();
         }
     }
 
+    /// Execute one 60Hz frame: up to cycles instructions, then a timer tick.
+    /// Stops early if a draw is waiting for vblank, see Quirks::display_wait, or if the program
+    /// is spinning on a jump to itself, which would only repeat until the next tick.
+    /// If PC reaches a breakpoint the frame ends right away, without ticking the timers.
+    pub fn run_frame(&mut self, cycles: u32) -> Result<FrameEnd, Chip8Error> {
+        for _ in 0..cycles {
+            if self.waiting_for_vblank || self.idle {
+                break;
+            }
+            self.step()?;
+            if self.breakpoints.contains(&self.pc) {
+                return Ok(FrameEnd::Breakpoint(self.pc));
+            }
+        }
+        self.tick_timers();
+        Ok(FrameEnd::Complete)
+    }
+
+    /// Listing of the loaded program as it is in RAM now, see disasm::disassemble_rom.
+    pub fn disassemble_rom(&self) -> Vec<(u16, String)> {
+        disasm::disassemble_rom(&self.ram[0x200..0x200 + self.rom.len()])
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Returns false if there was no breakpoint at addr.
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> &HashSet<u16> {
+        &self.breakpoints
+    }
+
+    /// Registers, timers, the stack and a summary of the display, for crash reports.
+    pub fn dump_state(&self) -> String {
+        let registers: Vec<String> = self
+            .registers
+            .iter()
+            .enumerate()
+            .map(|(x, v)| format!("V{x:X}=0x{v:02X}"))
+            .collect();
+        let stack: Vec<String> = self.stack[..self.sp]
+            .iter()
+            .map(|addr| format!("0x{addr:04X}"))
+            .collect();
+        format!(
+            "{}\nI=0x{:04X} PC=0x{:04X} SP={} DT={} ST={}\nstack: [{}]\nlit pixels: {}\nseed: {}",
+            registers.join(" "),
+            self.i,
+            self.pc,
+            self.sp,
+            self.dt,
+            self.st,
+            stack.join(", "),
+            self.display.lit_pixel_count(),
+            self.seed
+        )
+    }
+
+    /// Classic hex dump of len bytes from start, 16 per line with an ASCII column.
+    /// Clamped to the end of RAM.
+    pub fn dump_memory(&self, start: u16, len: u16) -> String {
+        let start = (start as usize).min(self.ram.len());
+        let end = (start + len as usize).min(self.ram.len());
+        let mut lines = Vec::new();
+        for (row, chunk) in self.ram[start..end].chunks(16).enumerate() {
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{byte:02X}")).collect();
+            let ascii: String = chunk
+                .iter()
+                .map(|&byte| {
+                    if byte.is_ascii_graphic() || byte == b' ' {
+                        byte as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            // pad a short last line so the ASCII column lines up
+            lines.push(format!(
+                "0x{:04X}: {:<47}  {ascii}",
+                start + row * 16,
+                hex.join(" ")
+            ));
+        }
+        lines.join("\n")
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        self.registers[x] = value;
+    }
+
+    /// Pixels of the current resolution in row-major order, lit if set on any plane.
+    pub fn get_display_pixels(&self) -> Vec<bool> {
+        let (width, height) = (self.display.width(), self.display.height());
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| self.display.pixel_color(x, y) != 0)
+            .collect()
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn set_i(&mut self, addr: u16) {
+        self.i = addr;
+    }
+
+    /// Stack levels in use.
+    pub fn sp(&self) -> usize {
+        self.sp
+    }
+
+    /// Return addresses on the stack, the innermost call last.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp]
+    }
+
+    pub fn dt(&self) -> u8 {
+        self.dt
+    }
+
+    pub fn st(&self) -> u8 {
+        self.st
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// Overwrite a byte of RAM, e.g. from a debugger's memory editor.
+    pub fn write_ram(&mut self, addr: u16, value: u8) -> Result<(), Chip8Error> {
+        let byte = self
+            .ram
+            .get_mut(addr as usize)
+            .ok_or(Chip8Error::MemoryOutOfBounds(addr))?;
+        *byte = value;
+        Ok(())
+    }
+
+    pub fn renderer_mut(&mut self) -> &mut dyn Renderer {
+        self.renderer.as_mut()
+    }
+
+    pub fn audio_mut(&mut self) -> &mut dyn AudioSink {
+        self.audio.as_mut()
+    }
+
+    /// Present the display through the configured renderer if it changed.
+    pub fn draw(&mut self) -> Result<(), String> {
+        self.display.draw(self.renderer.as_mut())
+    }
+
+    pub fn compat(&self) -> CompatMode {
+        self.compat
+    }
+
+    /// Stop DT and ST from counting down, so stepping through code doesn't let timers expire.
+    /// The buzzer keeps its current state while frozen.
+    pub fn freeze_timer(&mut self, freeze: bool) {
+        self.timers_frozen = freeze;
+    }
+
+    pub fn timers_frozen(&self) -> bool {
+        self.timers_frozen
+    }
+
+    /// Whether a draw is waiting for the next timer tick, see Quirks::display_wait.
+    /// The main loop can stop executing instructions for the frame once this is set.
+    pub fn waiting_for_vblank(&self) -> bool {
+        self.waiting_for_vblank
+    }
+
+    /// Instructions executed so far, the clock input recordings are timed by.
+    pub fn cycle_count(&self) -> u64 {
+        self.cycle_count
+    }
+
+    /// Whether the last frame ended on a jump to itself, see run_frame.
+    pub fn is_idle(&self) -> bool {
+        self.idle
+    }
+
+    /// Raw instruction at PC, without fetching it. None if PC is past the end of RAM.
+    pub fn current_instruction(&self) -> Option<u16> {
+        let pc = self.pc as usize;
+        let bytes = self.ram.get(pc..pc + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// Move PC to addr, which must be an even address inside RAM.
+    pub fn set_pc(&mut self, addr: u16) -> Result<(), Chip8Error> {
+        if addr as usize >= self.ram.len() {
+            return Err(Chip8Error::PcOutOfBounds(addr));
+        }
+        if !addr.is_multiple_of(2) {
+            return Err(Chip8Error::PcMisaligned(addr));
+        }
+        self.pc = addr;
+        Ok(())
+    }
+
+    /// Lowest numbered key currently held down, None if no key is pressed.
+    pub fn await_key(&self) -> Option<u8> {
+        self.keys
+            .iter()
+            .position(|&pressed| pressed)
+            .map(|key| key as u8)
+    }
+
+    /// Refresh the keypad from input.
+    pub fn poll_input(&mut self, input: &mut dyn InputSource) {
+        input.poll(&mut self.keys);
+    }
+
+    /// Which keypad keys are held down.
+    pub fn keys(&self) -> [bool; KEY_COUNT] {
+        self.keys
+    }
+
     pub fn set_key(&mut self, idx: usize, pressed: bool) {
         self.keys[idx] = pressed;
     }
@@ -126,9 +755,49 @@ impl VM {
 
     /// Return from a subroutine.
     /// interpreter sets PC to addr at top of the stack, subtracts 1 from the sp.
-    fn ret(&mut self) {
+    fn ret(&mut self) -> Result<(), Chip8Error> {
+        if self.sp == 0 {
+            return Err(Chip8Error::StackUnderflow);
+        }
         self.sp -= 1;
         self.pc = self.stack[self.sp];
+        Ok(())
+    }
+
+    /// Scroll display down n lines. (SCHIP)
+    /// Vacated lines at the top are cleared.
+    fn scd(&mut self, n: u8) {
+        self.display.scroll_down(n as usize);
+    }
+
+    /// Scroll display 4 pixels right. (SCHIP)
+    /// Vacated columns on the left are cleared.
+    fn scr(&mut self) {
+        self.display.scroll_right(4);
+    }
+
+    /// Scroll display 4 pixels left. (SCHIP)
+    /// Vacated columns on the right are cleared.
+    fn scl(&mut self) {
+        self.display.scroll_left(4);
+    }
+
+    /// Select drawing planes by bitmask n. (XO-CHIP)
+    /// Later draw, clear and scroll instructions only affect the selected planes.
+    fn sel_plane(&mut self, n: u8) {
+        self.display.select_planes(n);
+    }
+
+    /// Disable extended screen mode. (SCHIP)
+    /// Returns to the 64x32 display, clearing the screen.
+    fn low(&mut self) {
+        self.display.set_hires(false);
+    }
+
+    /// Enable extended screen mode. (SCHIP)
+    /// Switches to the 128x64 display, clearing the screen.
+    fn high(&mut self) {
+        self.display.set_hires(true);
     }
 
     /// Jump to a machine code routine at nnn.
@@ -139,23 +808,42 @@ impl VM {
 
     /// Jump to location nnn.
     /// The interpreter sets the program counter to nnn.
+    /// A jump to its own address is an idle loop, see run_frame.
     fn jp(&mut self, nnn: u16) {
+        // PC is already past the jump
+        if nnn == self.pc.wrapping_sub(2) {
+            self.idle = true;
+        }
         self.pc = nnn;
     }
 
     /// Call subroutine at nnn.
     /// interpreter increments sp, puts current PC on top of stack. PC is set to nnn.
-    fn call(&mut self, nnn: u16) {
+    fn call(&mut self, nnn: u16) -> Result<(), Chip8Error> {
+        if self.sp >= self.stack.len() {
+            return Err(Chip8Error::StackOverflow);
+        }
         self.stack[self.sp] = self.pc;
         self.sp += 1;
         self.pc = nnn;
+        Ok(())
+    }
+
+    /// Advance PC past the next instruction.
+    /// XO-CHIP's F000 NNNN is 4 bytes long, so skipping it moves PC by 4 instead of 2.
+    fn skip(&mut self) {
+        let pc = self.pc as usize;
+        let next_is_long =
+            pc + 1 < self.ram.len() && self.ram[pc] == 0xF0 && self.ram[pc + 1] == 0x00;
+        // PC wraps at the end of XO-CHIP's 64KB, as it does in Octo
+        self.pc = self.pc.wrapping_add(if next_is_long { 4 } else { 2 });
     }
 
     /// Skip next instruction if Vx = kk.
     /// interpreter compares register Vx to kk, if equal, increments pc by 2.
     fn se_vx_kk(&mut self, x: u8, kk: u8) {
         if self.registers[x as usize] == kk {
-            self.pc += 2;
+            self.skip();
         }
     }
 
@@ -163,7 +851,7 @@ impl VM {
     /// interpreter compares register Vx to kk, if not equal, increments pc by 2.
     fn sne_vx_kk(&mut self, x: u8, kk: u8) {
         if self.registers[x as usize] != kk {
-            self.pc += 2;
+            self.skip();
         }
     }
 
@@ -171,7 +859,7 @@ impl VM {
     /// interpreter compares register Vx to register Vy, if equal, increments pc by 2.
     fn se_vx_vy(&mut self, x: u8, y: u8) {
         if self.registers[x as usize] == self.registers[y as usize] {
-            self.pc += 2;
+            self.skip();
         }
     }
 
@@ -198,18 +886,28 @@ impl VM {
     /// Performs bitwise OR on Vx and Vy values, then stores the result in Vx.
     fn or_vx_vy(&mut self, x: u8, y: u8) {
         self.registers[x as usize] |= self.registers[y as usize];
+        self.reset_vf_on_bitwise();
     }
 
     /// Set Vx = Vx AND Vy.
     /// Performs bitwise AND on Vx and Vy values, then stores the result in Vx
     fn and_vx_vy(&mut self, x: u8, y: u8) {
         self.registers[x as usize] &= self.registers[y as usize];
+        self.reset_vf_on_bitwise();
     }
 
     /// Set Vx = Vx XOR Vy.
     /// Performs bitwise XOR on Vx and Vy values, then stores the result in Vx
     fn xor_vx_vy(&mut self, x: u8, y: u8) {
         self.registers[x as usize] ^= self.registers[y as usize];
+        self.reset_vf_on_bitwise();
+    }
+
+    /// COSMAC VIP side effect of OR, AND and XOR, see Quirks::vf_reset_on_bitwise.
+    fn reset_vf_on_bitwise(&mut self) {
+        if self.quirks.vf_reset_on_bitwise {
+            self.registers[0xF] = 0;
+        }
     }
 
     /// Set Vx = Vx + Vy, set VF = carry.
@@ -237,10 +935,21 @@ impl VM {
 
     /// Set Vx = Vx >> 1.
     /// If least-significant bit of Vx is 1, VF is set to 1, else 0. Vx is divided by 2.
-    fn shr_vx_vy(&mut self, x: u8, _y: u8) {
-        let lsb = self.registers[x as usize] & 1;
-        self.registers[0xF] = lsb;
-        self.registers[x as usize] >>= 1;
+    /// With the shift_uses_vy quirk, Vy is shifted instead and the result stored in Vx.
+    fn shr_vx_vy(&mut self, x: u8, y: u8) {
+        let source = self.registers[self.shift_source(x, y)];
+        // flag is written last so it wins when x is F
+        self.registers[x as usize] = source >> 1;
+        self.registers[0xF] = source & 1;
+    }
+
+    /// Register 8XY6/8XYE read from, see Quirks::shift_uses_vy.
+    fn shift_source(&self, x: u8, y: u8) -> usize {
+        if self.quirks.shift_uses_vy {
+            y as usize
+        } else {
+            x as usize
+        }
     }
 
     /// Set Vx = Vy - Vx, set VF = NOT borrow.
@@ -257,17 +966,18 @@ impl VM {
 
     /// Set Vx = Vx << 1.
     /// If most-significant bit of Vx is 1, VF is set to 1, else to 0. Vx is multiplied by 2.
-    fn shl_vx_vy(&mut self, x: u8, _y: u8) {
-        let msb = (self.registers[x as usize] >> 7) & 1;
-        self.registers[0xF] = msb;
-        self.registers[x as usize] <<= 1;
+    /// With the shift_uses_vy quirk, Vy is shifted instead and the result stored in Vx.
+    fn shl_vx_vy(&mut self, x: u8, y: u8) {
+        let source = self.registers[self.shift_source(x, y)];
+        self.registers[x as usize] = source << 1;
+        self.registers[0xF] = (source >> 7) & 1;
     }
 
     // Skip next instruction if Vx != Vy.
     // Vx and Vy values are compared, if not equal, increments pc by 2.
     fn sne_vx_vy(&mut self, x: u8, y: u8) {
         if self.registers[x as usize] != self.registers[y as usize] {
-            self.pc += 2;
+            self.skip();
         }
     }
 
@@ -277,69 +987,140 @@ impl VM {
         self.i = nnn;
     }
 
+    /// Set I = nnnn. (XO-CHIP)
+    /// 4-byte instruction F000 NNNN, allows I to address beyond 0xFFF.
+    fn ld_i_long(&mut self, nnnn: u16) {
+        self.i = nnnn;
+    }
+
     // Jump to location nnn + V0.
     // PC set to nnn plus V0 value.
+    // With the jump_with_vx quirk, the high nibble of nnn selects the register instead of V0.
     fn jp_v0_addr(&mut self, nnn: u16) {
-        self.pc = nnn + self.registers[0x0] as u16;
+        let offset_register = if self.quirks.jump_with_vx {
+            (nnn >> 8) as usize
+        } else {
+            0x0
+        };
+        self.pc = nnn + self.registers[offset_register] as u16;
     }
 
     // Set Vx = random byte AND kk.
     // interpreter generates random number from 0 to 255, ANDed value kk. The results are stored in Vx.
     fn rnd_vx_kk(&mut self, x: u8, kk: u8) {
-        let rng: u8 = random();
+        let rng = self.next_random();
         self.registers[x as usize] = rng & kk;
     }
 
+    /// Advance the LCG (Knuth's MMIX constants) and return its top byte,
+    /// the low bits of an LCG have short periods.
+    fn next_random(&mut self) -> u8 {
+        self.rng_state = self
+            .rng_state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.rng_state >> 56) as u8
+    }
+
+    /// Seed CXKK's generator started from, see VmBuilder::random_seed.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
     // Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
     // interpreter reads n bytes from memory, starting at the address stored in I.
     // bytes are then displayed as sprites on screen at coordinates (Vx, Vy).
     // Sprites are XORed onto existing screen. If this causes any pixels to be erased, VF is set to 1, else VF set to 0.
     // If sprite is positioned so part is outside the coordinates of the display, it wraps around to opposite side of screen.
-    fn drw_vx_vy_n(&mut self, x: u8, y: u8, n: u8) {
+    // With the clip_sprites quirk that part is not drawn instead, only the starting position wraps.
+    // SCHIP: in extended mode, n = 0 displays a 16x16 sprite read as 32 bytes, two bytes per row.
+    // XO-CHIP: each selected plane draws its own sprite, stored back to back starting at I.
+    fn drw_vx_vy_n(&mut self, x: u8, y: u8, n: u8) -> Result<(), Chip8Error> {
         // Reset VF register
         self.registers[0xF] = 0;
 
-        let x_pos = self.registers[x as usize] % SCREEN_WIDTH as u8;
-        let y_pos = self.registers[y as usize] % SCREEN_HEIGHT as u8;
-
-        for byte_index in 0..n {
-            let sprite_byte = self.ram[(self.i + byte_index as u16) as usize];
-            for bit_index in 0..8 {
-                let sprite_pixel = (sprite_byte >> (7 - bit_index)) & 1;
-                let x_coord = (x_pos as usize + bit_index as usize) % SCREEN_WIDTH;
-                let y_coord = (y_pos as usize + byte_index as usize) % SCREEN_HEIGHT;
-
-                // XOR sprite pixel with the existing pixel on the display
-                if sprite_pixel == 1 {
-                    // collision check
-                    if self.display.get_pixel_state(x_coord, y_coord) {
-                        self.registers[0xF] = 1;
+        let width = self.display.width();
+        let height = self.display.height();
+        let x_pos = self.registers[x as usize] as usize % width;
+        let y_pos = self.registers[y as usize] as usize % height;
+        let big_sprite = n == 0 && self.display.is_hires();
+        let sprite_len = if big_sprite { 32 } else { n as usize };
+
+        let planes: Vec<usize> = self.display.selected().collect();
+        // every sprite byte is read below, make sure they are all in RAM first
+        self.ram_range(self.i as usize, planes.len() * sprite_len)?;
+        for (idx, plane) in planes.into_iter().enumerate() {
+            let sprite_start = self.i as usize + idx * sprite_len;
+            if big_sprite {
+                for row in 0..16 {
+                    let addr = sprite_start + row * 2;
+                    let row_bits = u16::from_be_bytes([self.ram[addr], self.ram[addr + 1]]);
+                    let y_coord = y_pos + row;
+                    if y_coord >= height && self.quirks.clip_sprites {
+                        break;
                     }
-                    self.display.xor_pixel(x_coord, y_coord, true);
+                    self.draw_sprite_row(plane, y_coord % height, row_bits, x_pos);
+                }
+            } else {
+                for byte_index in 0..n {
+                    let sprite_byte = self.ram[sprite_start + byte_index as usize];
+                    let y_coord = y_pos + byte_index as usize;
+                    if y_coord >= height && self.quirks.clip_sprites {
+                        break;
+                    }
+                    // 8-pixel rows occupy the high byte, the trailing zero bits draw nothing
+                    let row_bits = (sprite_byte as u16) << 8;
+                    self.draw_sprite_row(plane, y_coord % height, row_bits, x_pos);
                 }
             }
         }
 
-        self.display.set_draw_flag(true);
+        self.display.mark_dirty();
+        self.waiting_for_vblank = self.quirks.display_wait;
+        Ok(())
+    }
+
+    /// XOR up to 16 sprite pixels onto row y_coord of plane, starting at column x_start.
+    /// Most-significant bit is the leftmost pixel. Sets VF if any lit pixel is erased.
+    fn draw_sprite_row(&mut self, plane: usize, y_coord: usize, row_bits: u16, x_start: usize) {
+        let width = self.display.width();
+        for bit_index in 0..16 {
+            let sprite_pixel = (row_bits >> (15 - bit_index)) & 1;
+            let x_coord = x_start + bit_index;
+            if x_coord >= width && self.quirks.clip_sprites {
+                break;
+            }
+            let x_coord = x_coord % width;
+
+            // XOR sprite pixel with the existing pixel on the display
+            if sprite_pixel == 1 {
+                // collision check
+                if self.display.get_pixel_state(plane, x_coord, y_coord) {
+                    self.registers[0xF] = 1;
+                }
+                self.display.xor_pixel(plane, x_coord, y_coord, true);
+            }
+        }
     }
 
     /// Skip next instruction if key with the value of Vx is pressed.
     /// Checks keyboard, if key equal to the value of Vx is currently in the down position, increments PC by 2.
     fn skp_vx(&mut self, x: u8) {
-        let vx = self.registers[x as usize] as usize;
+        // only the low nibble selects a key, as on the COSMAC VIP
+        let vx = self.registers[x as usize] as usize & 0xF;
         let key = self.keys[vx];
         if key {
-            self.pc += 2;
+            self.skip();
         }
     }
 
     /// Skip next instruction if key with the value of Vx is not pressed.
     /// Checks keyboard, if key equal to the value of Vx is currently in the up position, increments PC by 2.
     fn sknp_vx(&mut self, x: u8) {
-        let vx = self.registers[x as usize] as usize;
+        let vx = self.registers[x as usize] as usize & 0xF;
         let key = self.keys[vx];
         if !key {
-            self.pc += 2;
+            self.skip();
         }
     }
 
@@ -351,12 +1132,28 @@ impl VM {
 
     /// Wait for a key press, store the value of the key in Vx.
     /// All execution stops until a key is pressed, value of that key is stored in Vx.
+    /// With the wait_for_key_release quirk, the key is stored once it is released instead.
     fn ld_vx_k(&mut self, x: u8) {
-        if let Some((pressed_index, _)) = self.keys.iter().enumerate().find(|(_, &pressed)| pressed)
-        {
-            self.registers[x as usize] = pressed_index as u8;
-        } else {
-            self.pc -= 2;
+        if !self.quirks.wait_for_key_release {
+            match self.await_key() {
+                Some(key) => self.registers[x as usize] = key,
+                // re-run this instruction until a key is down
+                None => self.pc = self.pc.wrapping_sub(2),
+            }
+            return;
+        }
+
+        match self.pending_key {
+            Some(key) if !self.keys[key as usize] => {
+                self.registers[x as usize] = key;
+                self.pending_key = None;
+            }
+            _ => {
+                if self.pending_key.is_none() {
+                    self.pending_key = self.await_key();
+                }
+                self.pc = self.pc.wrapping_sub(2);
+            }
         }
     }
 
@@ -375,97 +1172,1361 @@ impl VM {
     /// Set I = I + Vx.
     /// I and Vx values are added, results are stored in I.
     fn add_i_vx(&mut self, x: u8) {
-        self.i += self.registers[x as usize] as u16;
+        self.i = self.i.wrapping_add(self.registers[x as usize] as u16);
     }
 
     /// Set I = location of sprite for digit Vx.
     /// value of I set to location for the hexadecimal sprite equal to the value of Vx.
     fn ld_f_vx(&mut self, x: u8) {
-        let digit = self.registers[x as usize] as usize;
-        self.i = (digit * 5) as u16;
+        // only the low nibble selects a digit, as on the COSMAC VIP
+        let digit = (self.registers[x as usize] & 0xF) as u16;
+        self.i = self.font_start + digit * 5;
+    }
+
+    /// Set I = location of large sprite for digit Vx. (SCHIP)
+    /// value of I set to location for the 10-byte decimal sprite equal to the value of Vx, 0 through 9.
+    fn ld_lf_vx(&mut self, x: u8) {
+        let digit = (self.registers[x as usize] % 10) as u16;
+        self.i = self.font_start + SPRITES.len() as u16 + digit * 10;
     }
 
     /// Store Binary-Coded Decimal (BCD) representation of Vx in memory locations I, I+1, and I+2.
     /// interpreter decimal value of Vx, places (in memory) hundreds digit at location I, tens I+1, ones I+2.
-    fn ld_b_vx(&mut self, x: u8) {
+    fn ld_b_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
         let vx = self.registers[x as usize];
         let hundreds = vx / 100;
         let tens = (vx / 10) % 10;
         let ones = vx % 10;
-        let i = self.i as usize;
-        self.ram[i] = hundreds;
-        self.ram[i + 1] = tens;
-        self.ram[i + 2] = ones;
+        let range = self.ram_range(self.i as usize, 3)?;
+        self.ram[range].copy_from_slice(&[hundreds, tens, ones]);
+        Ok(())
+    }
+
+    /// Store registers Vx through Vy in memory starting at location I. (XO-CHIP)
+    /// x and y may be given in either order. I is not modified.
+    fn ld_i_range(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        let regs = x.min(y) as usize..=x.max(y) as usize;
+        let range = self.ram_range(self.i as usize, regs.clone().count())?;
+        self.ram[range].copy_from_slice(&self.registers[regs]);
+        Ok(())
+    }
+
+    /// Read registers Vx through Vy from memory starting at location I. (XO-CHIP)
+    /// x and y may be given in either order. I is not modified.
+    fn ld_range_i(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        let regs = x.min(y) as usize..=x.max(y) as usize;
+        let range = self.ram_range(self.i as usize, regs.clone().count())?;
+        self.registers[regs].copy_from_slice(&self.ram[range]);
+        Ok(())
+    }
+
+    /// Load the audio pattern from memory starting at location I. (XO-CHIP)
+    /// 16 bytes are copied into the audio buffer and the buzzer plays them instead of the configured tone.
+    fn store_audio_buffer(&mut self) -> Result<(), Chip8Error> {
+        let range = self.ram_range(self.i as usize, PATTERN_BYTES)?;
+        self.audio_buffer.copy_from_slice(&self.ram[range]);
+        self.audio.set_pattern(&self.audio_buffer, self.pitch);
+        Ok(())
+    }
+
+    /// Set the audio pattern's playback rate to Vx, 64 plays it at 4000 samples a second. (XO-CHIP)
+    fn ld_pitch_vx(&mut self, x: u8) {
+        self.pitch = self.registers[x as usize];
+        self.audio.set_pitch(self.pitch);
     }
 
     /// Store registers V0 through Vx in memory starting at location I.
     /// interpreter copies values of registers V0 through Vx into memory, starting at the address in I.
-    fn ld_i_vx(&mut self, x: u8) {
-        let i = self.i as usize;
-        for idx in 0..=(x as usize) {
-            self.ram[i + idx] = self.registers[idx];
-        }
+    fn ld_i_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        let range = self.ram_range(self.i as usize, x as usize + 1)?;
+        self.ram[range].copy_from_slice(&self.registers[..=x as usize]);
+        self.increment_i_after_load_store(x);
+        Ok(())
     }
 
     /// Read registers V0 through Vx from memory starting at location I.
     /// interpreter reads values from memory starting at location I into registers V0 through Vx.
-    fn ld_vx_i(&mut self, x: u8) {
-        let i = self.i as usize;
-        for idx in 0..=(x as usize) {
-            self.registers[idx] = self.ram[i + idx];
-        }
-    }
-
-    pub fn decode(&mut self) {
-        let hi = self.ram[self.pc as usize] as u16;
-        let lo = self.ram[(self.pc + 1) as usize] as u16;
-        let instruction = (hi << 8) | lo;
-        self.pc += 2;
-        let n1 = instruction >> 12; // & 0x000F not needed, shift operation alone aligns the target bits to the rightmost position
-        let nnn = instruction & 0x0FFF;
-        let n2 = ((instruction >> 8) & 0x000F) as u8;
-        let n3 = ((instruction >> 4) & 0x000F) as u8;
-        let n4 = (instruction & 0x000F) as u8; // No shift needed, already aligned
-        let kk = (instruction & 0x00FF) as u8;
-        match n1 {
-            0x0 if nnn == 0x00E0 => self.cls(),
-            0x0 if nnn == 0x00EE => self.ret(),
-            0x0 => self.sys(nnn),
-            0x1 => self.jp(nnn),
-            0x2 => self.call(nnn),
-            0x3 => self.se_vx_kk(n2, kk),
-            0x4 => self.sne_vx_kk(n2, kk),
-            0x5 if n4 == 0x0 => self.se_vx_vy(n2, n3), // TODO(aalhendi): Check if last n check needed
-            0x6 => self.ld_vx_kk(n2, kk),
-            0x7 => self.add_vx_kk(n2, kk),
-            0x8 if n4 == 0x0 => self.ld_vx_vy(n2, n3),
-            0x8 if n4 == 0x1 => self.or_vx_vy(n2, n3),
-            0x8 if n4 == 0x2 => self.and_vx_vy(n2, n3),
-            0x8 if n4 == 0x3 => self.xor_vx_vy(n2, n3),
-            0x8 if n4 == 0x4 => self.add_vx_vy(n2, n3),
-            0x8 if n4 == 0x5 => self.sub_vx_vy(n2, n3),
-            0x8 if n4 == 0x6 => self.shr_vx_vy(n2, n3),
-            0x8 if n4 == 0x7 => self.subn_vx_vy(n2, n3),
-            0x8 if n4 == 0xE => self.shl_vx_vy(n2, n3),
-            0x9 if n4 == 0x0 => self.sne_vx_vy(n2, n3), // TODO(aalhendi): Check if last n check needed
-            0xA => self.ld_i_addr(nnn),
-            0xB => self.jp_v0_addr(nnn),
-            0xC => self.rnd_vx_kk(n2, kk),
-            0xD => self.drw_vx_vy_n(n2, n3, n4),
-            0xE if kk == 0x9E => self.skp_vx(n2),
-            0xE if kk == 0xA1 => self.sknp_vx(n2),
-            0xF if kk == 0x07 => self.ld_vx_dt(n2),
-            0xF if kk == 0x0A => self.ld_vx_k(n2),
-            0xF if kk == 0x15 => self.ld_dt_vx(n2),
-            0xF if kk == 0x18 => self.ld_st_vx(n2),
-            0xF if kk == 0x1E => self.add_i_vx(n2),
-            0xF if kk == 0x29 => self.ld_f_vx(n2),
-            0xF if kk == 0x33 => self.ld_b_vx(n2),
-            0xF if kk == 0x55 => self.ld_i_vx(n2),
-            0xF if kk == 0x65 => self.ld_vx_i(n2),
-
-            // TODO(aalhendi): Add Super Chip-8 instructions
-            _ => unimplemented!(),
+    fn ld_vx_i(&mut self, x: u8) -> Result<(), Chip8Error> {
+        let range = self.ram_range(self.i as usize, x as usize + 1)?;
+        self.registers[..=x as usize].copy_from_slice(&self.ram[range]);
+        self.increment_i_after_load_store(x);
+        Ok(())
+    }
+
+    /// COSMAC VIP leaves I past the last register transferred, see Quirks::load_store_increments_i.
+    fn increment_i_after_load_store(&mut self, x: u8) {
+        if self.quirks.load_store_increments_i {
+            self.i = self.i.wrapping_add(x as u16 + 1);
+        }
+    }
+
+    /// Store V0 through Vx in RPL user flags (x <= 7). (SCHIP)
+    /// Flags are also written to disk so they persist across sessions.
+    fn ld_r_vx(&mut self, x: u8) -> Result<(), Chip8Error> {
+        let x = x as usize;
+        if x >= RPL_FLAG_COUNT {
+            return Err(Chip8Error::RplFlagOutOfRange(x as u8));
+        }
+        self.rpl_flags[..=x].copy_from_slice(&self.registers[..=x]);
+
+        if let Some(path) = &self.rpl_path {
+            if let Err(e) = rpl::save(path, &self.rpl_flags) {
+                eprintln!("Unable to save RPL flags to {}: {e}", path.display());
+            }
+        }
+        Ok(())
+    }
+
+    /// Read V0 through Vx from RPL user flags (x <= 7). (SCHIP)
+    fn ld_vx_r(&mut self, x: u8) -> Result<(), Chip8Error> {
+        let x = x as usize;
+        if x >= RPL_FLAG_COUNT {
+            return Err(Chip8Error::RplFlagOutOfRange(x as u8));
+        }
+        self.registers[..=x].copy_from_slice(&self.rpl_flags[..=x]);
+        Ok(())
+    }
+
+    /// RAM indices of len bytes starting at start, or the first address past the end of RAM.
+    fn ram_range(&self, start: usize, len: usize) -> Result<Range<usize>, Chip8Error> {
+        if start + len > self.ram.len() {
+            let addr = start.max(self.ram.len()).min(u16::MAX as usize);
+            return Err(Chip8Error::MemoryOutOfBounds(addr as u16));
+        }
+        Ok(start..start + len)
+    }
+
+    /// Read the 16-bit word at PC and advance PC past it.
+    fn fetch(&mut self) -> Result<u16, Chip8Error> {
+        let pc = self.pc as usize;
+        if pc + 1 >= self.ram.len() {
+            return Err(Chip8Error::PcOutOfBounds(self.pc));
+        }
+        let hi = self.ram[pc] as u16;
+        let lo = self.ram[pc + 1] as u16;
+        self.pc = self.pc.wrapping_add(2);
+        Ok((hi << 8) | lo)
+    }
+
+    /// Fetch and execute the next instruction.
+    /// Does nothing while a draw is waiting for vblank, see Quirks::display_wait.
+    pub fn step(&mut self) -> Result<(), Chip8Error> {
+        if self.waiting_for_vblank {
+            return Ok(());
+        }
+        let instruction = self.fetch()?;
+        self.cycle_count += 1;
+        // fetch already moved past it
+        self.record_history(self.pc.wrapping_sub(2), instruction);
+        let opcode = if instruction == 0xF000 {
+            // XO-CHIP 4-byte instruction, the address is the following word
+            Opcode::LdILong {
+                nnnn: self.fetch()?,
+            }
+        } else {
+            match Opcode::decode(instruction) {
+                Some(opcode) => opcode,
+                None => {
+                    let pc = self.pc.wrapping_sub(2);
+                    if !self.skip_unknown_opcodes {
+                        return Err(Chip8Error::UnknownOpcode { instruction, pc });
+                    }
+                    // fetch already moved PC past it
+                    if !self.skipped_opcodes.iter().any(|entry| entry.pc == pc) {
+                        self.skipped_opcodes.push(TraceEntry {
+                            pc,
+                            opcode: instruction,
+                        });
+                    }
+                    return Ok(());
+                }
+            }
+        };
+        self.execute_opcode(opcode)
+    }
+
+    fn record_history(&mut self, pc: u16, opcode: u16) {
+        self.history[self.history_head] = TraceEntry { pc, opcode };
+        self.history_head = (self.history_head + 1) % HISTORY_LEN;
+        self.history_len = (self.history_len + 1).min(HISTORY_LEN);
+    }
+
+    /// The last executed instructions (up to HISTORY_LEN), oldest first.
+    pub fn history_iter(&self) -> impl Iterator<Item = &TraceEntry> {
+        let start = (self.history_head + HISTORY_LEN - self.history_len) % HISTORY_LEN;
+        (0..self.history_len).map(move |idx| &self.history[(start + idx) % HISTORY_LEN])
+    }
+
+    /// Unknown opcodes skipped since the last call, see VmBuilder::skip_unknown_opcodes.
+    /// An address skipped several times in between is listed once.
+    pub fn take_skipped_opcodes(&mut self) -> Vec<TraceEntry> {
+        std::mem::take(&mut self.skipped_opcodes)
+    }
+
+    /// Run instructions until the displayed pixels change or max_cycles have executed.
+    /// Returns the number of instructions executed, e.g. to advance the debugger a frame at a time.
+    /// Stops early if a draw is waiting for vblank, since nothing runs until the next timer tick.
+    pub fn step_until_display_changes(&mut self, max_cycles: u64) -> Result<u64, Chip8Error> {
+        let start = self.display.pixel_hash();
+        let first_cycle = self.cycle_count;
+        while self.cycle_count - first_cycle < max_cycles && !self.waiting_for_vblank {
+            self.step()?;
+            if self.display.pixel_hash() != start {
+                break;
+            }
+        }
+        Ok(self.cycle_count - first_cycle)
+    }
+
+    /// Execute a single opcode without fetching it from RAM.
+    /// PC is left alone, except by jumps, calls and skips, so this can be used to script the VM.
+    pub fn execute_opcode(&mut self, opcode: Opcode) -> Result<(), Chip8Error> {
+        match opcode {
+            Opcode::Cls => self.cls(),
+            Opcode::Ret => self.ret()?,
+            Opcode::Scd { n } => self.scd(n),
+            Opcode::Scr => self.scr(),
+            Opcode::Scl => self.scl(),
+            Opcode::Low => self.low(),
+            Opcode::High => self.high(),
+            Opcode::SelPlane { n } => self.sel_plane(n),
+            Opcode::Sys { nnn } => self.sys(nnn),
+            Opcode::Jp { nnn } => self.jp(nnn),
+            Opcode::Call { nnn } => self.call(nnn)?,
+            Opcode::SeVxKk { x, kk } => self.se_vx_kk(x, kk),
+            Opcode::SneVxKk { x, kk } => self.sne_vx_kk(x, kk),
+            Opcode::SeVxVy { x, y } => self.se_vx_vy(x, y),
+            Opcode::LdIRange { x, y } => self.ld_i_range(x, y)?,
+            Opcode::LdRangeI { x, y } => self.ld_range_i(x, y)?,
+            Opcode::LdVxKk { x, kk } => self.ld_vx_kk(x, kk),
+            Opcode::AddVxKk { x, kk } => self.add_vx_kk(x, kk),
+            Opcode::LdVxVy { x, y } => self.ld_vx_vy(x, y),
+            Opcode::OrVxVy { x, y } => self.or_vx_vy(x, y),
+            Opcode::AndVxVy { x, y } => self.and_vx_vy(x, y),
+            Opcode::XorVxVy { x, y } => self.xor_vx_vy(x, y),
+            Opcode::AddVxVy { x, y } => self.add_vx_vy(x, y),
+            Opcode::SubVxVy { x, y } => self.sub_vx_vy(x, y),
+            Opcode::ShrVxVy { x, y } => self.shr_vx_vy(x, y),
+            Opcode::SubnVxVy { x, y } => self.subn_vx_vy(x, y),
+            Opcode::ShlVxVy { x, y } => self.shl_vx_vy(x, y),
+            Opcode::SneVxVy { x, y } => self.sne_vx_vy(x, y),
+            Opcode::LdIAddr { nnn } => self.ld_i_addr(nnn),
+            Opcode::LdILong { nnnn } => self.ld_i_long(nnnn),
+            Opcode::JpV0Addr { nnn } => self.jp_v0_addr(nnn),
+            Opcode::RndVxKk { x, kk } => self.rnd_vx_kk(x, kk),
+            Opcode::DrwVxVyN { x, y, n } => self.drw_vx_vy_n(x, y, n)?,
+            Opcode::SkpVx { x } => self.skp_vx(x),
+            Opcode::SknpVx { x } => self.sknp_vx(x),
+            Opcode::LdVxDt { x } => self.ld_vx_dt(x),
+            Opcode::LdVxK { x } => self.ld_vx_k(x),
+            Opcode::LdDtVx { x } => self.ld_dt_vx(x),
+            Opcode::LdStVx { x } => self.ld_st_vx(x),
+            Opcode::AddIVx { x } => self.add_i_vx(x),
+            Opcode::LdFVx { x } => self.ld_f_vx(x),
+            Opcode::LdLfVx { x } => self.ld_lf_vx(x),
+            Opcode::LdBVx { x } => self.ld_b_vx(x)?,
+            Opcode::LdAudioI => self.store_audio_buffer()?,
+            Opcode::LdPitchVx { x } => self.ld_pitch_vx(x),
+            Opcode::LdIVx { x } => self.ld_i_vx(x)?,
+            Opcode::LdVxI { x } => self.ld_vx_i(x)?,
+            Opcode::LdRVx { x } => self.ld_r_vx(x)?,
+            Opcode::LdVxR { x } => self.ld_vx_r(x)?,
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for VM {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.dump_state())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn vm() -> VM {
+        VM::new_headless()
+    }
+
+    #[test]
+    fn cls_clears_display() {
+        let mut vm = vm();
+        vm.display.xor_pixel(0, 3, 4, true);
+        vm.cls();
+        assert_eq!(vm.display.lit_pixel_count(), 0);
+    }
+
+    #[test]
+    fn ret_with_empty_stack_underflows() {
+        let mut vm = vm();
+        assert!(matches!(vm.ret(), Err(Chip8Error::StackUnderflow)));
+    }
+
+    #[test]
+    fn self_jump_ends_the_frame_until_the_next_tick() {
+        let mut vm = vm();
+        // ADD V0, 1 then JP 0x202 forever
+        vm.load_rom(&[0x70, 0x01, 0x12, 0x02]).unwrap();
+        vm.run_frame(10).unwrap();
+        assert_eq!(vm.registers[0], 1);
+        assert_eq!(vm.history_iter().count(), 2);
+        // the tick at the end of the frame lets the next frame run the jump again
+        assert!(!vm.is_idle());
+        vm.run_frame(10).unwrap();
+        assert_eq!(vm.history_iter().count(), 3);
+
+        vm.jp(0x300);
+        assert!(!vm.is_idle());
+    }
+
+    #[test]
+    fn history_keeps_the_last_entries_in_order() {
+        let mut vm = vm();
+        for n in 0..300u16 {
+            vm.record_history(0x200 + n * 2, n);
+        }
+        let history: Vec<u16> = vm.history_iter().map(|entry| entry.opcode).collect();
+        assert_eq!(history, (44..300).collect::<Vec<u16>>());
+        assert_eq!(vm.history_iter().next().unwrap().pc, 0x200 + 44 * 2);
+    }
+
+    #[test]
+    fn step_until_display_changes_counts_executed_instructions() {
+        let mut vm = vm();
+        vm.quirks.display_wait = true;
+        // V0 = 0, I = font digit 0, draw it, then loop forever
+        vm.load_rom(&[0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06])
+            .unwrap();
+        assert_eq!(vm.step_until_display_changes(100).unwrap(), 3);
+        assert!(vm.waiting_for_vblank());
+        // stalled on vblank, nothing runs
+        assert_eq!(vm.step_until_display_changes(100).unwrap(), 0);
+        assert_eq!(vm.cycle_count(), 3);
+        vm.tick_timers();
+        assert_eq!(vm.step_until_display_changes(5).unwrap(), 5);
+        assert_eq!(vm.cycle_count(), 8);
+    }
+
+    #[test]
+    fn frozen_timers_do_not_decrement() {
+        let mut vm = vm();
+        vm.dt = 5;
+        vm.st = 5;
+        vm.freeze_timer(true);
+        vm.tick_timers();
+        assert_eq!((vm.dt, vm.st), (5, 5));
+        vm.freeze_timer(false);
+        vm.tick_timers();
+        assert_eq!((vm.dt, vm.st), (4, 4));
+    }
+
+    #[test]
+    fn debugger_edits_reach_the_registers_and_ram() {
+        let mut vm = vm();
+        vm.set_register(0xA, 0x42);
+        vm.set_i(0x300);
+        vm.write_ram(0x300, 0x99).unwrap();
+        assert_eq!(vm.registers()[0xA], 0x42);
+        assert_eq!((vm.i(), vm.ram()[0x300]), (0x300, 0x99));
+        assert_eq!(
+            vm.write_ram(0x1000, 0),
+            Err(Chip8Error::MemoryOutOfBounds(0x1000))
+        );
+
+        vm.call(0x300).unwrap();
+        vm.call(0x400).unwrap();
+        assert_eq!(vm.stack(), [0x200, 0x300]);
+    }
+
+    #[test]
+    fn call_and_ret_round_trip() {
+        let mut vm = vm();
+        vm.pc = 0x204;
+        vm.call(0x300).unwrap();
+        assert_eq!(vm.pc, 0x300);
+        assert_eq!(vm.sp, 1);
+        vm.ret().unwrap();
+        assert_eq!(vm.pc, 0x204);
+        assert_eq!(vm.sp, 0);
+    }
+
+    #[test]
+    fn call_past_stack_depth_overflows() {
+        let mut vm = vm();
+        for _ in 0..16 {
+            vm.call(0x300).unwrap();
+        }
+        assert!(matches!(vm.call(0x300), Err(Chip8Error::StackOverflow)));
+    }
+
+    #[test]
+    fn sixteen_nested_calls_return_in_order() {
+        let mut vm = vm();
+        for level in 0..16 {
+            vm.pc = 0x200 + level * 2;
+            vm.call(0x400 + level * 2).unwrap();
+        }
+        assert_eq!(vm.sp, 16);
+        for level in (0..16).rev() {
+            vm.ret().unwrap();
+            assert_eq!(vm.pc, 0x200 + level * 2);
+        }
+        assert!(matches!(vm.ret(), Err(Chip8Error::StackUnderflow)));
+
+        // call, call, ret, call, ret, ret
+        vm.pc = 0x202;
+        vm.call(0x300).unwrap();
+        vm.call(0x310).unwrap();
+        vm.ret().unwrap();
+        assert_eq!(vm.pc, 0x300);
+        vm.pc = 0x306;
+        vm.call(0x320).unwrap();
+        vm.ret().unwrap();
+        assert_eq!(vm.pc, 0x306);
+        vm.ret().unwrap();
+        assert_eq!((vm.pc, vm.sp), (0x202, 0));
+    }
+
+    #[test]
+    fn jp_sets_pc() {
+        let mut vm = vm();
+        vm.jp(0x345);
+        assert_eq!(vm.pc, 0x345);
+    }
+
+    #[test]
+    fn jp_v0_adds_offset() {
+        let mut vm = vm();
+        vm.registers[0] = 0x10;
+        vm.registers[3] = 0x20;
+        vm.jp_v0_addr(0x300);
+        assert_eq!(vm.pc, 0x310);
+        vm.quirks.jump_with_vx = true;
+        vm.jp_v0_addr(0x300);
+        assert_eq!(vm.pc, 0x320);
+    }
+
+    #[test]
+    fn skips_compare_registers_and_bytes() {
+        let mut vm = vm();
+        vm.registers[1] = 0x42;
+        vm.registers[2] = 0x42;
+        vm.se_vx_kk(1, 0x42);
+        assert_eq!(vm.pc, 0x202);
+        vm.sne_vx_kk(1, 0x42);
+        assert_eq!(vm.pc, 0x202);
+        vm.se_vx_vy(1, 2);
+        assert_eq!(vm.pc, 0x204);
+        vm.sne_vx_vy(1, 2);
+        assert_eq!(vm.pc, 0x204);
+        vm.sne_vx_kk(1, 0);
+        assert_eq!(vm.pc, 0x206);
+    }
+
+    #[test]
+    fn skip_steps_over_long_load() {
+        let mut vm = vm();
+        vm.load_rom(&[0xF0, 0x00, 0x12, 0x34]).unwrap();
+        vm.skip();
+        assert_eq!(vm.pc, 0x204);
+    }
+
+    #[test]
+    fn ld_and_add_vx_kk() {
+        let mut vm = vm();
+        vm.ld_vx_kk(5, 0xFE);
+        vm.add_vx_kk(5, 3);
+        assert_eq!(vm.registers[5], 0x01);
+        // 7XKK never touches the flag
+        assert_eq!(vm.registers[0xF], 0);
+    }
+
+    #[test]
+    fn ld_vx_vy_copies() {
+        let mut vm = vm();
+        vm.registers[2] = 9;
+        vm.ld_vx_vy(1, 2);
+        assert_eq!(vm.registers[1], 9);
+    }
+
+    #[test]
+    fn bitwise_ops_reset_vf_with_quirk() {
+        let mut vm = vm();
+        vm.quirks.vf_reset_on_bitwise = true;
+        vm.registers[0] = 0b1100;
+        vm.registers[1] = 0b1010;
+        vm.registers[0xF] = 1;
+        vm.or_vx_vy(0, 1);
+        assert_eq!(vm.registers[0], 0b1110);
+        assert_eq!(vm.registers[0xF], 0);
+
+        vm.registers[0xF] = 1;
+        vm.and_vx_vy(0, 1);
+        assert_eq!(vm.registers[0], 0b1010);
+        assert_eq!(vm.registers[0xF], 0);
+
+        vm.registers[0xF] = 1;
+        vm.xor_vx_vy(0, 1);
+        assert_eq!(vm.registers[0], 0);
+        assert_eq!(vm.registers[0xF], 0);
+    }
+
+    #[test]
+    fn bitwise_ops_keep_vf_without_quirk() {
+        let mut vm = vm();
+        vm.quirks.vf_reset_on_bitwise = false;
+        vm.registers[0xF] = 1;
+        vm.or_vx_vy(0, 1);
+        assert_eq!(vm.registers[0xF], 1);
+    }
+
+    #[test]
+    fn add_vx_vy_sets_carry() {
+        let mut vm = vm();
+        vm.registers[0] = 200;
+        vm.registers[1] = 100;
+        vm.add_vx_vy(0, 1);
+        assert_eq!(vm.registers[0], 44);
+        assert_eq!(vm.registers[0xF], 1);
+        vm.add_vx_vy(0, 1);
+        assert_eq!(vm.registers[0], 144);
+        assert_eq!(vm.registers[0xF], 0);
+    }
+
+    #[test]
+    fn sub_and_subn_set_not_borrow() {
+        let mut vm = vm();
+        vm.registers[0] = 5;
+        vm.registers[1] = 3;
+        vm.sub_vx_vy(0, 1);
+        assert_eq!(vm.registers[0], 2);
+        assert_eq!(vm.registers[0xF], 1);
+        vm.sub_vx_vy(0, 1);
+        assert_eq!(vm.registers[0], 255);
+        assert_eq!(vm.registers[0xF], 0);
+
+        vm.registers[0] = 3;
+        vm.registers[1] = 5;
+        vm.subn_vx_vy(0, 1);
+        assert_eq!(vm.registers[0], 2);
+        assert_eq!(vm.registers[0xF], 1);
+    }
+
+    #[test]
+    fn shifts_follow_shift_quirk() {
+        let mut vm = vm();
+        vm.quirks.shift_uses_vy = true;
+        vm.registers[0] = 0;
+        vm.registers[1] = 0b1000_0001;
+        vm.shr_vx_vy(0, 1);
+        assert_eq!(vm.registers[0], 0b0100_0000);
+        assert_eq!(vm.registers[0xF], 1);
+        vm.shl_vx_vy(0, 1);
+        assert_eq!(vm.registers[0], 0b0000_0010);
+        assert_eq!(vm.registers[0xF], 1);
+
+        vm.quirks.shift_uses_vy = false;
+        vm.registers[0] = 0b0000_0010;
+        vm.shr_vx_vy(0, 1);
+        assert_eq!(vm.registers[0], 0b0000_0001);
+        assert_eq!(vm.registers[0xF], 0);
+    }
+
+    #[test]
+    fn arithmetic_flag_wins_when_x_is_vf() {
+        let mut vm = vm();
+        vm.registers[0xF] = 0xFF;
+        vm.registers[1] = 1;
+        vm.add_vx_vy(0xF, 1);
+        assert_eq!(vm.registers[0xF], 1);
+
+        vm.quirks.shift_uses_vy = false;
+        vm.registers[0xF] = 0b10;
+        vm.shr_vx_vy(0xF, 0);
+        assert_eq!(vm.registers[0xF], 0);
+    }
+
+    #[test]
+    fn ld_i_and_add_i() {
+        let mut vm = vm();
+        vm.ld_i_addr(0x300);
+        vm.registers[2] = 0x10;
+        vm.add_i_vx(2);
+        assert_eq!(vm.i, 0x310);
+        vm.ld_i_long(0xABCD);
+        assert_eq!(vm.i, 0xABCD);
+    }
+
+    #[test]
+    fn rnd_is_masked_and_seedable() {
+        let mut a = VM::new_with_seed(7);
+        let mut b = VM::new_with_seed(7);
+        for _ in 0..32 {
+            a.rnd_vx_kk(0, 0x0F);
+            b.rnd_vx_kk(0, 0x0F);
+            assert_eq!(a.registers[0], b.registers[0]);
+            assert_eq!(a.registers[0] & 0xF0, 0);
+        }
+    }
+
+    // draws font digits at random positions, forever
+    const RANDOM_DIGITS: [u8; 12] = [
+        0xC0, 0xFF, // RND V0, 0xFF
+        0xC1, 0x1F, // RND V1, 0x1F
+        0xC2, 0x0F, // RND V2, 0x0F
+        0xF2, 0x29, // LD F, V2
+        0xD0, 0x15, // DRW V0, V1, 5
+        0x12, 0x00, // JP 0x200
+    ];
+
+    fn run_cycles(vm: &mut VM, cycles: u32) {
+        for _ in 0..cycles {
+            vm.step().unwrap();
+        }
+    }
+
+    fn random_digits_vm() -> VM {
+        let mut vm = VM::new_with_seed(42);
+        vm.quirks.display_wait = false;
+        vm.load_rom(&RANDOM_DIGITS).unwrap();
+        vm
+    }
+
+    #[test]
+    fn same_seed_runs_identically() {
+        let run = || {
+            let mut vm = random_digits_vm();
+            run_cycles(&mut vm, 1000);
+            (vm.registers, vm.get_display_pixels())
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn drw_draws_sprite_and_detects_collision() {
+        let mut vm = vm();
+        vm.quirks.display_wait = false;
+        vm.i = 0x300;
+        vm.ram[0x300] = 0b1010_0000;
+        vm.ram[0x301] = 0b0101_0000;
+        vm.registers[0] = 2;
+        vm.registers[1] = 3;
+        vm.drw_vx_vy_n(0, 1, 2).unwrap();
+
+        assert!(vm.display.get_pixel_state(0, 2, 3));
+        assert!(!vm.display.get_pixel_state(0, 3, 3));
+        assert!(vm.display.get_pixel_state(0, 4, 3));
+        assert!(vm.display.get_pixel_state(0, 3, 4));
+        assert!(vm.display.get_pixel_state(0, 5, 4));
+        assert_eq!(vm.display.lit_pixel_count(), 4);
+        assert_eq!(vm.registers[0xF], 0);
+
+        // drawing the same sprite again erases it
+        vm.drw_vx_vy_n(0, 1, 2).unwrap();
+        assert_eq!(vm.display.lit_pixel_count(), 0);
+        assert_eq!(vm.registers[0xF], 1);
+    }
+
+    #[test]
+    fn drw_16x16_in_hires_draws_collides_and_erases() {
+        let mut vm = vm();
+        vm.quirks.display_wait = false;
+        vm.high();
+        // each row lights the diagonal and the rightmost column
+        vm.i = 0x300;
+        for row in 0..16 {
+            let bits: u16 = (0x8000 >> row) | 0x0001;
+            vm.ram[0x300 + row * 2..0x302 + row * 2].copy_from_slice(&bits.to_be_bytes());
+        }
+        vm.registers[0] = 100;
+        vm.registers[1] = 40;
+        vm.drw_vx_vy_n(0, 1, 0).unwrap();
+
+        for row in 0..16 {
+            assert!(vm.display.get_pixel_state(0, 100 + row, 40 + row));
+            assert!(vm.display.get_pixel_state(0, 115, 40 + row));
+        }
+        assert!(!vm.display.get_pixel_state(0, 101, 40));
+        assert!(!vm.display.get_pixel_state(0, 100, 56));
+        // 15 rows of two pixels, the last row's two are the same pixel
+        assert_eq!(vm.display.lit_pixel_count(), 31);
+        assert_eq!(vm.registers[0xF], 0);
+
+        // a single pixel on the diagonal collides
+        vm.cls();
+        vm.display.xor_pixel(0, 105, 45, true);
+        vm.drw_vx_vy_n(0, 1, 0).unwrap();
+        assert!(!vm.display.get_pixel_state(0, 105, 45));
+        assert_eq!(vm.registers[0xF], 1);
+
+        // the same sprite twice erases it
+        vm.cls();
+        vm.drw_vx_vy_n(0, 1, 0).unwrap();
+        vm.drw_vx_vy_n(0, 1, 0).unwrap();
+        assert_eq!(vm.display.lit_pixel_count(), 0);
+        assert_eq!(vm.registers[0xF], 1);
+    }
+
+    #[test]
+    fn drw_with_n_0_in_lores_draws_nothing() {
+        let mut vm = vm();
+        vm.quirks.display_wait = false;
+        vm.i = 0x300;
+        vm.ram[0x300..0x320].fill(0xFF);
+        vm.drw_vx_vy_n(0, 1, 0).unwrap();
+        assert_eq!(vm.display.lit_pixel_count(), 0);
+        assert_eq!(vm.registers[0xF], 0);
+    }
+
+    #[test]
+    fn drw_draws_on_the_selected_planes_only() {
+        let mut vm = vm();
+        vm.quirks.display_wait = false;
+        vm.i = 0x300;
+        vm.ram[0x300] = 0b1000_0000;
+        vm.ram[0x301] = 0b0100_0000;
+
+        vm.sel_plane(2);
+        vm.drw_vx_vy_n(0, 1, 1).unwrap();
+        assert!(!vm.display.get_pixel_state(0, 0, 0));
+        assert!(vm.display.get_pixel_state(1, 0, 0));
+
+        // with both selected, plane 2's sprite follows plane 1's in RAM
+        vm.cls();
+        vm.sel_plane(3);
+        vm.drw_vx_vy_n(0, 1, 1).unwrap();
+        assert!(vm.display.get_pixel_state(0, 0, 0));
+        assert!(!vm.display.get_pixel_state(1, 0, 0));
+        assert!(vm.display.get_pixel_state(1, 1, 0));
+        assert_eq!(vm.display.pixel_color(0, 0), 1);
+        assert_eq!(vm.display.pixel_color(1, 0), 2);
+    }
+
+    #[test]
+    fn drw_wraps_or_clips_at_edges() {
+        let mut vm = vm();
+        vm.quirks.display_wait = false;
+        vm.quirks.clip_sprites = false;
+        vm.i = 0x300;
+        vm.ram[0x300] = 0b1100_0000;
+        vm.registers[0] = (SCREEN_WIDTH - 1) as u8;
+        vm.drw_vx_vy_n(0, 1, 1).unwrap();
+        assert!(vm.display.get_pixel_state(0, SCREEN_WIDTH - 1, 0));
+        assert!(vm.display.get_pixel_state(0, 0, 0));
+
+        vm.cls();
+        vm.quirks.clip_sprites = true;
+        vm.drw_vx_vy_n(0, 1, 1).unwrap();
+        assert!(vm.display.get_pixel_state(0, SCREEN_WIDTH - 1, 0));
+        assert!(!vm.display.get_pixel_state(0, 0, 0));
+    }
+
+    #[test]
+    fn drw_at_column_60_wraps_or_clips_the_last_4_columns() {
+        let mut vm = vm();
+        vm.quirks.display_wait = false;
+        vm.i = 0x300;
+        vm.ram[0x300..0x302].copy_from_slice(&[0xFF, 0xFF]);
+        vm.registers[0] = 60;
+        let lit = |vm: &VM| -> Vec<usize> {
+            (0..SCREEN_WIDTH)
+                .filter(|&x| {
+                    vm.display.get_pixel_state(0, x, 0) && vm.display.get_pixel_state(0, x, 1)
+                })
+                .collect()
+        };
+
+        vm.quirks.clip_sprites = true;
+        vm.drw_vx_vy_n(0, 1, 2).unwrap();
+        assert_eq!(lit(&vm), [60, 61, 62, 63]);
+
+        vm.cls();
+        vm.quirks.clip_sprites = false;
+        vm.drw_vx_vy_n(0, 1, 2).unwrap();
+        assert_eq!(lit(&vm), [0, 1, 2, 3, 60, 61, 62, 63]);
+    }
+
+    #[test]
+    fn drw_waits_for_vblank_with_quirk() {
+        let mut vm = vm();
+        vm.quirks.display_wait = true;
+        vm.drw_vx_vy_n(0, 0, 1).unwrap();
+        assert!(vm.waiting_for_vblank());
+        vm.tick_timers();
+        assert!(!vm.waiting_for_vblank());
+    }
+
+    #[test]
+    fn skp_and_sknp_read_keys() {
+        let mut vm = vm();
+        vm.registers[0] = 0xA;
+        vm.skp_vx(0);
+        assert_eq!(vm.pc, 0x200);
+        vm.sknp_vx(0);
+        assert_eq!(vm.pc, 0x202);
+        vm.set_key(0xA, true);
+        vm.skp_vx(0);
+        assert_eq!(vm.pc, 0x204);
+        vm.sknp_vx(0);
+        assert_eq!(vm.pc, 0x204);
+    }
+
+    #[test]
+    fn ld_vx_k_blocks_until_key_pressed() {
+        let mut vm = vm();
+        vm.quirks.wait_for_key_release = false;
+        // as if FX0A was just fetched
+        vm.pc = 0x202;
+        vm.ld_vx_k(3);
+        assert_eq!(vm.pc, 0x200);
+
+        vm.pc = 0x202;
+        vm.set_key(0x7, true);
+        vm.ld_vx_k(3);
+        assert_eq!(vm.pc, 0x202);
+        assert_eq!(vm.registers[3], 0x7);
+    }
+
+    #[test]
+    fn ld_vx_k_completes_on_release_with_quirk() {
+        let mut vm = vm();
+        vm.quirks.wait_for_key_release = true;
+        vm.pc = 0x202;
+        vm.set_key(0x7, true);
+        vm.ld_vx_k(3);
+        assert_eq!(vm.pc, 0x200);
+
+        vm.pc = 0x202;
+        vm.set_key(0x7, false);
+        vm.ld_vx_k(3);
+        assert_eq!(vm.pc, 0x202);
+        assert_eq!(vm.registers[3], 0x7);
+    }
+
+    #[test]
+    fn timers_load_and_read() {
+        let mut vm = vm();
+        vm.registers[0] = 2;
+        vm.ld_dt_vx(0);
+        vm.ld_st_vx(0);
+        vm.tick_timers();
+        vm.ld_vx_dt(1);
+        assert_eq!(vm.registers[1], 1);
+        assert_eq!(vm.st, 1);
+    }
+
+    #[test]
+    fn ld_f_points_at_font() {
+        let mut vm = vm();
+        vm.registers[0] = 0xA;
+        vm.ld_f_vx(0);
+        let start = vm.i as usize;
+        assert_eq!(vm.ram[start..start + 5], SPRITES[50..55]);
+    }
+
+    #[test]
+    fn ld_lf_points_at_the_large_digit() {
+        let mut vm = vm();
+        let large_start = vm.font_start as usize + SPRITES.len();
+        for digit in 0..10u8 {
+            vm.registers[0] = digit;
+            vm.ld_lf_vx(0);
+            let start = large_start + digit as usize * 10;
+            assert_eq!(vm.i as usize, start);
+            assert_eq!(
+                vm.ram[start..start + 10],
+                LARGE_SPRITES[digit as usize * 10..][..10]
+            );
+        }
+        // SCHIP only has large sprites for 0-9, larger values wrap around to them
+        vm.registers[0] = 13;
+        vm.ld_lf_vx(0);
+        assert_eq!(vm.i as usize, large_start + 3 * 10);
+    }
+
+    #[test]
+    fn large_digit_draws_its_glyph() {
+        let mut vm = vm();
+        vm.quirks.display_wait = false;
+        vm.high();
+        vm.ld_lf_vx(0);
+        vm.drw_vx_vy_n(0, 0, 10).unwrap();
+        let glyph: Vec<String> = (0..10)
+            .map(|y| {
+                (0..8)
+                    .map(|x| {
+                        if vm.display.get_pixel_state(0, x, y) {
+                            '#'
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            glyph,
+            [
+                "..####..", ".######.", "###..###", "##....##", "##....##", "##....##", "##....##",
+                "###..###", ".######.", "..####..",
+            ]
+        );
+    }
+
+    #[test]
+    fn ld_b_vx_stores_bcd() {
+        let mut vm = vm();
+        vm.i = 0x300;
+        for (value, digits) in [(0, [0, 0, 0]), (99, [0, 9, 9]), (255, [2, 5, 5])] {
+            vm.registers[0] = value;
+            vm.ld_b_vx(0).unwrap();
+            assert_eq!(vm.ram[0x300..0x303], digits);
+        }
+    }
+
+    #[test]
+    fn ld_i_vx_and_ld_vx_i_round_trip() {
+        let mut vm = vm();
+        vm.quirks.load_store_increments_i = true;
+        vm.i = 0x300;
+        vm.registers[..4].copy_from_slice(&[1, 2, 3, 4]);
+        vm.ld_i_vx(3).unwrap();
+        assert_eq!(vm.ram[0x300..0x304], [1, 2, 3, 4]);
+        assert_eq!(vm.i, 0x304);
+
+        vm.quirks.load_store_increments_i = false;
+        vm.i = 0x300;
+        vm.registers = [0; 16];
+        vm.ld_vx_i(3).unwrap();
+        assert_eq!(vm.registers[..4], [1, 2, 3, 4]);
+        assert_eq!(vm.i, 0x300);
+    }
+
+    #[test]
+    fn range_load_store_accepts_either_order() {
+        let mut vm = vm();
+        vm.i = 0x300;
+        vm.registers[2..5].copy_from_slice(&[7, 8, 9]);
+        vm.ld_i_range(4, 2).unwrap();
+        assert_eq!(vm.ram[0x300..0x303], [7, 8, 9]);
+        assert_eq!(vm.i, 0x300);
+
+        vm.registers = [0; 16];
+        vm.ld_range_i(2, 4).unwrap();
+        assert_eq!(vm.registers[2..5], [7, 8, 9]);
+    }
+
+    #[test]
+    fn audio_pattern_plays_at_the_set_pitch() {
+        // every pitch the VM hands the audio sink, from set_pattern and set_pitch
+        struct PitchLog(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+        impl AudioSink for PitchLog {
+            fn set_playing(&mut self, _playing: bool) {}
+            fn set_pattern(&mut self, _pattern: &[u8; PATTERN_BYTES], pitch: u8) {
+                self.0.borrow_mut().push(pitch);
+            }
+            fn set_pitch(&mut self, pitch: u8) {
+                self.0.borrow_mut().push(pitch);
+            }
+        }
+        let log = std::rc::Rc::default();
+        let mut vm = VM::headless(VM::builder().audio(PitchLog(std::rc::Rc::clone(&log))));
+
+        // F002 before any FX3A uses the default pitch
+        vm.i = 0x300;
+        vm.execute_opcode(Opcode::decode(0xF002).unwrap()).unwrap();
+        vm.registers[3] = 112;
+        vm.execute_opcode(Opcode::decode(0xF33A).unwrap()).unwrap();
+        vm.execute_opcode(Opcode::decode(0xF002).unwrap()).unwrap();
+        assert_eq!(*log.borrow(), [DEFAULT_PITCH, 112, 112]);
+
+        vm.reset();
+        vm.execute_opcode(Opcode::decode(0xF002).unwrap()).unwrap();
+        assert_eq!(log.borrow().last(), Some(&DEFAULT_PITCH));
+    }
+
+    #[test]
+    fn xochip_has_64kb_of_ram() {
+        // I = 0x8000, V0 = 0x42, store V0 at I
+        let rom = [0xF0, 0x00, 0x80, 0x00, 0x60, 0x42, 0xF0, 0x55];
+        let mut xo = VM::headless(VM::builder().compat(CompatMode::XoChip));
+        xo.load_rom(&rom).unwrap();
+        for _ in 0..3 {
+            xo.step().unwrap();
+        }
+        assert_eq!(xo.ram[0x8000], 0x42);
+        // states carry the whole 64KB and only load into an XO-CHIP VM
+        let state = xo.save_state();
+        assert!(xo.load_state(&state).is_ok());
+        assert!(matches!(
+            vm().load_state(&state),
+            Err(Chip8Error::InvalidState(_))
+        ));
+        // programs can be larger than CHIP-8's 3.5KB too
+        assert!(xo.load_rom(&[0; 0x8000]).is_ok());
+
+        let mut vm = vm();
+        vm.load_rom(&rom).unwrap();
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(vm.step(), Err(Chip8Error::MemoryOutOfBounds(0x8000)));
+        assert!(vm.load_rom(&[0; 0x8000]).is_err());
+    }
+
+    #[test]
+    fn rom_size_is_checked() {
+        let mut vm = vm();
+        assert_eq!(vm.load_rom(&[]), Err(Chip8Error::RomTooSmall(0)));
+        assert_eq!(vm.load_rom(&[0x00]), Err(Chip8Error::RomTooSmall(1)));
+        assert_eq!(vm.load_rom(&[0; 512]), Ok(()));
+        assert_eq!(vm.load_rom(&[0; 3584]), Ok(()));
+        assert_eq!(
+            vm.load_rom(&[0; 3585]),
+            Err(Chip8Error::RomTooLarge {
+                size: 3585,
+                max: 3584
+            })
+        );
+    }
+
+    #[test]
+    fn quirk_presets() {
+        assert_eq!(Quirks::preset("vip"), Some(Quirks::default()));
+        assert_eq!(
+            Quirks::preset("SCHIP"),
+            Some(CompatMode::SuperChip.default_quirks())
+        );
+        assert_eq!(
+            Quirks::preset("xochip"),
+            Some(CompatMode::XoChip.default_quirks())
+        );
+        let modern = Quirks::preset("modern").unwrap();
+        assert!(!modern.shift_uses_vy && !modern.jump_with_vx && modern.clip_sprites);
+        assert_eq!(Quirks::preset("chip48"), None);
+    }
+
+    #[test]
+    fn unknown_opcodes_are_errors() {
+        for instruction in [0x5001u16, 0x800F, 0xE000, 0xF0FF] {
+            let mut vm = vm();
+            vm.load_rom(&instruction.to_be_bytes()).unwrap();
+            assert_eq!(
+                vm.step(),
+                Err(Chip8Error::UnknownOpcode {
+                    instruction,
+                    pc: 0x200
+                })
+            );
+            // moved past it, so the program can carry on
+            assert_eq!(vm.pc, 0x202);
+        }
+    }
+
+    #[test]
+    fn unknown_opcodes_are_skipped_when_set() {
+        // E000 doesn't exist, then V0 = 5 and spin
+        let rom = [0xE0, 0x00, 0x60, 0x05, 0x12, 0x04];
+        let mut vm = VM::builder()
+            .display(NullRenderer)
+            .rpl_path(None)
+            .skip_unknown_opcodes(true)
+            .build()
+            .unwrap();
+        vm.load_rom(&rom).unwrap();
+        assert_eq!(vm.run_frame(10), Ok(FrameEnd::Complete));
+        assert_eq!(vm.registers[0], 5);
+        assert_eq!(vm.pc, 0x204);
+        let skipped = [TraceEntry {
+            pc: 0x200,
+            opcode: 0xE000,
+        }];
+        assert_eq!(vm.take_skipped_opcodes(), skipped);
+        assert!(vm.take_skipped_opcodes().is_empty());
+
+        // the same address again is reported once per take
+        vm.reset();
+        vm.run_frame(10).unwrap();
+        vm.reset();
+        vm.run_frame(10).unwrap();
+        assert_eq!(vm.take_skipped_opcodes(), skipped);
+    }
+
+    #[test]
+    fn range_store_past_ram_is_an_error() {
+        let mut vm = vm();
+        vm.i = 0xFFF;
+        assert!(matches!(
+            vm.ld_i_range(0, 1),
+            Err(Chip8Error::MemoryOutOfBounds(0x1000))
+        ));
+    }
+
+    #[test]
+    fn i_relative_access_past_ram_is_an_error_and_changes_nothing() {
+        let mut vm = vm();
+        vm.registers[0] = 255;
+        vm.i = 0xFFE;
+        // BCD would write 0xFFE, 0xFFF and 0x1000, nothing is written
+        assert_eq!(vm.ld_b_vx(0), Err(Chip8Error::MemoryOutOfBounds(0x1000)));
+        assert_eq!(vm.ram[0xFFE..], [0, 0]);
+        assert_eq!(vm.ld_i_vx(2), Err(Chip8Error::MemoryOutOfBounds(0x1000)));
+        assert_eq!(vm.ram[0xFFE..], [0, 0]);
+        assert_eq!(vm.ld_vx_i(2), Err(Chip8Error::MemoryOutOfBounds(0x1000)));
+        assert_eq!(vm.registers[0], 255);
+
+        // second sprite byte is past the end, no row is drawn
+        vm.i = 0xFFF;
+        vm.ram[0xFFF] = 0xFF;
+        assert_eq!(
+            vm.drw_vx_vy_n(1, 1, 2),
+            Err(Chip8Error::MemoryOutOfBounds(0x1000))
+        );
+        assert_eq!(vm.display.lit_pixel_count(), 0);
+    }
+
+    #[test]
+    fn rpl_flags_round_trip() {
+        let mut vm = vm();
+        vm.registers[..3].copy_from_slice(&[4, 5, 6]);
+        vm.ld_r_vx(2).unwrap();
+        vm.registers = [0; 16];
+        vm.ld_vx_r(2).unwrap();
+        assert_eq!(vm.registers[..3], [4, 5, 6]);
+    }
+
+    #[test]
+    fn rpl_flags_reload_in_a_fresh_vm() {
+        let path = std::env::temp_dir().join(format!("crust8-rpl-{}.bin", std::process::id()));
+        let with_path = || {
+            VM::builder()
+                .display(NullRenderer)
+                .rpl_path(path.clone())
+                .build()
+                .unwrap()
+        };
+
+        let mut first = with_path();
+        first.registers[..4].copy_from_slice(&[1, 2, 3, 4]);
+        first.ld_r_vx(3).unwrap();
+        drop(first);
+
+        let mut second = with_path();
+        second.ld_vx_r(3).unwrap();
+        assert_eq!(second.registers[..4], [1, 2, 3, 4]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn dump_state_lists_registers_and_stack() {
+        let mut vm = vm();
+        for x in 0..16 {
+            vm.registers[x] = 0xA0 + x as u8;
+        }
+        let dump = vm.dump_state();
+        for x in 0..16 {
+            assert!(
+                dump.contains(&format!("V{x:X}=0x{:02X}", 0xA0 + x)),
+                "{dump}"
+            );
+        }
+        assert!(dump.contains("stack: []"), "{dump}");
+
+        vm.pc = 0x202;
+        vm.call(0x300).unwrap();
+        vm.pc = 0x302;
+        vm.call(0x400).unwrap();
+        let dump = vm.dump_state();
+        assert!(dump.contains("SP=2"), "{dump}");
+        assert!(dump.contains("stack: [0x0202, 0x0302]"), "{dump}");
+    }
+
+    #[test]
+    fn dump_memory_formats_hex_and_ascii() {
+        let mut vm = vm();
+        let bytes = b"Hello, CHIP-8!\x00\x01\x7F ~\x80\xFFABC\n\r\t0123\x1B";
+        vm.ram[0x300..0x320].copy_from_slice(bytes);
+        assert_eq!(
+            vm.dump_memory(0x300, 32),
+            "0x0300: 48 65 6C 6C 6F 2C 20 43 48 49 50 2D 38 21 00 01  Hello, CHIP-8!..\n\
+             0x0310: 7F 20 7E 80 FF 41 42 43 0A 0D 09 30 31 32 33 1B  . ~..ABC...0123."
+        );
+    }
+
+    #[test]
+    fn dump_memory_pads_short_lines_and_stops_at_the_end_of_ram() {
+        let mut vm = vm();
+        vm.ram[0x310..0x314].copy_from_slice(&[0x7F, 0x20, 0x7E, 0x80]);
+        let dump = vm.dump_memory(0x300, 20);
+        assert_eq!(
+            dump.lines().nth(1),
+            Some(format!("0x0310: 7F 20 7E 80{}  . ~.", " ".repeat(36)).as_str())
+        );
+
+        let dump = vm.dump_memory(0xFF8, 0x20);
+        assert_eq!(dump.lines().count(), 1);
+        assert!(
+            dump.starts_with("0x0FF8: 00 00 00 00 00 00 00 00 "),
+            "{dump}"
+        );
+        assert_eq!(vm.dump_memory(0x2000, 16), "");
+    }
+
+    #[test]
+    fn reset_restarts_program() {
+        let mut vm = vm();
+        let rom = [0x60, 0x12, 0x61, 0x34, 0xA3, 0x00, 0x22, 0x0A];
+        vm.load_rom(&rom).unwrap();
+        for _ in 0..4 {
+            vm.step().unwrap();
+        }
+        vm.ram[0x300] = 0xAA;
+        vm.reset();
+        assert_eq!(vm.pc, 0x200);
+        assert_eq!(vm.registers, [0; 16]);
+        assert_eq!((vm.i, vm.sp), (0, 0));
+        assert_eq!(vm.ram[0x200..0x208], rom);
+        assert_eq!(vm.ram[0x300], 0);
+        assert_eq!(vm.ram[..5], SPRITES[..5]);
+    }
+
+    #[test]
+    fn save_and_load_state_round_trip() {
+        let mut vm = vm();
+        vm.load_rom(&[0x12, 0x00]).unwrap();
+        vm.registers[3] = 0x42;
+        vm.i = 0x321;
+        vm.dt = 7;
+        vm.call(0x280).unwrap();
+        vm.set_key(0xB, true);
+        vm.display.set_hires(true);
+        vm.display.xor_pixel(0, 100, 50, true);
+        let state = vm.save_state();
+
+        let mut restored = VM::new_headless();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.registers[3], 0x42);
+        assert_eq!(restored.pc, 0x280);
+        assert!(restored.display.is_hires());
+        assert!(restored.display.get_pixel_state(0, 100, 50));
+    }
+
+    #[test]
+    fn loaded_state_continues_like_the_original() {
+        let mut continuous = random_digits_vm();
+        run_cycles(&mut continuous, 2000);
+
+        let mut first = random_digits_vm();
+        run_cycles(&mut first, 1000);
+        let mut second = VM::new_with_seed(0);
+        second.quirks.display_wait = false;
+        second.load_state(&first.save_state()).unwrap();
+        run_cycles(&mut second, 1000);
+
+        assert_eq!(second.get_display_pixels(), continuous.get_display_pixels());
+        assert_eq!(second.registers, continuous.registers);
+    }
+
+    #[test]
+    fn load_state_rejects_other_formats() {
+        let mut vm = vm();
+        let mut state = vm.save_state();
+        state[STATE_MAGIC.len()] = STATE_VERSION + 1;
+        assert_eq!(
+            vm.load_state(&state),
+            Err(Chip8Error::UnsupportedStateVersion(STATE_VERSION + 1))
+        );
+        state[0] = b'X';
+        assert_eq!(vm.load_state(&state), Err(Chip8Error::BadStateMagic));
+        let state = vm.save_state();
+        assert!(matches!(
+            vm.load_state(&state[..10]),
+            Err(Chip8Error::InvalidState(_))
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn add_vx_vy_matches_wrapping_add(a: u8, b: u8) {
+            let mut vm = vm();
+            vm.registers[0] = a;
+            vm.registers[1] = b;
+            vm.add_vx_vy(0, 1);
+            prop_assert_eq!(vm.registers[0], a.wrapping_add(b));
+            prop_assert_eq!(vm.registers[0xF], (a as u16 + b as u16 > 255) as u8);
+        }
+
+        #[test]
+        fn sub_vx_vy_matches_wrapping_sub(a: u8, b: u8) {
+            let mut vm = vm();
+            vm.registers[0] = a;
+            vm.registers[1] = b;
+            vm.sub_vx_vy(0, 1);
+            prop_assert_eq!(vm.registers[0], a.wrapping_sub(b));
+            prop_assert_eq!(vm.registers[0xF], (a >= b) as u8);
+        }
+
+        #[test]
+        fn subn_vx_vy_matches_wrapping_sub(a: u8, b: u8) {
+            let mut vm = vm();
+            vm.registers[0] = a;
+            vm.registers[1] = b;
+            vm.subn_vx_vy(0, 1);
+            prop_assert_eq!(vm.registers[0], b.wrapping_sub(a));
+            prop_assert_eq!(vm.registers[0xF], (b >= a) as u8);
+        }
+
+        #[test]
+        fn add_vx_kk_wraps_and_keeps_vf(a: u8, kk: u8, vf: u8) {
+            let mut vm = vm();
+            vm.registers[0] = a;
+            vm.registers[0xF] = vf;
+            vm.add_vx_kk(0, kk);
+            prop_assert_eq!(vm.registers[0], a.wrapping_add(kk));
+            prop_assert_eq!(vm.registers[0xF], vf);
+        }
+
+        #[test]
+        fn shifts_move_out_one_bit(a: u8) {
+            let mut vm = vm();
+            vm.quirks.shift_uses_vy = false;
+            vm.registers[0] = a;
+            vm.shr_vx_vy(0, 1);
+            prop_assert_eq!(vm.registers[0], a >> 1);
+            prop_assert_eq!(vm.registers[0xF], a & 1);
+
+            vm.registers[0] = a;
+            vm.shl_vx_vy(0, 1);
+            prop_assert_eq!(vm.registers[0], a << 1);
+            prop_assert_eq!(vm.registers[0xF], a >> 7);
+        }
+
+        // same loop as the fuzz/ target, any program must end in Ok or a Chip8Error
+        #[test]
+        fn arbitrary_programs_never_panic(rom in proptest::collection::vec(any::<u8>(), 2..512)) {
+            let mut vm = vm();
+            vm.load_rom(&rom).unwrap();
+            for cycle in 0..1000 {
+                if vm.step().is_err() {
+                    break;
+                }
+                if cycle % 10 == 9 {
+                    vm.tick_timers();
+                }
+            }
+        }
+
+        #[test]
+        fn ld_b_vx_digits_rebuild_value(a: u8) {
+            let mut vm = vm();
+            vm.i = 0x300;
+            vm.registers[0] = a;
+            vm.ld_b_vx(0).unwrap();
+            let digits = &vm.ram[0x300..0x303];
+            prop_assert_eq!(digits[0] as u16 * 100 + digits[1] as u16 * 10 + digits[2] as u16, a as u16);
         }
     }
 }
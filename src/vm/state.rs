@@ -0,0 +1,132 @@
+//! Save states: the machine state as a compact binary blob.
+//!
+//! Layout, multi-byte values little-endian:
+//! magic "CH8S", version, RAM (4KB, or 64KB for XO-CHIP), V0-VF, I, PC, SP, stack, DT, ST, keys, pending FX0A key,
+//! RNG state, then the screen (mode, selected planes and every plane at 1 bit per pixel).
+
+use super::VM;
+use crate::{display::SCREEN_STATE_LEN, error::Chip8Error, input::KEY_COUNT};
+
+pub const STATE_MAGIC: [u8; 4] = *b"CH8S";
+// bumped whenever the layout changes
+pub const STATE_VERSION: u8 = 3;
+
+const HEADER_LEN: usize = STATE_MAGIC.len() + 1;
+// everything after RAM
+const REGISTERS_LEN: usize = 16 + 2 + 2 + 1 + 16 * 2 + 1 + 1 + KEY_COUNT + 1 + 8 + SCREEN_STATE_LEN;
+
+/// Reads the blob front to back, load_state checks the length up front so reads can't run out.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> &'a [u8] {
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        head
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.take(1)[0]
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.take(2).try_into().expect("2 bytes"))
+    }
+
+    fn u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.take(8).try_into().expect("8 bytes"))
+    }
+}
+
+impl VM {
+    fn state_len(&self) -> usize {
+        HEADER_LEN + self.ram.len() + REGISTERS_LEN
+    }
+
+    /// Snapshot of the machine state, restored with load_state.
+    /// Settings such as quirks, breakpoints and the attached frontend are not included.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(self.state_len());
+        state.extend_from_slice(&STATE_MAGIC);
+        state.push(STATE_VERSION);
+        state.extend_from_slice(&self.ram);
+        state.extend_from_slice(&self.registers);
+        state.extend_from_slice(&self.i.to_le_bytes());
+        state.extend_from_slice(&self.pc.to_le_bytes());
+        state.push(self.sp as u8);
+        for addr in self.stack {
+            state.extend_from_slice(&addr.to_le_bytes());
+        }
+        state.push(self.dt);
+        state.push(self.st);
+        state.extend(self.keys.map(u8::from));
+        // 0xFF when FX0A hasn't seen a key go down
+        state.push(self.pending_key.unwrap_or(0xFF));
+        state.extend_from_slice(&self.rng_state.to_le_bytes());
+        self.display.save_state(&mut state);
+        state
+    }
+
+    /// Restore a snapshot taken with save_state. The VM is left untouched if it can't be read.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), Chip8Error> {
+        if !state.starts_with(&STATE_MAGIC) {
+            return Err(Chip8Error::BadStateMagic);
+        }
+        match state.get(STATE_MAGIC.len()) {
+            Some(&STATE_VERSION) => {}
+            Some(&version) => return Err(Chip8Error::UnsupportedStateVersion(version)),
+            None => return Err(Chip8Error::InvalidState("missing version".to_string())),
+        }
+        // a different length is also what a state saved in another variant looks like
+        if state.len() != self.state_len() {
+            return Err(Chip8Error::InvalidState(format!(
+                "{} bytes, expected {}",
+                state.len(),
+                self.state_len()
+            )));
+        }
+
+        let mut reader = Reader {
+            bytes: &state[HEADER_LEN..],
+        };
+        let ram = reader.take(self.ram.len());
+        let registers = reader.take(16);
+        let i = reader.u16();
+        let pc = reader.u16();
+        let sp = reader.u8() as usize;
+        let stack: Vec<u16> = (0..16).map(|_| reader.u16()).collect();
+        let dt = reader.u8();
+        let st = reader.u8();
+        let keys = reader.take(KEY_COUNT);
+        let pending_key = reader.u8();
+        let rng_state = reader.u64();
+        let screen = reader.take(SCREEN_STATE_LEN);
+
+        if sp > self.stack.len() {
+            return Err(Chip8Error::InvalidState(format!("stack pointer {sp}")));
+        }
+        if pc as usize >= self.ram.len() {
+            return Err(Chip8Error::PcOutOfBounds(pc));
+        }
+
+        self.ram.copy_from_slice(ram);
+        self.registers.copy_from_slice(registers);
+        self.i = i;
+        self.pc = pc;
+        self.sp = sp;
+        self.stack.copy_from_slice(&stack);
+        self.dt = dt;
+        self.st = st;
+        for (key, &pressed) in self.keys.iter_mut().zip(keys) {
+            *key = pressed != 0;
+        }
+        self.pending_key = (pending_key < KEY_COUNT as u8).then_some(pending_key);
+        self.rng_state = rng_state;
+        self.display.load_state(screen);
+        self.waiting_for_vblank = false;
+        self.idle = false;
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+//! Runs the Timendus chip8-test-suite ROMs headlessly and compares the screen against golden
+//! images in tests/fixtures, 1 bit per pixel, most significant bit first, 64x32 = 256 bytes.
+//!
+//! The ROMs (MIT licensed) go in tests/roms, from
+//! https://github.com/Timendus/chip8-test-suite/tree/main/bin, and the golden images next to
+//! them in tests/fixtures. Until both are committed the tests are ignored, run them with
+//! `cargo test --test compatibility -- --ignored`; a missing ROM or golden image fails.
+//!
+//! UPDATE_GOLDEN=1 writes the golden images from the current output and prints each screen,
+//! compare it with the suite's screenshots before committing the image.
+
+use std::{env, fs, path::PathBuf};
+
+use chip_8::{
+    vm::{SCREEN_HEIGHT, SCREEN_WIDTH},
+    VM,
+};
+
+const GOLDEN_BYTES: usize = SCREEN_WIDTH * SCREEN_HEIGHT / 8;
+
+fn test_dir(sub: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join(sub)
+}
+
+fn pack(pixels: &[bool]) -> Vec<u8> {
+    pixels
+        .chunks(8)
+        .map(|byte| byte.iter().fold(0, |acc, &lit| (acc << 1) | u8::from(lit)))
+        .collect()
+}
+
+fn unpack(golden: &[u8]) -> Vec<bool> {
+    golden
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |bit| byte >> bit & 1 == 1))
+        .collect()
+}
+
+// one line per row, # for lit pixels
+fn draw(pixels: &[bool]) -> String {
+    pixels
+        .chunks(SCREEN_WIDTH)
+        .map(|row| row.iter().map(|&lit| if lit { '#' } else { '.' }).collect())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Run rom for frames 60Hz frames and check the screen against its golden image.
+fn check_rom(rom: &str, frames: u32) {
+    let rom_path = test_dir("roms").join(rom);
+    let data = fs::read(&rom_path).unwrap_or_else(|e| {
+        panic!(
+            "{}: {e}, see the top of tests/compatibility.rs",
+            rom_path.display()
+        )
+    });
+
+    let mut vm = VM::new_headless();
+    vm.load_rom(&data).unwrap();
+    for _ in 0..frames {
+        vm.run_frame(10).unwrap();
+    }
+    let pixels = vm.get_display_pixels();
+    assert_eq!(
+        pixels.len(),
+        SCREEN_WIDTH * SCREEN_HEIGHT,
+        "{rom} left low-res mode"
+    );
+
+    let golden_path = test_dir("fixtures").join(rom).with_extension("golden");
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden_path, pack(&pixels)).unwrap();
+        println!("{}:\n{}", golden_path.display(), draw(&pixels));
+        return;
+    }
+    let golden = fs::read(&golden_path).unwrap_or_else(|e| {
+        panic!(
+            "{}: {e}, see the top of tests/compatibility.rs",
+            golden_path.display()
+        )
+    });
+    assert_eq!(
+        golden.len(),
+        GOLDEN_BYTES,
+        "{} is corrupt",
+        golden_path.display()
+    );
+
+    let mismatches: Vec<String> = unpack(&golden)
+        .iter()
+        .zip(&pixels)
+        .enumerate()
+        .filter(|(_, (expected, actual))| expected != actual)
+        .map(|(idx, (expected, _))| {
+            let (x, y) = (idx % SCREEN_WIDTH, idx / SCREEN_WIDTH);
+            let expected = if *expected { "lit" } else { "unlit" };
+            format!("  ({x}, {y}) expected {expected}")
+        })
+        .collect();
+    assert!(
+        mismatches.is_empty(),
+        "{rom}: {} pixels differ\n{}",
+        mismatches.len(),
+        mismatches.join("\n")
+    );
+}
+
+#[test]
+#[ignore = "needs the test suite ROMs and golden images"]
+fn chip8_logo() {
+    check_rom("1-chip8-logo.ch8", 60);
+}
+
+#[test]
+#[ignore = "needs the test suite ROMs and golden images"]
+fn ibm_logo() {
+    check_rom("2-ibm-logo.ch8", 60);
+}
+
+#[test]
+#[ignore = "needs the test suite ROMs and golden images"]
+fn corax_plus() {
+    check_rom("3-corax+.ch8", 120);
+}
+
+#[test]
+#[ignore = "needs the test suite ROMs and golden images"]
+fn flags() {
+    check_rom("4-flags.ch8", 120);
+}